@@ -1,5 +1,5 @@
 fn main() {
-    { extern crate ansi_interpreter as ai; ai::intercept_stdio(); }
+    let _intercept = { extern crate ansi_interpreter as ai; ai::intercept_stdio().unwrap() };
 
     print!("abcdef\x1b[3D");
 
@@ -1,7 +1,7 @@
 use std::iter::repeat;
 
 fn main() {
-    { extern crate ansi_interpreter as ai; ai::intercept_stdio(); }
+    let _intercept = { extern crate ansi_interpreter as ai; ai::intercept_stdio().unwrap() };
     print!("\x1b]2;Hello, World! ☺ ℌ𝔬𝔬𝔯𝔞𝔶 𝔘𝔫𝔦𝔠𝔬𝔡𝔢!\x07");
     println!("Secret text!");
     print!("\x1b[A\x1b[1B\x1b[2A\x1b[B");
@@ -5,12 +5,19 @@
 
 use std::cmp::min;
 use std::error::Error;
+use std::fmt;
 use std::ops::{Add, Mul};
 use std::io::{self, Write};
 use conv::{TryFrom, TryInto, UnwrapOk, ValueFrom, ValueInto};
-use num::Zero;
+use num::{Bounded, CheckedAdd, CheckedMul, Zero};
 use smallvec::{Array, SmallVec};
+use ansisys::ScreenMode;
+use charset::{Charset, CharsetSlot};
+use query::{decode_capability_names, Query};
+use sgr::{sgr_attrs, SgrAttrs, SgrParam};
+use style::StyleMask;
 use util::drop_front;
+use window::WindowOp;
 
 pub type GenError = Box<Error + Send + Sync>;
 
@@ -32,6 +39,8 @@ fn test_max_seq_size() {
 const SEQ_BUFFER_SIZE: usize = 32;
 
 const ESC: u8 = 0x1b;
+const SO: u8 = 0x0e;
+const SI: u8 = 0x0f;
 
 marker_error! {
     #[derive(Copy, Clone, Debug, Eq, PartialEq)]
@@ -44,37 +53,86 @@ marker_error! {
 pub struct AnsiIntercept<I>
 where I: AnsiInterpret {
     /// Buffer for incomplete escape sequences.
-    buffer: SmallVec<[u8; MIN_BUFFER_SIZE]>,
+    buffer: PartialSeq,
 
     /// Interpreter instance.
     interp: I,
 }
 
+/**
+The state of parsing a single stream of bytes: whatever part of an escape sequence has turned up so far.
+
+This is kept apart from the interpreter so that several streams can share one.
+*/
+pub struct PartialSeq {
+    buffer: SmallVec<[u8; MIN_BUFFER_SIZE]>,
+}
+
 impl<I> AnsiIntercept<I>
 where I: AnsiInterpret {
     pub fn new(interp: I) -> Self {
         AnsiIntercept {
-            buffer: SmallVec::new(),
+            buffer: PartialSeq::new(),
             interp: interp,
         }
     }
+
+    pub fn get_ref(&self) -> &I {
+        &self.interp
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.interp
+    }
 }
 
 impl<I> Write for AnsiIntercept<I>
 where I: AnsiInterpret {
-    fn write(&mut self, mut buf: &[u8]) -> io::Result<usize> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.write(&mut self.interp, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interp.flush()
+    }
+}
+
+impl PartialSeq {
+    pub fn new() -> Self {
+        PartialSeq {
+            buffer: SmallVec::new(),
+        }
+    }
+
+    /**
+    Parse as much of `buf` as possible, passing whatever it contains on to `interp`.  This behaves like `Write::write`, returning how many bytes of `buf` were consumed.
+    */
+    pub fn write<I>(&mut self, interp: &mut I, mut buf: &[u8]) -> io::Result<usize>
+    where I: AnsiInterpret {
         /*
         Fast path: no partial escape sequence being buffered, so if we can find a run of bytes with no escape sequences, we can just dump everything up to that point.
         */
         if self.buffer.len() == 0 {
             let run_len = buf.iter().cloned().enumerate()
-                .filter(|&(_, b)| is_escape_start(b))
+                .filter(|&(_, b)| is_escape_start(b) || is_shift_control(b))
                 .map(|(i, _)| i)
                 .next()
                 .unwrap_or(buf.len());
             if run_len > 0 {
                 let run = &buf[0..run_len];
-                return self.interp.write_text(run);
+                return interp.write_text(run);
+            }
+
+            /*
+            The locking shifts are single control bytes that can turn up in the middle of text, so they never need buffering.
+            */
+            if let Some(&b) = buf.first() {
+                if is_shift_control(b) {
+                    return match parse_shift_control(b, interp) {
+                        Ok(()) => Ok(1),
+                        Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err))
+                    };
+                }
             }
         }
 
@@ -98,17 +156,17 @@ where I: AnsiInterpret {
             let bytes = self.buffer.iter().cloned()
                 .chain(buf.iter().cloned());
 
-            extract_sequence(bytes, &mut self.interp)
+            extract_sequence(bytes, interp)
         } {
             Ok(EscSeqParse::IncompleteSeq) => {
                 // If the buffer is getting suspiciously long, give up and dump up to `MAX_SEQ_SIZE` bytes.  This is so that spurious escape bytes don't cause large chunks of output to disappear.
                 if self.buffer.len() + buf.len() > MAX_SEQ_SIZE {
                     let limit = MAX_SEQ_SIZE - self.buffer.len();
-                    try!(self.interp.write_text(&self.buffer));
+                    try!(interp.write_text(&self.buffer));
                     self.buffer = SmallVec::new();
 
                     let limit = min(limit, buf.len());
-                    try!(self.interp.write_text(&buf[..limit]));
+                    try!(interp.write_text(&buf[..limit]));
                     Ok(limit)
                 } else {
                     self.buffer.extend(buf.iter().cloned());
@@ -132,10 +190,6 @@ where I: AnsiInterpret {
             }
         }
     }
-
-    fn flush(&mut self) -> io::Result<()> {
-        self.interp.flush()
-    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
@@ -194,6 +248,29 @@ impl TryFrom<Option<u8>> for EraseLine {
     }
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+/**
+Work out the shape and blinkiness requested by a DECSCUSR parameter.  Zero is the terminal's default, which is conventionally a blinking block.
+*/
+fn decscusr_style(n: u8) -> Option<(CursorShape, bool)> {
+    use self::CursorShape::*;
+    match n {
+        0 | 1 => Some((Block, true)),
+        2 => Some((Block, false)),
+        3 => Some((Underline, true)),
+        4 => Some((Underline, false)),
+        5 => Some((Bar, true)),
+        6 => Some((Bar, false)),
+        _ => None
+    }
+}
+
 pub trait AnsiInterpret {
     fn write_text(&mut self, buf: &[u8]) -> io::Result<usize>;
     fn flush(&mut self) -> io::Result<()> { Ok(()) }
@@ -205,17 +282,58 @@ pub trait AnsiInterpret {
     fn cup_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> { Ok(()) }
     fn ed_seq(&mut self, n: EraseDisplay) -> Result<(), GenError> { Ok(()) }
     fn el_seq(&mut self, n: EraseLine) -> Result<(), GenError> { Ok(()) }
-    fn sgr_seq(&mut self, ns: &[u8]) -> Result<(), GenError> { Ok(()) }
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> { Ok(()) }
+    fn xtpushsgr_seq(&mut self, mask: StyleMask) -> Result<(), GenError> { Ok(()) }
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> { Ok(()) }
     fn dsr_seq(&mut self) -> Result<(), GenError> { Ok(()) }
     fn scp_seq(&mut self) -> Result<(), GenError> { Ok(()) }
     fn rcp_seq(&mut self) -> Result<(), GenError> { Ok(()) }
 
+    fn decsc_seq(&mut self) -> Result<(), GenError> { Ok(()) }
+    fn decrc_seq(&mut self) -> Result<(), GenError> { Ok(()) }
+    fn ris_seq(&mut self) -> Result<(), GenError> { Ok(()) }
+    fn deckpam_seq(&mut self) -> Result<(), GenError> { Ok(()) }
+    fn deckpnm_seq(&mut self) -> Result<(), GenError> { Ok(()) }
+    fn decaln_seq(&mut self) -> Result<(), GenError> { Ok(()) }
+
+    fn scs_seq(&mut self, slot: CharsetSlot, set: Charset) -> Result<(), GenError> { Ok(()) }
+    fn ls_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> { Ok(()) }
+    fn ss_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> { Ok(()) }
+
+    fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool) -> Result<(), GenError> { Ok(()) }
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), GenError> { Ok(()) }
+    fn set_line_wrap(&mut self, wrap: bool) -> Result<(), GenError> { Ok(()) }
+
+    /**
+    Called for `ANSI.SYS` screen mode changes.  Wrap the interpreter in `AnsiSys` to have `CSI = 7 h`/`l` treated as line wrap changes.
+    */
+    fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool) -> Result<(), GenError> { Ok(()) }
+
+    fn xtwinops_seq(&mut self, op: WindowOp) -> Result<(), GenError> { Ok(()) }
+
+    /**
+    Called for all device attribute and status queries.
+
+    By default, this forwards cursor position queries to `dsr_seq` and ignores everything else.
+    */
+    fn query_seq(&mut self, q: Query) -> Result<(), GenError> {
+        match q {
+            Query::CursorPosition => self.dsr_seq(),
+            _ => Ok(())
+        }
+    }
+
     fn osc_txt_seq(&mut self, n: u16, txt: &str) -> Result<(), GenError> { Ok(()) }
 
     fn hvp_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
         self.cup_seq(r, c)
     }
 
+    /**
+    Called for every sequence not handled by one of the other methods, with the bytes after the `ESC`.
+
+    This includes sequences that are complete but malformed, such as ones with too many parameters or an erase argument out of range; they arrive here rather than failing the write.  Private modes that a `CSI ? Pm h` or `l` sets but that have no method of their own arrive one at a time, as if each had been set by itself.
+    */
     fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
         Ok(())
     }
@@ -261,18 +379,81 @@ where
     match parse_sequence(&seq_bytes, interp) {
         // Don't forget that we dropped the leading `ESC`.
         Ok(UsedBytes(bs)) => Ok(UsedBytes(bs + 1)),
-        other => rethrow!(other)
+        Ok(IncompleteSeq) => Ok(IncompleteSeq),
+        Err(err) => match err.downcast::<InterpError>() {
+            // Whatever the interpreter returned goes back out as it was.
+            Ok(err) => Err(err.0),
+            /*
+            A sequence with parameters we can't make sense of is still a complete sequence.  Failing here would leave it at the front of the buffer, and every write after it would fail the same way.
+            */
+            Err(ref err) if is_malformed(&**err) => {
+                try!(interp.other_seq(&seq_bytes));
+                Ok(UsedBytes(seq_bytes.len() as u16 + 1))
+            },
+            Err(err) => Err(err)
+        }
+    }
+}
+
+/// Is this error about the sequence itself?
+fn is_malformed(err: &(Error + Send + Sync + 'static)) -> bool {
+    err.is::<MalformedSeq>() || err.is::<InvalidEraseDisplayArg>() || err.is::<InvalidEraseLineArg>()
+}
+
+/**
+An error returned by one of the interpreter's methods while dispatching a sequence.
+
+These are kept apart from errors in parsing the sequence, so that a sequence the interpreter has already had a go at isn't mistaken for a malformed one and handed to it again.
+*/
+#[derive(Debug)]
+struct InterpError(GenError);
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(fmt)
     }
 }
 
+impl Error for InterpError {
+    fn description(&self) -> &str {
+        self.0.description()
+    }
+}
+
+fn from_interp<T>(result: Result<T, GenError>) -> Result<T, GenError> {
+    result.map_err(|err| Box::new(InterpError(err)) as GenError)
+}
+
+#[test]
+fn test_interp_errors_pass_through() {
+    // An interpreter that fails the same way a malformed sequence would.
+    struct Failing(Vec<Vec<u8>>);
+    impl AnsiInterpret for Failing {
+        fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> { Ok(buf.len()) }
+        fn el_seq(&mut self, _: EraseLine) -> Result<(), GenError> { throw!(MalformedSeq) }
+        fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
+            self.0.push(bytes.to_owned());
+            Ok(())
+        }
+    }
+
+    let mut intercept = AnsiIntercept::new(Failing(vec![]));
+    intercept.write_all(b"\x1b[3J").unwrap();
+    assert!(intercept.write_all(b"\x1b[K").is_err());
+    assert_eq!(intercept.get_ref().0, vec![b"[3J".to_vec()]);
+}
+
 #[derive(Copy, Clone, Eq, PartialEq)]
 enum ExtractState {
     Start,
+    EscInter,
     CsiStart,
     CsiBody,
     CsiTail,
     Osc,
     OscEsc,
+    Dcs,
+    DcsEsc,
     End
 }
 
@@ -283,15 +464,20 @@ impl ExtractState {
         *self = match (*self, b) {
             (Start, b'[') => CsiStart,
             (Start, b']') => Osc,
+            (Start, b'P') => Dcs,
+            (Start, 0x20...0x2f) => EscInter,
             (Start, _) => End,
 
+            (EscInter, 0x20...0x2f) => EscInter,
+            (EscInter, _) => End,
+
             (CsiStart, 0x3c...0x3f) => CsiStart,
-            (CsiStart, 0x30...0x39) | (CsiStart, 0x3b) => CsiBody,
+            (CsiStart, 0x30...0x3b) => CsiBody,
             (CsiStart, 0x20...0x2f) => CsiTail,
             (CsiStart, 0x40...0x7e) => End,
             (CsiStart, _) => End,
 
-            (CsiBody, 0x30...0x39) | (CsiBody, 0x3b) => CsiBody,
+            (CsiBody, 0x30...0x3b) => CsiBody,
             (CsiBody, 0x20...0x2f) => CsiTail,
             (CsiBody, 0x40...0x7e) => End,
             (CsiBody, _) => End,
@@ -307,6 +493,12 @@ impl ExtractState {
             (OscEsc, b'\\') => End,
             (OscEsc, _) => Osc,
 
+            (Dcs, 0x1b) => DcsEsc,
+            (Dcs, _) => Dcs,
+
+            (DcsEsc, b'\\') => End,
+            (DcsEsc, _) => Dcs,
+
             (End, _) => return false
         };
 
@@ -333,77 +525,153 @@ where I: AnsiInterpret {
             None => throw!(MalformedSeq)
         };
         let arg_bytes = &tail_bytes[..tail_bytes.len()-1];
-        match term {
-            b'A' => {
+
+        // Split off the private-use marker (if any) and trailing intermediate bytes.
+        let (private, arg_bytes) = match arg_bytes.first() {
+            Some(&b @ 0x3c...0x3f) => (Some(b), &arg_bytes[1..]),
+            _ => (None, arg_bytes)
+        };
+        let inter_at = arg_bytes.iter().position(|&b| 0x20 <= b && b <= 0x2f)
+            .unwrap_or(arg_bytes.len());
+        let (arg_bytes, inter) = arg_bytes.split_at(inter_at);
+
+        match (private, inter, term) {
+            (None, b"", b'A') => {
                 let r = try!(parse_1n(arg_bytes));
                 let r = r.unwrap_or(1);
-                rethrow!(interp.cuu_seq(r).map(ok_result))
+                rethrow!(from_interp(interp.cuu_seq(r)).map(ok_result))
             },
-            b'B' => {
+            (None, b"", b'B') => {
                 let r = try!(parse_1n(arg_bytes));
                 let r = r.unwrap_or(1);
-                rethrow!(interp.cud_seq(r).map(ok_result))
+                rethrow!(from_interp(interp.cud_seq(r)).map(ok_result))
             },
-            b'C' => {
+            (None, b"", b'C') => {
                 let c = try!(parse_1n(arg_bytes));
                 let c = c.unwrap_or(1);
-                rethrow!(interp.cuf_seq(c).map(ok_result))
+                rethrow!(from_interp(interp.cuf_seq(c)).map(ok_result))
             },
-            b'D' => {
+            (None, b"", b'D') => {
                 let c = try!(parse_1n(arg_bytes));
                 let c = c.unwrap_or(1);
-                rethrow!(interp.cub_seq(c).map(ok_result))
+                rethrow!(from_interp(interp.cub_seq(c)).map(ok_result))
             },
-            b'H' => {
+            (None, b"", b'H') => {
                 let (r, c) = try!(parse_2n(arg_bytes));
                 let r = r.unwrap_or(1);
                 let c = c.unwrap_or(1);
-                rethrow!(interp.cup_seq(r, c).map(ok_result))
+                rethrow!(from_interp(interp.cup_seq(r, c)).map(ok_result))
             },
-            b'J' => {
+            (None, b"", b'J') => {
                 let n = try!(parse_1n(arg_bytes));
                 let n = try!(n.try_into());
-                rethrow!(interp.ed_seq(n)
+                rethrow!(from_interp(interp.ed_seq(n))
                     .map(ok_result))
             },
-            b'K' => {
+            (None, b"", b'K') => {
                 let n = try!(parse_1n(arg_bytes));
                 let n = try!(n.try_into());
-                rethrow!(interp.el_seq(n)
+                rethrow!(from_interp(interp.el_seq(n))
                     .map(ok_result))
             },
-            b'f' => {
+            (None, b"", b'f') => {
                 let (r, c) = try!(parse_2n(arg_bytes));
                 let r = r.unwrap_or(1);
                 let c = c.unwrap_or(1);
-                rethrow!(interp.hvp_seq(r, c).map(ok_result))
+                rethrow!(from_interp(interp.hvp_seq(r, c)).map(ok_result))
             },
-            b'm' => {
-                let mut ns = try!(parse_ns::<[_; 2], _>(arg_bytes));
-                if ns.len() == 0 {
-                    ns.push(0);
+            (None, b"", b'm') => {
+                let mut ps = try!(parse_sgr_params::<[_; 8]>(arg_bytes));
+                if ps.len() == 0 {
+                    ps.push(SgrParam { value: Some(0), sub: false });
                 }
-                rethrow!(interp.sgr_seq(&ns).map(ok_result))
+                rethrow!(from_interp(interp.sgr_attrs(sgr_attrs(&ps))).map(ok_result))
             },
-            b'n' => {
-                let n = try!(parse_1n(arg_bytes));
-                let n = n.unwrap_or(0);
-                // n = 6 is the only meaningful parameter for us.
-                if n == 6 {
-                    rethrow!(interp.dsr_seq().map(ok_result))
-                } else {
-                    rethrow!(interp.other_seq(&bytes).map(ok_result))
+            (None, b"", b'n') => {
+                let n = try!(parse_1n::<u16>(arg_bytes));
+                match n.unwrap_or(0) {
+                    5 => rethrow!(from_interp(interp.query_seq(Query::Status)).map(ok_result)),
+                    6 => rethrow!(from_interp(interp.query_seq(Query::CursorPosition)).map(ok_result)),
+                    _ => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+                }
+            },
+            (None, b"", b'c') | (Some(b'>'), b"", b'c') | (Some(b'='), b"", b'c') => {
+                let n = try!(parse_1n::<u16>(arg_bytes));
+                let q = match private {
+                    None => Query::PrimaryDeviceAttributes,
+                    Some(b'>') => Query::SecondaryDeviceAttributes,
+                    _ => Query::TertiaryDeviceAttributes,
+                };
+                match n.unwrap_or(0) {
+                    0 => rethrow!(from_interp(interp.query_seq(q)).map(ok_result)),
+                    _ => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+                }
+            },
+            (None, b"$", b'p') | (Some(b'?'), b"$", b'p') => {
+                let n = try!(parse_1n::<u16>(arg_bytes));
+                let n = match n { Some(n) => n, None => throw!(MalformedSeq) };
+                let q = Query::Mode { private: private.is_some(), mode: n };
+                rethrow!(from_interp(interp.query_seq(q)).map(ok_result))
+            },
+            (Some(b'>'), b"", b'q') => {
+                let n = try!(parse_1n::<u16>(arg_bytes));
+                match n.unwrap_or(0) {
+                    0 => rethrow!(from_interp(interp.query_seq(Query::Version)).map(ok_result)),
+                    _ => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
                 }
             },
-            b's' => {
+            (None, b"#", b'{') | (None, b"#", b'p') => {
+                let ns = try!(parse_ns_opt::<[_; 8], u16>(arg_bytes));
+                let ns: SmallVec<[u16; 8]> = ns.iter().filter_map(|&n| n).collect();
+                rethrow!(from_interp(interp.xtpushsgr_seq(StyleMask::from(&ns[..]))).map(ok_result))
+            },
+            (None, b"#", b'}') | (None, b"#", b'q') => {
                 try!(parse_0n(arg_bytes));
-                rethrow!(interp.scp_seq().map(ok_result))
+                rethrow!(from_interp(interp.xtpopsgr_seq()).map(ok_result))
             },
-            b'u' => {
+            (None, b"", b's') => {
                 try!(parse_0n(arg_bytes));
-                rethrow!(interp.rcp_seq().map(ok_result))
+                rethrow!(from_interp(interp.scp_seq()).map(ok_result))
             },
-            _ => rethrow!(interp.other_seq(&bytes).map(ok_result))
+            (None, b"", b'u') => {
+                try!(parse_0n(arg_bytes));
+                rethrow!(from_interp(interp.rcp_seq()).map(ok_result))
+            },
+            (None, b"", b't') => {
+                let ns = try!(parse_ns_opt::<[_; 4], u16>(arg_bytes));
+                match ns[..].try_into() {
+                    Ok(op) => rethrow!(from_interp(interp.xtwinops_seq(op)).map(ok_result)),
+                    Err(_) => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+                }
+            },
+            (None, b" ", b'q') => {
+                let n = try!(parse_1n(arg_bytes));
+                match decscusr_style(n.unwrap_or(0)) {
+                    Some((shape, blinking)) => rethrow!(from_interp(interp.set_cursor_style(shape, blinking)).map(ok_result)),
+                    None => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+                }
+            },
+            (Some(b'?'), b"", b'h') | (Some(b'?'), b"", b'l') => {
+                // Each mode in the list is dealt with separately; ones we don't know are passed on as if they'd been set by themselves.
+                let set = term == b'h';
+                let ns = try!(parse_ns::<[u16; 4], _>(arg_bytes));
+                for &n in ns.iter() {
+                    match n {
+                        7 => try!(from_interp(interp.set_line_wrap(set))),
+                        25 => try!(from_interp(interp.set_cursor_visible(set))),
+                        n => try!(from_interp(interp.other_seq(format!("[?{}{}", n, term as char).as_bytes())))
+                    }
+                }
+                Ok(ok_result(()))
+            },
+            (Some(b'='), b"", b'h') | (Some(b'='), b"", b'l') => {
+                let n = try!(parse_1n(arg_bytes));
+                match n.try_into() {
+                    Ok(mode) => rethrow!(from_interp(interp.screen_mode_seq(mode, term == b'h')).map(ok_result)),
+                    Err(_) => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+                }
+            },
+            _ => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
         }
     } else if let Some(&b']') = bytes.first() {
         // Grab leading number.
@@ -414,7 +682,7 @@ where I: AnsiInterpret {
         // Strip ;
         match tail_bytes.first() {
             Some(&b';') => (),
-            _ => return rethrow!(interp.other_seq(&bytes).map(ok_result))
+            _ => return rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
         }
         let tail_bytes = &tail_bytes[1..];
 
@@ -429,15 +697,101 @@ where I: AnsiInterpret {
         let txt = &tail_bytes[..tail_bytes.len() - drop_end];
         let txt = ::std::str::from_utf8(txt).expect("non-ASCII in OSC txt");
 
-        rethrow!(interp.osc_txt_seq(n, txt).map(ok_result))
+        rethrow!(from_interp(interp.osc_txt_seq(n, txt)).map(ok_result))
+    } else if let Some(&b'P') = bytes.first() {
+        // Strip the leading `P` and the trailing ST.
+        if bytes.len() < 3 {
+            throw!(MalformedSeq);
+        }
+        let body = &bytes[1..bytes.len() - 2];
+
+        if body.starts_with(b"$q") {
+            let pt = match ::std::str::from_utf8(&body[2..]) {
+                Ok(pt) => String::from(pt),
+                Err(_) => throw!(MalformedSeq)
+            };
+            rethrow!(from_interp(interp.query_seq(Query::Setting(pt))).map(ok_result))
+        } else if body.starts_with(b"+q") {
+            let names = match decode_capability_names(&body[2..]) {
+                Some(names) => names,
+                None => throw!(MalformedSeq)
+            };
+            rethrow!(from_interp(interp.query_seq(Query::Capabilities(names))).map(ok_result))
+        } else {
+            rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+        }
     } else {
-        rethrow!(interp.other_seq(&bytes).map(ok_result))
+        /*
+        Everything else is an `ESC`-level sequence: zero or more intermediate bytes followed by a single final byte.
+        */
+        match bytes {
+            b"7" => rethrow!(from_interp(interp.decsc_seq()).map(ok_result)),
+            b"8" => rethrow!(from_interp(interp.decrc_seq()).map(ok_result)),
+            b"c" => rethrow!(from_interp(interp.ris_seq()).map(ok_result)),
+            b"=" => rethrow!(from_interp(interp.deckpam_seq()).map(ok_result)),
+            b">" => rethrow!(from_interp(interp.deckpnm_seq()).map(ok_result)),
+            b"#8" => rethrow!(from_interp(interp.decaln_seq()).map(ok_result)),
+            b"N" => rethrow!(from_interp(interp.ss_seq(CharsetSlot::G2)).map(ok_result)),
+            b"O" => rethrow!(from_interp(interp.ss_seq(CharsetSlot::G3)).map(ok_result)),
+            b"n" => rethrow!(from_interp(interp.ls_seq(CharsetSlot::G2)).map(ok_result)),
+            b"o" => rethrow!(from_interp(interp.ls_seq(CharsetSlot::G3)).map(ok_result)),
+            &[inter @ b'('...b'+', fin] => {
+                let slot = match inter {
+                    b'(' => CharsetSlot::G0,
+                    b')' => CharsetSlot::G1,
+                    b'*' => CharsetSlot::G2,
+                    _ => CharsetSlot::G3,
+                };
+                match Charset::from_final(fin) {
+                    Some(set) => rethrow!(from_interp(interp.scs_seq(slot, set)).map(ok_result)),
+                    None => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+                }
+            },
+            _ => rethrow!(from_interp(interp.other_seq(&bytes)).map(ok_result))
+        }
     }
 }
 
-trait ParseNum: Zero + ValueFrom<u64> + Add<Self, Output=Self> + Mul<Self, Output=Self> {}
+/**
+Dispatch one of the single-byte locking shifts: SO invokes G1 into GL, SI invokes G0.
+*/
+fn parse_shift_control<I>(b: u8, interp: &mut I) -> Result<(), GenError>
+where I: AnsiInterpret {
+    match b {
+        SO => interp.ls_seq(CharsetSlot::G1),
+        SI => interp.ls_seq(CharsetSlot::G0),
+        _ => throw!(MalformedSeq)
+    }
+}
+
+/**
+Pull apart a control sequence with only numeric parameters, given without its leading `ESC`, as `other_seq` gets them.  Returns whether it had a `?` marker, the parameters with empty ones as 0, and the final byte.
+*/
+pub fn parse_csi(bytes: &[u8]) -> Option<(bool, Vec<u16>, u8)> {
+    let (&final_byte, body) = match bytes.split_last() {
+        Some((f, body)) if body.first() == Some(&b'[') => (f, &body[1..]),
+        _ => return None
+    };
+    let (private, body) = match body.first() {
+        Some(&b'?') => (true, &body[1..]),
+        _ => (false, body)
+    };
+    if body.iter().any(|&b| b != b';' && !b.is_ascii_digit()) {
+        return None;
+    }
+    let params = if body.is_empty() {
+        vec![]
+    } else {
+        body.split(|&b| b == b';')
+            .map(|p| p.iter().fold(0u16, |n, &d| n.saturating_mul(10).saturating_add((d - b'0') as u16)))
+            .collect()
+    };
+    Some((private, params, final_byte))
+}
+
+trait ParseNum: Zero + Bounded + CheckedAdd + CheckedMul + ValueFrom<u64> + Add<Self, Output=Self> + Mul<Self, Output=Self> {}
 impl<T> ParseNum for T
-where T: Zero + ValueFrom<u64> + Add<T, Output=T> + Mul<T, Output=T> {}
+where T: Zero + Bounded + CheckedAdd + CheckedMul + ValueFrom<u64> + Add<T, Output=T> + Mul<T, Output=T> {}
 
 fn parse_0n(mut bytes: &[u8]) -> Result<(), MalformedSeq> {
     if bytes != b"" {
@@ -558,20 +912,108 @@ fn test_parse_ns() {
     check_ns!(b"0;1m", Err(MalformedSeq));
 }
 
+/**
+Parse SGR parameters, which may carry `:`-separated sub-parameters (as in `38:2::255:0:0`).  Defaulted parameters are kept as `None`.
+*/
+fn parse_sgr_params<A>(mut bytes: &[u8]) -> Result<SmallVec<A>, MalformedSeq>
+where A: Array<Item=SgrParam> {
+    let mut ps = SmallVec::new();
+    let mut sub = false;
+
+    while bytes != b"" {
+        let (tail, n) = try!(parse_num(bytes));
+        ps.push(SgrParam { value: n, sub: sub });
+        match tail.first().cloned() {
+            Some(b';') => {
+                sub = false;
+                bytes = &tail[1..];
+            },
+            Some(b':') => {
+                sub = true;
+                bytes = &tail[1..];
+            },
+            Some(_) => return Err(MalformedSeq),
+            None => bytes = tail,
+        }
+    }
+
+    Ok(ps)
+}
+
+#[test]
+fn test_parse_sgr_params() {
+    fn ps(bs: &[u8]) -> Result<Vec<(Option<u16>, bool)>, MalformedSeq> {
+        parse_sgr_params::<[SgrParam; 2]>(bs)
+            .map(|a| a.iter().map(|p| (p.value, p.sub)).collect())
+    }
+
+    assert_eq!(ps(b""), Ok(vec![]));
+    assert_eq!(ps(b"1;31"), Ok(vec![(Some(1), false), (Some(31), false)]));
+    assert_eq!(ps(b"1;;31"), Ok(vec![(Some(1), false), (None, false), (Some(31), false)]));
+    assert_eq!(ps(b"4:3"), Ok(vec![(Some(4), false), (Some(3), true)]));
+    assert_eq!(ps(b"58:2::255:0:0;1"), Ok(vec![
+        (Some(58), false), (Some(2), true), (None, true),
+        (Some(255), true), (Some(0), true), (Some(0), true),
+        (Some(1), false)]));
+    assert_eq!(ps(b"1m"), Err(MalformedSeq));
+}
+
+/**
+Like `parse_ns`, except that defaulted parameters are kept as `None`, for sequences where a parameter's meaning depends on its position.
+*/
+fn parse_ns_opt<A, N>(mut bytes: &[u8]) -> Result<SmallVec<A>, MalformedSeq>
+where
+    A: Array<Item=Option<N>>,
+    N: ParseNum,
+{
+    let mut ns = SmallVec::new();
+
+    while bytes != b"" {
+        let (tail, n) = try!(parse_num(bytes));
+        match tail.first().cloned() {
+            Some(b';') => bytes = &tail[1..],
+            Some(_) => return Err(MalformedSeq),
+            None => bytes = tail,
+        }
+        ns.push(n);
+    }
+
+    Ok(ns)
+}
+
+#[test]
+fn test_parse_ns_opt() {
+    fn ns(bs: &[u8]) -> Result<Vec<Option<u8>>, MalformedSeq> {
+        parse_ns_opt::<[Option<u8>; 2], u8>(bs)
+            .map(|a| a.iter().cloned().collect())
+    }
+
+    assert_eq!(ns(b""), Ok(vec![]));
+    assert_eq!(ns(b"0"), Ok(vec![Some(0)]));
+    assert_eq!(ns(b";"), Ok(vec![None]));
+    assert_eq!(ns(b";1"), Ok(vec![None, Some(1)]));
+    assert_eq!(ns(b"8;;80"), Ok(vec![Some(8), None, Some(80)]));
+    assert_eq!(ns(b"0m"), Err(MalformedSeq));
+}
+
 fn parse_num<N>(mut bytes: &[u8]) -> Result<(&[u8], Option<N>), MalformedSeq>
 where N: ParseNum {
-    let mut v = Zero::zero();
+    let mut v: N = Zero::zero();
     let mut default = true;
     while let Some(&b) = bytes.first() {
         match b {
             b'0'...b'9' => {
                 let dig = try!(((b - b'0') as u64).value_into()
                     .map_err(|_| MalformedSeq));
-                v = (v * try!(10.value_into().map_err(|_| MalformedSeq))) + dig;
+                let ten = try!(10.value_into().map_err(|_| MalformedSeq));
+                // Absurdly large parameters saturate rather than overflow.
+                v = v.checked_mul(&ten)
+                    .and_then(|v: N| v.checked_add(&dig))
+                    .unwrap_or_else(N::max_value);
                 default = false;
                 bytes = {&bytes[1..]};
             },
-            b';' => {
+            b';' | b':' => {
                 let v = if default { None } else { Some(v) };
                 return Ok((bytes, v))
             },
@@ -594,6 +1036,10 @@ fn test_parse_num() {
     assert_eq!(parse_num(b"12"),      Ok((bs(b""), Some(12))));
     assert_eq!(parse_num(b"12;3"),    Ok((bs(b";3"), Some(12))));
 
+    assert_eq!(parse_num(b"4:3"),     Ok((bs(b":3"), Some(4))));
+    assert_eq!(parse_num::<u8>(b"300"),   Ok((bs(b""), Some(255))));
+    assert_eq!(parse_num::<u16>(b"99999999;1"), Ok((bs(b";1"), Some(65535))));
+
     assert_eq!(parse_num::<i32>(b"m"),    Err(MalformedSeq));
     assert_eq!(parse_num::<i32>(b"0m"),   Err(MalformedSeq));
 }
@@ -602,6 +1048,10 @@ fn is_escape_start(b: u8) -> bool {
     b == ESC
 }
 
+fn is_shift_control(b: u8) -> bool {
+    b == SO || b == SI
+}
+
 fn is_escape_end(b: u8) -> bool {
     0x40 <= b && b <= 0x7e
 }
@@ -0,0 +1,273 @@
+/*!
+The MS-DOS `ANSI.SYS` dialect.
+
+Most of what `ANSI.SYS` understood is shared with everything else, but it also had screen modes (`CSI = Ps h` and `CSI = Ps l`) and, through the "iCE colour" convention used by BBS art, a way of getting bright backgrounds out of the blink attribute.
+*/
+use std::error::Error;
+use std::io;
+use conv::TryFrom;
+use ansi::{AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use charset::{Charset, CharsetSlot};
+use query::Query;
+use sgr::{self, Color, SgrAttr, SgrAttrs};
+use style::{StyleMask, MAX_PUSHED_STYLES};
+use window::WindowOp;
+
+/**
+A screen mode, as set by `CSI = Ps h`.
+
+The graphics modes are named for their resolution and number of colours.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum ScreenMode {
+    Text40x25Mono = 0,
+    Text40x25 = 1,
+    Text80x25Mono = 2,
+    Text80x25 = 3,
+    Graphics320x200x4 = 4,
+    Graphics320x200Mono = 5,
+    Graphics640x200Mono = 6,
+    /// Wrap at the end of a line; this is the only mode `CSI = Ps l` can actually turn off.
+    LineWrap = 7,
+    Graphics320x200x16 = 13,
+    Graphics640x200x16 = 14,
+    Graphics640x350Mono = 15,
+    Graphics640x350x16 = 16,
+    Graphics640x480Mono = 17,
+    Graphics640x480x16 = 18,
+    Graphics320x200x256 = 19,
+}
+
+marker_error! {
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct UnknownScreenMode
+    impl {
+        desc {"unknown screen mode"}
+    }
+}
+
+impl TryFrom<Option<u8>> for ScreenMode {
+    type Err = UnknownScreenMode;
+    fn try_from(v: Option<u8>) -> Result<ScreenMode, Self::Err> {
+        use self::ScreenMode::*;
+        match v.unwrap_or(0) {
+            0 => Ok(Text40x25Mono),
+            1 => Ok(Text40x25),
+            2 => Ok(Text80x25Mono),
+            3 => Ok(Text80x25),
+            4 => Ok(Graphics320x200x4),
+            5 => Ok(Graphics320x200Mono),
+            6 => Ok(Graphics640x200Mono),
+            7 => Ok(LineWrap),
+            13 => Ok(Graphics320x200x16),
+            14 => Ok(Graphics640x200x16),
+            15 => Ok(Graphics640x350Mono),
+            16 => Ok(Graphics640x350x16),
+            17 => Ok(Graphics640x480Mono),
+            18 => Ok(Graphics640x480x16),
+            19 => Ok(Graphics320x200x256),
+            _ => Err(UnknownScreenMode)
+        }
+    }
+}
+
+#[test]
+fn test_screen_mode_try_from() {
+    use conv::TryInto;
+    fn mode(n: Option<u8>) -> Result<ScreenMode, UnknownScreenMode> { n.try_into() }
+
+    assert_eq!(mode(None), Ok(ScreenMode::Text40x25Mono));
+    assert_eq!(mode(Some(3)), Ok(ScreenMode::Text80x25));
+    assert_eq!(mode(Some(7)), Ok(ScreenMode::LineWrap));
+    assert_eq!(mode(Some(19)), Ok(ScreenMode::Graphics320x200x256));
+    assert_eq!(mode(Some(8)), Err(UnknownScreenMode));
+}
+
+/**
+Wraps another interpreter, giving it the `ANSI.SYS` reading of sequences.
+
+`CSI = 7 h` and `CSI = 7 l` become `set_line_wrap`; the other screen modes are passed on untouched.  With iCE colours turned on, blinking text instead gets the bright version of its background colour, the way BBS art expects.  Since DOS always had a black background, the default background becomes bright black.
+
+Blinking and the background are saved and restored along with the wrapped interpreter's, by DECSC/DECRC and XTPUSHSGR/XTPOPSGR.
+*/
+pub struct AnsiSys<I>
+where I: AnsiInterpret {
+    interp: I,
+    ice_colors: bool,
+    blink: bool,
+    bg: Color,
+    saved: (bool, Color),
+    stack: Vec<(bool, Color, StyleMask)>,
+}
+
+impl<I> AnsiSys<I>
+where I: AnsiInterpret {
+    pub fn new(interp: I, ice_colors: bool) -> Self {
+        AnsiSys {
+            interp: interp,
+            ice_colors: ice_colors,
+            blink: false,
+            bg: Color::Default,
+            saved: (false, Color::Default),
+            stack: vec![],
+        }
+    }
+
+    pub fn get_ref(&self) -> &I {
+        &self.interp
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.interp
+    }
+
+    pub fn into_inner(self) -> I {
+        self.interp
+    }
+
+    fn ice_bg(&self) -> Color {
+        ice_bg(self.blink, self.bg)
+    }
+}
+
+/// The background to actually show, given whether text is blinking and the background it asked for.
+fn ice_bg(blink: bool, bg: Color) -> Color {
+    match (blink, bg) {
+        (false, c) => c,
+        (true, Color::Default) => Color::Indexed(8),
+        (true, Color::Indexed(n)) if n < 8 => Color::Indexed(n + 8),
+        (true, c) => c,
+    }
+}
+
+impl<I> AnsiInterpret for AnsiSys<I>
+where I: AnsiInterpret {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.interp.write_text(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interp.flush()
+    }
+
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> {
+        use sgr::SgrAttr::*;
+        if !self.ice_colors {
+            return self.interp.sgr_attrs(attrs);
+        }
+
+        let mut out: Vec<SgrAttr> = vec![];
+        for attr in attrs {
+            match attr {
+                Reset => {
+                    self.blink = false;
+                    self.bg = Color::Default;
+                    out.push(Reset);
+                },
+                SlowBlink | RapidBlink => {
+                    self.blink = true;
+                    out.push(Background(self.ice_bg()));
+                },
+                NotBlinking => {
+                    self.blink = false;
+                    out.push(Background(self.bg));
+                },
+                Background(c) => {
+                    self.bg = c;
+                    out.push(Background(self.ice_bg()));
+                },
+                attr => out.push(attr)
+            }
+        }
+
+        let ps = sgr::sgr_params(&out);
+        if ps.len() == 0 {
+            return Ok(());
+        }
+        self.interp.sgr_attrs(sgr::sgr_attrs(&ps))
+    }
+
+    fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool) -> Result<(), GenError> {
+        match mode {
+            ScreenMode::LineWrap => self.interp.set_line_wrap(set),
+            mode => self.interp.screen_mode_seq(mode, set)
+        }
+    }
+
+    fn xtpushsgr_seq(&mut self, mask: StyleMask) -> Result<(), GenError> {
+        if self.stack.len() < MAX_PUSHED_STYLES {
+            self.stack.push((self.blink, self.bg, mask));
+        }
+        self.interp.xtpushsgr_seq(mask)
+    }
+
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> {
+        let showing = self.ice_bg();
+        try!(self.interp.xtpopsgr_seq());
+        let (blink, bg, mask) = match self.stack.pop() {
+            Some(pushed) => pushed,
+            None => return Ok(())
+        };
+        if mask.blink {
+            self.blink = blink;
+        }
+        if mask.bg {
+            self.bg = bg;
+        }
+
+        // The wrapped interpreter only put its background back if the mask said to, but blinking changes it too.
+        let showing = if mask.bg { ice_bg(blink, bg) } else { showing };
+        if self.ice_colors && showing != self.ice_bg() {
+            let ps = sgr::sgr_params(&[SgrAttr::Background(self.ice_bg())]);
+            try!(self.interp.sgr_attrs(sgr::sgr_attrs(&ps)));
+        }
+        Ok(())
+    }
+
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        self.saved = (self.blink, self.bg);
+        self.interp.decsc_seq()
+    }
+
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        let (blink, bg) = self.saved;
+        self.blink = blink;
+        self.bg = bg;
+        self.interp.decrc_seq()
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        self.blink = false;
+        self.bg = Color::Default;
+        self.saved = (false, Color::Default);
+        self.stack.clear();
+        self.interp.ris_seq()
+    }
+
+    forward_seqs! { interp;
+        fn cuu_seq(&mut self, r: u16);
+        fn cud_seq(&mut self, r: u16);
+        fn cuf_seq(&mut self, c: u16);
+        fn cub_seq(&mut self, c: u16);
+        fn cup_seq(&mut self, r: u16, c: u16);
+        fn ed_seq(&mut self, n: EraseDisplay);
+        fn el_seq(&mut self, n: EraseLine);
+        fn dsr_seq(&mut self);
+        fn scp_seq(&mut self);
+        fn rcp_seq(&mut self);
+        fn deckpam_seq(&mut self);
+        fn deckpnm_seq(&mut self);
+        fn decaln_seq(&mut self);
+        fn scs_seq(&mut self, slot: CharsetSlot, set: Charset);
+        fn ls_seq(&mut self, slot: CharsetSlot);
+        fn ss_seq(&mut self, slot: CharsetSlot);
+        fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool);
+        fn set_cursor_visible(&mut self, visible: bool);
+        fn set_line_wrap(&mut self, wrap: bool);
+        fn xtwinops_seq(&mut self, op: WindowOp);
+        fn query_seq(&mut self, q: Query);
+        fn osc_txt_seq(&mut self, n: u16, txt: &str);
+        fn hvp_seq(&mut self, r: u16, c: u16);
+        fn other_seq(&mut self, bytes: &[u8]);
+    }
+}
@@ -0,0 +1,256 @@
+/*!
+Deciding how much of the escape sequences in some output a stream can take.
+*/
+use std::env;
+use std::fs::File;
+use std::io::{self, Write};
+use ansi::{AnsiIntercept, AnsiInterpret};
+use palette::ColorReducer;
+use sgr::ColorDepth;
+use writer::AnsiWriter;
+
+/**
+What an `AutoStream` does with the escape sequences written to it.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum StreamMode {
+    /// Write everything through untouched.
+    PassThrough,
+    /// Squash colours down to the given depth, leaving everything else alone.  With `Monochrome`, colours are dropped entirely.
+    Reduce(ColorDepth),
+    /// Take out every escape sequence, leaving just the text.
+    Strip,
+}
+
+impl StreamMode {
+    /**
+    Work out what a stream should get from whether it's a terminal and the environment, following the usual conventions:
+
+    - `CLICOLOR_FORCE` set to anything but `0` means colour even when not writing to a terminal, and overrides everything below.
+    - Output that isn't going to a terminal, or going to one with `TERM=dumb`, gets no escape sequences at all.
+    - `NO_COLOR` set to anything, or `CLICOLOR=0`, means no colour, though other sequences still go through.
+    - Otherwise the colour depth comes from `COLORTERM` being `truecolor` or `24bit`, or `TERM` mentioning `256color`, and is 16 colours if neither says more.
+    */
+    pub fn detect(is_terminal: bool) -> StreamMode {
+        StreamMode::detect_with(is_terminal, |name| env::var(name).ok())
+    }
+
+    /// `detect`, with the environment looked up by `var` rather than read from the process.
+    pub fn detect_with<F>(is_terminal: bool, var: F) -> StreamMode
+    where F: Fn(&str) -> Option<String> {
+        let term = var("TERM").unwrap_or_default();
+        let set = |name, ignore: &str| match var(name) {
+            Some(value) => !value.is_empty() && value != ignore,
+            None => false
+        };
+        if !set("CLICOLOR_FORCE", "0") {
+            if !is_terminal || term == "dumb" {
+                return StreamMode::Strip;
+            }
+            if set("NO_COLOR", "") || var("CLICOLOR") == Some(String::from("0")) {
+                return StreamMode::Reduce(ColorDepth::Monochrome);
+            }
+        }
+
+        match var("COLORTERM") {
+            Some(ref colorterm) if colorterm == "truecolor" || colorterm == "24bit" => StreamMode::PassThrough,
+            _ if term.contains("256color") => StreamMode::Reduce(ColorDepth::Indexed256),
+            _ => StreamMode::Reduce(ColorDepth::Ansi16)
+        }
+    }
+}
+
+/**
+Something that can tell whether it's a terminal, for `AutoStream::new`.
+*/
+pub trait IsTty {
+    fn is_tty(&self) -> bool;
+}
+
+impl IsTty for io::Stdout {
+    fn is_tty(&self) -> bool {
+        is_tty(self)
+    }
+}
+
+impl IsTty for io::Stderr {
+    fn is_tty(&self) -> bool {
+        is_tty(self)
+    }
+}
+
+impl IsTty for File {
+    fn is_tty(&self) -> bool {
+        is_tty(self)
+    }
+}
+
+#[cfg(unix)]
+fn is_tty<T>(file: &T) -> bool
+where T: ::std::os::unix::io::AsRawFd {
+    ::unix::is_tty(file.as_raw_fd())
+}
+
+#[cfg(windows)]
+fn is_tty<T>(file: &T) -> bool
+where T: ::std::os::windows::io::AsRawHandle {
+    ::win32::is_console(file.as_raw_handle())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn is_tty<T>(_: &T) -> bool {
+    false
+}
+
+/**
+A writer that passes output on with as many of its escape sequences as the destination can cope with.
+
+`new` decides that with `StreamMode::detect`, so the same program can write colourful output and have it come out right whether it's on a terminal, piped into a file or run with `NO_COLOR`.  Anything more than passing output through runs it through the parser, so sequences are only ever changed whole, even when split across writes.
+*/
+pub struct AutoStream<W>
+where W: Write {
+    mode: StreamMode,
+    inner: Inner<W>,
+}
+
+enum Inner<W>
+where W: Write {
+    PassThrough(W),
+    Reduce(Box<AnsiIntercept<ColorReducer<AnsiWriter<W>>>>),
+    Strip(AnsiIntercept<TextWriter<W>>),
+}
+
+impl<W> AutoStream<W>
+where W: Write {
+    /// Wrap `out`, detecting what it can take from whether it's a terminal and the environment.
+    pub fn new(out: W) -> Self
+    where W: IsTty {
+        let mode = StreamMode::detect(out.is_tty());
+        AutoStream::with_mode(out, mode)
+    }
+
+    /// Wrap `out`, treating it the way `mode` says.
+    pub fn with_mode(out: W, mode: StreamMode) -> Self {
+        let inner = match mode {
+            StreamMode::PassThrough => Inner::PassThrough(out),
+            StreamMode::Reduce(depth) => Inner::Reduce(Box::new(AnsiIntercept::new(ColorReducer::new(AnsiWriter::new(out), depth)))),
+            StreamMode::Strip => Inner::Strip(AnsiIntercept::new(TextWriter(out))),
+        };
+        AutoStream {
+            mode: mode,
+            inner: inner,
+        }
+    }
+
+    pub fn mode(&self) -> StreamMode {
+        self.mode
+    }
+
+    pub fn get_ref(&self) -> &W {
+        match self.inner {
+            Inner::PassThrough(ref out) => out,
+            Inner::Reduce(ref out) => out.get_ref().get_ref().get_ref(),
+            Inner::Strip(ref out) => &out.get_ref().0,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        match self.inner {
+            Inner::PassThrough(ref mut out) => out,
+            Inner::Reduce(ref mut out) => out.get_mut().get_mut().get_mut(),
+            Inner::Strip(ref mut out) => &mut out.get_mut().0,
+        }
+    }
+}
+
+impl AutoStream<io::Stdout> {
+    /// Standard output, with what it can take detected.
+    pub fn stdout() -> Self {
+        AutoStream::new(io::stdout())
+    }
+}
+
+impl AutoStream<io::Stderr> {
+    /// Standard error, with what it can take detected.
+    pub fn stderr() -> Self {
+        AutoStream::new(io::stderr())
+    }
+}
+
+impl<W> Write for AutoStream<W>
+where W: Write {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner {
+            Inner::PassThrough(ref mut out) => out.write(buf),
+            Inner::Reduce(ref mut out) => out.write(buf),
+            Inner::Strip(ref mut out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner {
+            Inner::PassThrough(ref mut out) => out.flush(),
+            Inner::Reduce(ref mut out) => out.flush(),
+            Inner::Strip(ref mut out) => out.flush(),
+        }
+    }
+}
+
+/// An interpreter that writes out the text and ignores everything else.
+struct TextWriter<W>(W);
+
+impl<W> AnsiInterpret for TextWriter<W>
+where W: Write {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[test]
+fn test_stream_mode_detect() {
+    use sgr::ColorDepth::*;
+    use self::StreamMode::*;
+
+    fn detect(is_terminal: bool, vars: &[(&str, &str)]) -> StreamMode {
+        StreamMode::detect_with(is_terminal, |name| {
+            vars.iter().find(|&&(k, _)| k == name).map(|&(_, v)| String::from(v))
+        })
+    }
+
+    assert_eq!(detect(true, &[]), Reduce(Ansi16));
+    assert_eq!(detect(false, &[]), Strip);
+    assert_eq!(detect(true, &[("TERM", "xterm-256color")]), Reduce(Indexed256));
+    assert_eq!(detect(true, &[("TERM", "xterm-256color"), ("COLORTERM", "truecolor")]), PassThrough);
+    assert_eq!(detect(true, &[("COLORTERM", "24bit")]), PassThrough);
+    assert_eq!(detect(true, &[("COLORTERM", "yes")]), Reduce(Ansi16));
+    assert_eq!(detect(true, &[("TERM", "dumb"), ("COLORTERM", "truecolor")]), Strip);
+    assert_eq!(detect(true, &[("NO_COLOR", "1"), ("COLORTERM", "truecolor")]), Reduce(Monochrome));
+    assert_eq!(detect(true, &[("NO_COLOR", "")]), Reduce(Ansi16));
+    assert_eq!(detect(true, &[("CLICOLOR", "0")]), Reduce(Monochrome));
+    assert_eq!(detect(true, &[("CLICOLOR", "1")]), Reduce(Ansi16));
+    assert_eq!(detect(false, &[("CLICOLOR", "1")]), Strip);
+    assert_eq!(detect(false, &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")]), Reduce(Ansi16));
+    assert_eq!(detect(false, &[("CLICOLOR_FORCE", "1"), ("TERM", "dumb")]), Reduce(Ansi16));
+    assert_eq!(detect(false, &[("CLICOLOR_FORCE", "0")]), Strip);
+}
+
+#[test]
+fn test_auto_stream() {
+    fn write(mode: StreamMode, parts: &[&str]) -> String {
+        let mut out = AutoStream::with_mode(vec![], mode);
+        for part in parts {
+            out.write_all(part.as_bytes()).unwrap();
+        }
+        String::from_utf8(out.get_ref().clone()).unwrap()
+    }
+
+    let parts = ["\x1b[1;38;2;255;0", ";0mhot\x1b[0m \x1b", "[2Kcold"];
+    assert_eq!(write(StreamMode::PassThrough, &parts), "\x1b[1;38;2;255;0;0mhot\x1b[0m \x1b[2Kcold");
+    assert_eq!(write(StreamMode::Reduce(ColorDepth::Ansi16), &parts), "\x1b[1;91mhot\x1b[0m \x1b[2Kcold");
+    assert_eq!(write(StreamMode::Reduce(ColorDepth::Monochrome), &parts), "\x1b[1;39mhot\x1b[0m \x1b[2Kcold");
+    assert_eq!(write(StreamMode::Strip, &parts), "hot cold");
+}
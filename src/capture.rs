@@ -0,0 +1,312 @@
+/*!
+Capturing what a piece of code writes to the standard streams, decoded into events.
+*/
+#![cfg(unix)]
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use ansisys::ScreenMode;
+use ansi::{AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use charset::{Charset, CharsetSlot};
+use query::Query;
+use sgr::{SgrAttr, SgrAttrs};
+use style::StyleMask;
+use window::WindowOp;
+
+/**
+One thing the parser found in a stream; there's one of these for each `AnsiInterpret` method.
+
+Cursor position reports come through as `Query(Query::CursorPosition)`, and HVP as `Cup`.
+*/
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+pub enum Event {
+    /// A run of text between sequences.  Invalid UTF-8 is replaced with U+FFFD.
+    Text(String),
+    Cuu(u16),
+    Cud(u16),
+    Cuf(u16),
+    Cub(u16),
+    Cup(u16, u16),
+    Ed(EraseDisplay),
+    El(EraseLine),
+    Sgr(Vec<SgrAttr>),
+    XtPushSgr(StyleMask),
+    XtPopSgr,
+    Scp,
+    Rcp,
+    DecSc,
+    DecRc,
+    Ris,
+    DecKpam,
+    DecKpnm,
+    DecAln,
+    Scs(CharsetSlot, Charset),
+    Ls(CharsetSlot),
+    Ss(CharsetSlot),
+    CursorStyle(CursorShape, bool),
+    CursorVisible(bool),
+    LineWrap(bool),
+    ScreenMode(ScreenMode, bool),
+    XtWinOps(WindowOp),
+    Query(Query),
+    Osc(u16, String),
+    /// A sequence the parser didn't understand, without its leading `ESC`.
+    Other(Vec<u8>),
+}
+
+/**
+Everything written to standard output and standard error by the code given to `capture_stdio`.
+*/
+#[derive(Clone, Eq, PartialEq, Debug, Default)]
+pub struct Captured {
+    pub stdout: Vec<Event>,
+    pub stderr: Vec<Event>,
+}
+
+impl Captured {
+    /// Standard output with all the escape sequences taken out.
+    pub fn stdout_text(&self) -> String {
+        plain_text(&self.stdout)
+    }
+
+    /// Standard error with all the escape sequences taken out.
+    pub fn stderr_text(&self) -> String {
+        plain_text(&self.stderr)
+    }
+}
+
+fn plain_text(events: &[Event]) -> String {
+    events.iter()
+        .filter_map(|event| match *event {
+            Event::Text(ref text) => Some(&**text),
+            _ => None
+        })
+        .collect()
+}
+
+/// Held for the whole of each capture, since the standard streams belong to the whole process.
+static CAPTURING: Mutex<()> = Mutex::new(());
+
+/**
+Run `f` with standard output and standard error redirected, and return everything it wrote to them.
+
+The streams are put back when `f` returns, even if it panics.  Standard input is left alone.  Captures on different threads wait for each other.
+
+The test harness captures the output of `print!` and friends for itself unless run with `--nocapture`, and that never reaches the real standard output; writing through `io::stdout()` and `io::stderr()` directly, or running child processes, isn't affected.  To capture what a program prints, run it as a child process.
+*/
+pub fn capture_stdio<F>(f: F) -> io::Result<Captured>
+where F: FnOnce() {
+    use unix::intercept_fds;
+
+    // A capture that panicked has already put the streams back.
+    let _capturing = CAPTURING.lock().unwrap_or_else(|err| err.into_inner());
+
+    let stdout = Arc::new(Mutex::new(Recorder::default()));
+    let stderr = Arc::new(Mutex::new(Recorder::default()));
+    let handle = {
+        let (stdout, stderr) = (stdout.clone(), stderr.clone());
+        try!(intercept_fds(&[1, 2], move |fd, _, _| {
+            Shared(if fd == 1 { stdout.clone() } else { stderr.clone() })
+        }))
+    };
+    f();
+    try!(handle.join());
+
+    let stdout = mem::take(&mut *stdout.lock().unwrap());
+    let stderr = mem::take(&mut *stderr.lock().unwrap());
+    Ok(Captured {
+        stdout: stdout.finish(),
+        stderr: stderr.finish(),
+    })
+}
+
+/// Collects events, joining up text that arrives in pieces.
+#[derive(Default)]
+struct Recorder {
+    events: Vec<Event>,
+    text: Vec<u8>,
+}
+
+impl Recorder {
+    fn push(&mut self, event: Event) -> Result<(), GenError> {
+        self.end_text();
+        self.events.push(event);
+        Ok(())
+    }
+
+    fn end_text(&mut self) {
+        if !self.text.is_empty() {
+            let text = String::from_utf8_lossy(&self.text).into_owned();
+            self.events.push(Event::Text(text));
+            self.text.clear();
+        }
+    }
+
+    fn finish(mut self) -> Vec<Event> {
+        self.end_text();
+        self.events
+    }
+}
+
+/// A `Recorder` that can be got back after the interpreter has gone off to another thread.
+struct Shared(Arc<Mutex<Recorder>>);
+
+impl Shared {
+    fn push(&self, event: Event) -> Result<(), GenError> {
+        self.0.lock().unwrap().push(event)
+    }
+}
+
+impl AnsiInterpret for Shared {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().text.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn cuu_seq(&mut self, r: u16) -> Result<(), GenError> {
+        self.push(Event::Cuu(r))
+    }
+
+    fn cud_seq(&mut self, r: u16) -> Result<(), GenError> {
+        self.push(Event::Cud(r))
+    }
+
+    fn cuf_seq(&mut self, c: u16) -> Result<(), GenError> {
+        self.push(Event::Cuf(c))
+    }
+
+    fn cub_seq(&mut self, c: u16) -> Result<(), GenError> {
+        self.push(Event::Cub(c))
+    }
+
+    fn cup_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
+        self.push(Event::Cup(r, c))
+    }
+
+    fn ed_seq(&mut self, n: EraseDisplay) -> Result<(), GenError> {
+        self.push(Event::Ed(n))
+    }
+
+    fn el_seq(&mut self, n: EraseLine) -> Result<(), GenError> {
+        self.push(Event::El(n))
+    }
+
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> {
+        self.push(Event::Sgr(attrs.collect()))
+    }
+
+    fn xtpushsgr_seq(&mut self, mask: StyleMask) -> Result<(), GenError> {
+        self.push(Event::XtPushSgr(mask))
+    }
+
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::XtPopSgr)
+    }
+
+    fn dsr_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::Query(Query::CursorPosition))
+    }
+
+    fn scp_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::Scp)
+    }
+
+    fn rcp_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::Rcp)
+    }
+
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::DecSc)
+    }
+
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::DecRc)
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::Ris)
+    }
+
+    fn deckpam_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::DecKpam)
+    }
+
+    fn deckpnm_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::DecKpnm)
+    }
+
+    fn decaln_seq(&mut self) -> Result<(), GenError> {
+        self.push(Event::DecAln)
+    }
+
+    fn scs_seq(&mut self, slot: CharsetSlot, set: Charset) -> Result<(), GenError> {
+        self.push(Event::Scs(slot, set))
+    }
+
+    fn ls_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> {
+        self.push(Event::Ls(slot))
+    }
+
+    fn ss_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> {
+        self.push(Event::Ss(slot))
+    }
+
+    fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool) -> Result<(), GenError> {
+        self.push(Event::CursorStyle(shape, blinking))
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), GenError> {
+        self.push(Event::CursorVisible(visible))
+    }
+
+    fn set_line_wrap(&mut self, wrap: bool) -> Result<(), GenError> {
+        self.push(Event::LineWrap(wrap))
+    }
+
+    fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool) -> Result<(), GenError> {
+        self.push(Event::ScreenMode(mode, set))
+    }
+
+    fn xtwinops_seq(&mut self, op: WindowOp) -> Result<(), GenError> {
+        self.push(Event::XtWinOps(op))
+    }
+
+    fn query_seq(&mut self, q: Query) -> Result<(), GenError> {
+        self.push(Event::Query(q))
+    }
+
+    fn osc_txt_seq(&mut self, n: u16, txt: &str) -> Result<(), GenError> {
+        self.push(Event::Osc(n, txt.to_owned()))
+    }
+
+    fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
+        self.push(Event::Other(bytes.to_owned()))
+    }
+}
+
+#[test]
+fn test_recorder() {
+    use ansi::AnsiIntercept;
+    use sgr::Color;
+    use std::io::Write;
+
+    let recorder = Arc::new(Mutex::new(Recorder::default()));
+    let mut out = AnsiIntercept::new(Shared(recorder.clone()));
+    out.write_all(b"one \xe2\x82").unwrap();
+    out.write_all(b"\xac two\x1b[1;31mthree\x1b[2;5f\x1b[6n\x1b[?1049h\x1b]0;hi\x07").unwrap();
+    drop(out);
+
+    let recorder = mem::take(&mut *recorder.lock().unwrap());
+    let captured = Captured { stdout: recorder.finish(), stderr: vec![] };
+    assert_eq!(captured.stdout, vec![
+        Event::Text(String::from("one \u{20ac} two")),
+        Event::Sgr(vec![SgrAttr::Bold, SgrAttr::Foreground(Color::Indexed(1))]),
+        Event::Text(String::from("three")),
+        Event::Cup(2, 5),
+        Event::Query(Query::CursorPosition),
+        Event::Other(b"[?1049h".to_vec()),
+        Event::Osc(0, String::from("hi")),
+    ]);
+    assert_eq!(captured.stdout_text(), "one \u{20ac} twothree");
+    assert_eq!(captured.stderr_text(), "");
+}
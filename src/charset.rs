@@ -0,0 +1,293 @@
+/*!
+Character set designation and translation.
+
+VT100-descended terminals can designate one of several 94-character sets into each of the four slots G0–G3, then invoke one of those slots into the "left" half of the code table (GL) with the locking shifts, or for a single character with the single shifts.  Applications mostly use this to get at the DEC Special Graphics line-drawing characters.
+*/
+use std::io;
+use ansisys::ScreenMode;
+use ansi::{AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use query::Query;
+use sgr::SgrAttrs;
+use style::StyleMask;
+use window::WindowOp;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum CharsetSlot {
+    G0,
+    G1,
+    G2,
+    G3,
+}
+
+impl CharsetSlot {
+    fn index(self) -> usize {
+        use self::CharsetSlot::*;
+        match self {
+            G0 => 0,
+            G1 => 1,
+            G2 => 2,
+            G3 => 3,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum Charset {
+    /// US ASCII; designated with `B`.
+    Ascii,
+    /// United Kingdom; ASCII with `#` replaced by `£`.  Designated with `A`.
+    Uk,
+    /// DEC Special Graphics (line drawing); designated with `0`.
+    DecSpecialGraphics,
+}
+
+impl Charset {
+    /**
+    Work out which character set a designation's final byte refers to.
+
+    Returns `None` for sets we don't know how to translate.
+    */
+    pub fn from_final(b: u8) -> Option<Charset> {
+        use self::Charset::*;
+        match b {
+            b'B' => Some(Ascii),
+            b'A' => Some(Uk),
+            b'0' => Some(DecSpecialGraphics),
+            _ => None
+        }
+    }
+
+    /// The final byte that designates this character set; the reverse of `from_final`.
+    pub fn final_byte(self) -> u8 {
+        use self::Charset::*;
+        match self {
+            Ascii => b'B',
+            Uk => b'A',
+            DecSpecialGraphics => b'0',
+        }
+    }
+
+    /**
+    Translate a single GL byte through this character set.
+
+    Returns `None` if the byte maps to itself.
+    */
+    pub fn translate(self, b: u8) -> Option<char> {
+        use self::Charset::*;
+        match self {
+            Ascii => None,
+            Uk => match b {
+                b'#' => Some('\u{a3}'),
+                _ => None
+            },
+            DecSpecialGraphics => match b {
+                0x5f...0x7e => Some(DEC_SPECIAL_GRAPHICS[(b - 0x5f) as usize]),
+                _ => None
+            },
+        }
+    }
+}
+
+/// Translations for `0x5f` through `0x7e` in the DEC Special Graphics set.
+const DEC_SPECIAL_GRAPHICS: [char; 32] = [
+    '\u{a0}',   // _ blank
+    '\u{25c6}', // ` diamond
+    '\u{2592}', // a checkerboard
+    '\u{2409}', // b HT
+    '\u{240c}', // c FF
+    '\u{240d}', // d CR
+    '\u{240a}', // e LF
+    '\u{b0}',   // f degree
+    '\u{b1}',   // g plus/minus
+    '\u{2424}', // h NL
+    '\u{240b}', // i VT
+    '\u{2518}', // j lower-right corner
+    '\u{2510}', // k upper-right corner
+    '\u{250c}', // l upper-left corner
+    '\u{2514}', // m lower-left corner
+    '\u{253c}', // n crossing lines
+    '\u{23ba}', // o scan line 1
+    '\u{23bb}', // p scan line 3
+    '\u{2500}', // q horizontal line
+    '\u{23bc}', // r scan line 7
+    '\u{23bd}', // s scan line 9
+    '\u{251c}', // t left tee
+    '\u{2524}', // u right tee
+    '\u{2534}', // v bottom tee
+    '\u{252c}', // w top tee
+    '\u{2502}', // x vertical line
+    '\u{2264}', // y less-than or equal
+    '\u{2265}', // z greater-than or equal
+    '\u{3c0}',  // { pi
+    '\u{2260}', // | not equal
+    '\u{a3}',   // } pound
+    '\u{b7}',   // ~ centred dot
+];
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+struct CharsetState {
+    sets: [Charset; 4],
+    gl: CharsetSlot,
+}
+
+impl CharsetState {
+    fn new() -> Self {
+        CharsetState {
+            sets: [Charset::Ascii; 4],
+            gl: CharsetSlot::G0,
+        }
+    }
+}
+
+/**
+Wraps another interpreter, translating text through the designated character sets before it reaches `write_text`.
+
+Designations and shifts are consumed by this interpreter; the wrapped interpreter only ever sees translated UTF-8 text.
+*/
+pub struct CharsetTranslator<I>
+where I: AnsiInterpret {
+    interp: I,
+    state: CharsetState,
+    saved: CharsetState,
+    single: Option<CharsetSlot>,
+}
+
+impl<I> CharsetTranslator<I>
+where I: AnsiInterpret {
+    pub fn new(interp: I) -> Self {
+        CharsetTranslator {
+            interp: interp,
+            state: CharsetState::new(),
+            saved: CharsetState::new(),
+            single: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &I {
+        &self.interp
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.interp
+    }
+
+    pub fn into_inner(self) -> I {
+        self.interp
+    }
+}
+
+impl<I> AnsiInterpret for CharsetTranslator<I>
+where I: AnsiInterpret {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Fast path: nothing to translate.
+        if self.single.is_none() && self.state.sets[self.state.gl.index()] == Charset::Ascii {
+            return self.interp.write_text(buf);
+        }
+
+        let mut out = Vec::with_capacity(buf.len());
+        for &b in buf {
+            match b {
+                0x20...0x7e => {
+                    let slot = self.single.take().unwrap_or(self.state.gl);
+                    match self.state.sets[slot.index()].translate(b) {
+                        Some(c) => {
+                            let mut enc = [0; 4];
+                            out.extend(c.encode_utf8(&mut enc).bytes());
+                        },
+                        None => out.push(b)
+                    }
+                },
+                0x80...0xff => {
+                    // Single shifts only apply to GL characters.
+                    self.single = None;
+                    out.push(b);
+                },
+                _ => out.push(b)
+            }
+        }
+
+        // We've already committed to the shift state, so the translated text has to go out in full.
+        let mut out = &out[..];
+        while out.len() > 0 {
+            match try!(self.interp.write_text(out)) {
+                0 => return Err(io::Error::new(io::ErrorKind::WriteZero, "could not write translated text")),
+                n => out = &out[n..]
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interp.flush()
+    }
+
+    fn scs_seq(&mut self, slot: CharsetSlot, set: Charset) -> Result<(), GenError> {
+        self.state.sets[slot.index()] = set;
+        Ok(())
+    }
+
+    fn ls_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> {
+        self.state.gl = slot;
+        Ok(())
+    }
+
+    fn ss_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> {
+        self.single = Some(slot);
+        Ok(())
+    }
+
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        self.saved = self.state;
+        self.interp.decsc_seq()
+    }
+
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        self.state = self.saved;
+        self.interp.decrc_seq()
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        self.state = CharsetState::new();
+        self.saved = CharsetState::new();
+        self.single = None;
+        self.interp.ris_seq()
+    }
+
+    forward_seqs! { interp;
+        fn cuu_seq(&mut self, r: u16);
+        fn cud_seq(&mut self, r: u16);
+        fn cuf_seq(&mut self, c: u16);
+        fn cub_seq(&mut self, c: u16);
+        fn cup_seq(&mut self, r: u16, c: u16);
+        fn ed_seq(&mut self, n: EraseDisplay);
+        fn el_seq(&mut self, n: EraseLine);
+        fn sgr_attrs(&mut self, attrs: SgrAttrs);
+        fn xtpushsgr_seq(&mut self, mask: StyleMask);
+        fn xtpopsgr_seq(&mut self);
+        fn dsr_seq(&mut self);
+        fn scp_seq(&mut self);
+        fn rcp_seq(&mut self);
+        fn deckpam_seq(&mut self);
+        fn deckpnm_seq(&mut self);
+        fn decaln_seq(&mut self);
+        fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool);
+        fn set_cursor_visible(&mut self, visible: bool);
+        fn set_line_wrap(&mut self, wrap: bool);
+        fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool);
+        fn xtwinops_seq(&mut self, op: WindowOp);
+        fn query_seq(&mut self, q: Query);
+        fn osc_txt_seq(&mut self, n: u16, txt: &str);
+        fn hvp_seq(&mut self, r: u16, c: u16);
+        fn other_seq(&mut self, bytes: &[u8]);
+    }
+}
+
+#[test]
+fn test_dec_special_graphics() {
+    let dsg = Charset::DecSpecialGraphics;
+    let border: String = b"lqqk".iter().map(|&b| dsg.translate(b).unwrap()).collect();
+    assert_eq!(border, "\u{250c}\u{2500}\u{2500}\u{2510}");
+    assert_eq!(dsg.translate(b'A'), None);
+    assert_eq!(Charset::Uk.translate(b'#'), Some('\u{a3}'));
+    assert_eq!(Charset::Ascii.translate(b'q'), None);
+}
@@ -0,0 +1,244 @@
+/*!
+Mapping SGR attributes onto console text attributes.
+*/
+use palette::Palette;
+use sgr::{Color, ColorDepth, SgrAttr, UnderlineStyle};
+use style::Style;
+use super::{
+    BACKGROUND_INTENSITY, BACKGROUND_SHIFT, BACKGROUND_WHITE, COLOR_ALL,
+    COMMON_LVB_REVERSE_VIDEO, COMMON_LVB_UNDERSCORE,
+    FOREGROUND_INTENSITY, FOREGROUND_SHIFT, FOREGROUND_WHITE,
+};
+
+/// SGR colours are numbered BGR, the console numbers them RGB.
+fn split_bits(c: u8) -> u16 {
+    (((c & 1) << 2)
+        | (c & 2)
+        | ((c & 4) >> 2)) as u16
+}
+
+/// The other way round; swapping red and blue undoes itself.
+fn join_bits(c: u16) -> u8 {
+    split_bits(c as u8) as u8
+}
+
+/// All the bits SGR can change.
+const SGR_ALL: u16 = COLOR_ALL | COMMON_LVB_REVERSE_VIDEO | COMMON_LVB_UNDERSCORE;
+const FOREGROUND_ALL: u16 = FOREGROUND_WHITE | FOREGROUND_INTENSITY;
+const BACKGROUND_ALL: u16 = BACKGROUND_WHITE | BACKGROUND_INTENSITY;
+
+/**
+Apply SGR attributes to console text attributes, using the default palette to pick console colours.
+
+See `apply_sgr_with_palette`.
+*/
+pub fn apply_sgr(current: u16, original: u16, attrs: &[SgrAttr]) -> u16 {
+    apply_sgr_with_palette(current, original, attrs, &Palette::default())
+}
+
+/**
+Apply SGR attributes to console text attributes.
+
+`current` is what the console is using now, and `original` is what it was using before anyone started sending escape sequences at it; resets and default colours go back to the latter.  Colours outside the basic 16 are squashed to whichever of them looks closest in `palette`, which should match the console's colour table.
+
+The console has no bold, so bold and bright foregrounds share the foreground intensity bit.  Nor does it have concealed text: that's done by making the foreground match the background.  Only text concealed by these same attributes can be revealed again; to carry that across calls, use `apply_sgr_with_conceal`.  Attributes with no console equivalent are ignored.
+*/
+pub fn apply_sgr_with_palette(current: u16, original: u16, attrs: &[SgrAttr], palette: &Palette) -> u16 {
+    apply_sgr_with_conceal(current, original, &mut None, attrs, palette)
+}
+
+/**
+Apply SGR attributes to console text attributes, keeping track of concealed text.
+
+`concealed` holds the foreground that's hidden while text is concealed, and is `None` otherwise.  Foreground changes made while concealed go to it rather than to the visible attributes, and revealing puts it back.
+
+See `apply_sgr_with_palette` for the rest.
+*/
+pub fn apply_sgr_with_conceal(current: u16, original: u16, concealed: &mut Option<u16>, attrs: &[SgrAttr], palette: &Palette) -> u16 {
+    use sgr::SgrAttr::*;
+
+    fn conceal(a: u16) -> u16 {
+        (a & !FOREGROUND_ALL) | (((a & BACKGROUND_ALL) >> BACKGROUND_SHIFT) << FOREGROUND_SHIFT)
+    }
+
+    let mut a = current;
+    for &attr in attrs {
+        a = match (attr, *concealed) {
+            (Conceal, None) => {
+                *concealed = Some(a & FOREGROUND_ALL);
+                conceal(a)
+            },
+            (Conceal, Some(_)) => a,
+            (Reveal, Some(fg)) => {
+                *concealed = None;
+                (a & !FOREGROUND_ALL) | fg
+            },
+            (Reveal, None) => a,
+            (Reset, _) => {
+                *concealed = None;
+                apply_one(a, original, attr, palette)
+            },
+            (_, Some(fg)) => {
+                let a = apply_one((a & !FOREGROUND_ALL) | fg, original, attr, palette);
+                *concealed = Some(a & FOREGROUND_ALL);
+                conceal(a)
+            },
+            (_, None) => apply_one(a, original, attr, palette),
+        };
+    }
+    a
+}
+
+/// Apply a single attribute, other than concealing and revealing.
+fn apply_one(a: u16, original: u16, attr: SgrAttr, palette: &Palette) -> u16 {
+    use sgr::SgrAttr::*;
+
+    match attr {
+        Reset => (a & !SGR_ALL) | (original & SGR_ALL),
+        Bold => a | FOREGROUND_INTENSITY,
+        Faint | NormalIntensity => a & !FOREGROUND_INTENSITY,
+        Underline(UnderlineStyle::None) => a & !COMMON_LVB_UNDERSCORE,
+        Underline(_) => a | COMMON_LVB_UNDERSCORE,
+        Reverse => a | COMMON_LVB_REVERSE_VIDEO,
+        NotReversed => a & !COMMON_LVB_REVERSE_VIDEO,
+        Foreground(Color::Default) => (a & !FOREGROUND_ALL) | (original & FOREGROUND_ALL),
+        Background(Color::Default) => (a & !BACKGROUND_ALL) | (original & BACKGROUND_ALL),
+        Foreground(c) => match palette.reduce(c, ColorDepth::Ansi16) {
+            // Leave intensity alone, so that bold still works on the basic colours.
+            Color::Indexed(n @ 0...7) => (a & !FOREGROUND_WHITE) | (split_bits(n) << FOREGROUND_SHIFT),
+            Color::Indexed(n) => (a & !FOREGROUND_ALL) | (split_bits(n - 8) << FOREGROUND_SHIFT) | FOREGROUND_INTENSITY,
+            _ => a
+        },
+        Background(c) => match palette.reduce(c, ColorDepth::Ansi16) {
+            Color::Indexed(n @ 0...7) => (a & !BACKGROUND_ALL) | (split_bits(n) << BACKGROUND_SHIFT),
+            Color::Indexed(n) => (a & !BACKGROUND_ALL) | (split_bits(n - 8) << BACKGROUND_SHIFT) | BACKGROUND_INTENSITY,
+            _ => a
+        },
+        _ => a
+    }
+}
+
+/**
+Work out the `Style` that console text attributes stand for; the reverse of `apply_sgr`.
+
+Colours that match `original` come back as `Color::Default`, and the rest as one of the basic 16, with the intensity bits picking the bright versions.  Reverse video and underscore carry over; everything else in the attributes is ignored.
+*/
+pub fn attrs_to_style(attrs: u16, original: u16) -> Style {
+    fn color(attrs: u16, original: u16, all: u16, intensity: u16, shift: usize) -> Color {
+        if attrs & all == original & all {
+            Color::Default
+        } else {
+            let bright = if attrs & intensity != 0 { 8 } else { 0 };
+            Color::Indexed(join_bits((attrs >> shift) & FOREGROUND_WHITE) + bright)
+        }
+    }
+
+    Style {
+        fg: color(attrs, original, FOREGROUND_ALL, FOREGROUND_INTENSITY, FOREGROUND_SHIFT),
+        bg: color(attrs, original, BACKGROUND_ALL, BACKGROUND_INTENSITY, BACKGROUND_SHIFT),
+        underline: if attrs & COMMON_LVB_UNDERSCORE != 0 { UnderlineStyle::Single } else { UnderlineStyle::None },
+        reverse: attrs & COMMON_LVB_REVERSE_VIDEO != 0,
+        ..Style::default()
+    }
+}
+
+#[test]
+fn test_apply_sgr() {
+    use sgr::SgrAttr::*;
+    use sgr::Color::*;
+
+    const FR: u16 = super::FOREGROUND_RED;
+    const FG: u16 = super::FOREGROUND_GREEN;
+    const FB: u16 = super::FOREGROUND_BLUE;
+    const FI: u16 = FOREGROUND_INTENSITY;
+    const FW: u16 = FOREGROUND_WHITE;
+    const BR: u16 = super::BACKGROUND_RED;
+    const BB: u16 = super::BACKGROUND_BLUE;
+    const BI: u16 = BACKGROUND_INTENSITY;
+    const BW: u16 = BACKGROUND_WHITE;
+    const REV: u16 = COMMON_LVB_REVERSE_VIDEO;
+    const UND: u16 = COMMON_LVB_UNDERSCORE;
+    // Light grey on blue, the way some people like their consoles.
+    const ORIG: u16 = FW | BB;
+
+    let table: &[(u16, &[SgrAttr], u16)] = &[
+        (ORIG, &[], ORIG),
+        (ORIG, &[Bold], ORIG | FI),
+        (ORIG | FI, &[NormalIntensity], ORIG),
+        (ORIG | FI, &[Faint], ORIG),
+        (ORIG, &[Foreground(Indexed(1))], FR | BB),
+        (ORIG | FI, &[Foreground(Indexed(1))], FR | FI | BB),
+        (ORIG, &[Foreground(Indexed(9))], FR | FI | BB),
+        (ORIG, &[Foreground(Indexed(6))], FG | FB | BB),
+        (ORIG, &[Background(Indexed(3))], FW | BR | super::BACKGROUND_GREEN),
+        (ORIG, &[Background(Indexed(12))], FW | BB | BI),
+        (FW | BB | BI, &[Background(Indexed(1))], FW | BR),
+        (ORIG, &[Foreground(Indexed(196))], FR | FI | BB),
+        (ORIG, &[Foreground(Rgb(0, 0x90, 0))], FG | BB),
+        (FR | FI | BR, &[Foreground(Default)], FW | BR),
+        (FR | FI | BR, &[Background(Default)], FR | FI | BB),
+        (FR | FI | BR | REV | UND, &[Reset], ORIG),
+        (ORIG, &[Underline(UnderlineStyle::Single)], ORIG | UND),
+        (ORIG, &[Underline(UnderlineStyle::Curly)], ORIG | UND),
+        (ORIG | UND, &[Underline(UnderlineStyle::None)], ORIG),
+        (ORIG, &[Reverse], ORIG | REV),
+        (ORIG | REV, &[NotReversed], ORIG),
+        (FR | BW | BI, &[Conceal], FW | FI | BW | BI),
+        (FR | BB, &[Conceal], FB | BB),
+        (ORIG, &[Foreground(Indexed(1)), Conceal, Reveal], FR | BB),
+        (FR | BB, &[Conceal, Foreground(Indexed(2)), Bold, Reveal], FG | FI | BB),
+        (FR | BB, &[Conceal, Background(Indexed(1))], FR | BR),
+        (FR | BB, &[Conceal, Reset, Reveal], ORIG),
+        // Nothing was concealed, so there's nothing to reveal.
+        (FB | BB, &[Reveal], FB | BB),
+        (ORIG, &[Italic, CrossedOut, Overlined, Unknown(26)], ORIG),
+        (ORIG, &[Bold, Foreground(Indexed(2)), Background(Indexed(0)), Reverse], FG | FI | REV),
+        // Grid and DBCS bits aren't SGR's business.
+        (ORIG | 0x0400, &[Reset], ORIG | 0x0400),
+    ];
+
+    for &(current, attrs, expected) in table {
+        let got = apply_sgr(current, ORIG, attrs);
+        assert!(got == expected, "{:?} on {:#06x}: expected {:#06x}, got {:#06x}", attrs, current, expected, got);
+    }
+}
+
+#[test]
+fn test_apply_sgr_with_conceal() {
+    use sgr::SgrAttr::*;
+    use sgr::Color::*;
+
+    const ORIG: u16 = FOREGROUND_WHITE | super::BACKGROUND_BLUE;
+    const RED: u16 = super::FOREGROUND_RED | super::BACKGROUND_BLUE;
+
+    // ESC[31;8m ... ESC[28m comes back red.
+    let palette = Palette::default();
+    let mut concealed = None;
+    let a = apply_sgr_with_conceal(ORIG, ORIG, &mut concealed, &[Foreground(Indexed(1)), Conceal], &palette);
+    assert_eq!(a, super::FOREGROUND_BLUE | super::BACKGROUND_BLUE);
+    assert_eq!(concealed, Some(super::FOREGROUND_RED));
+    assert_eq!(apply_sgr_with_conceal(a, ORIG, &mut concealed, &[Reveal], &palette), RED);
+    assert_eq!(concealed, None);
+}
+
+#[test]
+fn test_attrs_to_style() {
+    use sgr::Color::*;
+
+    const ORIG: u16 = FOREGROUND_WHITE | super::BACKGROUND_BLUE;
+
+    let plain = Style::default();
+    assert_eq!(attrs_to_style(ORIG, ORIG), plain);
+    assert_eq!(attrs_to_style(ORIG | FOREGROUND_INTENSITY, ORIG), Style { fg: Indexed(15), ..plain });
+    assert_eq!(attrs_to_style(super::FOREGROUND_RED | super::BACKGROUND_BLUE, ORIG), Style { fg: Indexed(1), ..plain });
+    assert_eq!(attrs_to_style(FOREGROUND_WHITE | super::BACKGROUND_GREEN | BACKGROUND_INTENSITY, ORIG), Style { bg: Indexed(10), ..plain });
+    assert_eq!(attrs_to_style(ORIG | COMMON_LVB_REVERSE_VIDEO | COMMON_LVB_UNDERSCORE | 0x0400, ORIG),
+        Style { reverse: true, underline: UnderlineStyle::Single, ..plain });
+
+    // Going there and back again gets the same attributes.
+    for attrs in 0..0x100 {
+        let style = attrs_to_style(attrs, ORIG);
+        let sgr = [SgrAttr::Foreground(style.fg), SgrAttr::Background(style.bg)];
+        assert_eq!(apply_sgr(ORIG, ORIG, &sgr), attrs);
+    }
+}
@@ -0,0 +1,111 @@
+/*!
+The console operations `ConsoleInterpreter` needs, so that it can drive something other than a real Windows console.
+*/
+use std::io;
+
+/// A cell position in a console screen buffer, like Win32's `COORD`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, Default)]
+pub struct Coord {
+    pub x: i16,
+    pub y: i16,
+}
+
+impl Coord {
+    pub fn new(x: i16, y: i16) -> Self {
+        Coord {
+            x: x,
+            y: y,
+        }
+    }
+}
+
+/// An inclusive rectangle of cells, like Win32's `SMALL_RECT`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, Default)]
+pub struct Rect {
+    pub left: i16,
+    pub top: i16,
+    pub right: i16,
+    pub bottom: i16,
+}
+
+impl Rect {
+    pub fn new(left: i16, top: i16, right: i16, bottom: i16) -> Self {
+        Rect {
+            left: left,
+            top: top,
+            right: right,
+            bottom: bottom,
+        }
+    }
+
+    pub fn width(&self) -> i16 {
+        self.right - self.left + 1
+    }
+
+    pub fn height(&self) -> i16 {
+        self.bottom - self.top + 1
+    }
+}
+
+/// The state of a screen buffer, like Win32's `CONSOLE_SCREEN_BUFFER_INFO`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct ScreenBufferInfo {
+    /// Size of the whole buffer, scrollback included.
+    pub size: Coord,
+    /// Cursor position within the buffer.
+    pub cursor: Coord,
+    /// Text attributes new output is written with.
+    pub attrs: u16,
+    /// The part of the buffer currently visible.
+    pub window: Rect,
+    /// The largest the window could be, given the buffer size and the screen.
+    pub max_window: Coord,
+}
+
+/// The cursor's appearance, like Win32's `CONSOLE_CURSOR_INFO`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct CursorInfo {
+    /// Percentage of the cell the cursor fills, from 1 to 100.
+    pub size: u32,
+    pub visible: bool,
+}
+
+/**
+A console screen buffer.
+
+The methods are modelled on the Win32 console functions of similar name, and behave the same way; in particular, positions are relative to the whole buffer rather than the window.
+*/
+pub trait ConsoleBackend {
+    /// Write text at the cursor, using the current attributes.
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize>;
+    fn flush(&mut self) -> io::Result<()>;
+
+    fn screen_buffer_info(&self) -> io::Result<ScreenBufferInfo>;
+    fn set_cursor_position(&mut self, pos: Coord) -> io::Result<()>;
+    fn set_text_attribute(&mut self, attrs: u16) -> io::Result<()>;
+
+    /// Set the attributes of `len` cells starting at `start`, continuing onto following rows; returns how many cells were changed.
+    fn fill_attribute(&mut self, attrs: u16, len: u32, start: Coord) -> io::Result<u32>;
+    /// Like `fill_attribute`, but for the characters.
+    fn fill_character(&mut self, ch: char, len: u32, start: Coord) -> io::Result<u32>;
+
+    fn cursor_info(&self) -> io::Result<CursorInfo>;
+    fn set_cursor_info(&mut self, info: &CursorInfo) -> io::Result<()>;
+
+    /// The output mode flags, as in `GetConsoleMode`; see `ENABLE_WRAP_AT_EOL_OUTPUT`.
+    fn mode(&self) -> io::Result<u32>;
+    fn set_mode(&mut self, mode: u32) -> io::Result<()>;
+
+    /// Move or resize the window; it has to stay inside the buffer.
+    fn set_window(&mut self, window: Rect) -> io::Result<()>;
+    /// Resize the buffer; it can't be made smaller than the window.
+    fn set_buffer_size(&mut self, size: Coord) -> io::Result<()>;
+    /// The largest the window could be with a big enough buffer, as in `GetLargestConsoleWindowSize`.
+    fn largest_window(&self) -> io::Result<Coord>;
+
+    /// The size of a character cell in pixels, as in `GetCurrentConsoleFont`.
+    fn font_size(&self) -> io::Result<Coord>;
+
+    fn title(&self) -> io::Result<String>;
+    fn set_title(&mut self, title: &str) -> io::Result<()>;
+}
@@ -0,0 +1,594 @@
+/*!
+Turning escape sequences into console operations.
+*/
+use std::cmp::{max, min};
+use std::io::{self, Write};
+use ansi::{AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use palette::Palette;
+use query::{ModeState, Query, Responder};
+use sgr::{SgrAttr, SgrAttrs};
+use style::{StyleMask, MAX_PUSHED_STYLES};
+use window::{WindowOp, WindowReport};
+use conv::{ConvUtil, UnwrapOrSaturate};
+use super::{ConsoleBackend, Coord, Rect, ScreenBufferInfo};
+use super::{
+    apply_sgr_with_conceal, apply_sgr_with_palette, COLOR_ALL, COMMON_LVB_REVERSE_VIDEO, COMMON_LVB_UNDERSCORE,
+    ENABLE_WRAP_AT_EOL_OUTPUT, FOREGROUND_INTENSITY, FOREGROUND_WHITE, BACKGROUND_INTENSITY, BACKGROUND_WHITE,
+};
+
+/**
+Interprets escape sequences by driving a console screen buffer, the way a Windows console without VT support would need.
+
+Replies to queries are written to `stdin`, which should be whatever the application reads its input from.
+
+Most terminals have a fixed-size screen, with lines pushed off the top going into scrollback.  A Windows console instead has a large buffer with a window that slides over it, and the user can scroll that window around at any time.  So that positions mean the same thing however the window has been scrolled, the interpreter keeps track of its own "screen": a run of rows in the buffer as tall as the window, which only moves down when output runs off the bottom of it.  Cursor positioning, erasing and cursor position reports all work relative to that.
+*/
+pub struct ConsoleInterpreter<WIn, B>
+where WIn: Write, B: ConsoleBackend {
+    responder: Responder<WIn>,
+    console: B,
+    /// The text attributes the console had before we started, which resets go back to.
+    original: u16,
+    /// The rows of the buffer that are acting as the terminal's screen.
+    view: Rect,
+    palette: Palette,
+    /// The foreground hidden by concealed text, to put back when it's revealed.
+    concealed: Option<u16>,
+    scp: SavedCursor,
+    decsc: SavedCursor,
+    sgr_stack: Vec<(u16, Option<u16>, StyleMask)>,
+    show_unknown: bool,
+}
+
+/// A saved cursor position, along with the text attributes in effect at the time.
+#[derive(Copy, Clone)]
+struct SavedCursor {
+    pos: Coord,
+    attrs: u16,
+    concealed: Option<u16>,
+}
+
+impl<WIn, B> ConsoleInterpreter<WIn, B>
+where WIn: Write, B: ConsoleBackend {
+    pub fn new(stdin: WIn, console: B) -> Self {
+        let info = console.screen_buffer_info().ok();
+        let original = info
+            .map(|info| info.attrs & COLOR_ALL)
+            .unwrap_or(FOREGROUND_WHITE);
+        let view = info
+            .map(|info| Rect::new(0, info.window.top, info.size.x - 1, info.window.bottom))
+            .unwrap_or(Rect::new(0, 0, 79, 24));
+        ConsoleInterpreter {
+            responder: Responder::new(stdin),
+            console: console,
+            original: original,
+            view: view,
+            palette: Palette::default(),
+            concealed: None,
+            scp: SavedCursor {
+                pos: Coord::new(0, 0),
+                attrs: original,
+                concealed: None,
+            },
+            decsc: SavedCursor {
+                pos: Coord::new(0, 0),
+                attrs: original,
+                concealed: None,
+            },
+            sgr_stack: vec![],
+            show_unknown: true,
+        }
+    }
+
+    /**
+    Set the palette used to pick which of the console's 16 colours is closest to a 256-colour or RGB colour.  This should match the console's actual colour table.
+    */
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /**
+    Set whether sequences the interpreter doesn't understand are written to the console as `[UNK:...]`, which is the default.  Turn this off to quietly drop them instead.
+    */
+    pub fn set_show_unknown(&mut self, show: bool) {
+        self.show_unknown = show;
+    }
+
+    pub fn get_ref(&self) -> &B {
+        &self.console
+    }
+
+    pub fn get_mut(&mut self) -> &mut B {
+        &mut self.console
+    }
+
+    pub fn into_inner(self) -> B {
+        self.console
+    }
+
+    fn save_cursor(&self) -> io::Result<SavedCursor> {
+        let info = try!(self.console.screen_buffer_info());
+        Ok(SavedCursor {
+            pos: info.cursor,
+            attrs: info.attrs,
+            concealed: self.concealed,
+        })
+    }
+
+    fn restore_cursor(&mut self, saved: SavedCursor) -> Result<(), GenError> {
+        try!(self.flush());
+        try!(self.mut_text_attrs(|attrs| {
+            let bits = COLOR_ALL | COMMON_LVB_REVERSE_VIDEO | COMMON_LVB_UNDERSCORE;
+            *attrs = (*attrs & !bits) | (saved.attrs & bits);
+        }));
+        try!(self.console.set_cursor_position(saved.pos));
+        self.concealed = saved.concealed;
+        Ok(())
+    }
+
+    /**
+    Get the state of the buffer, and where the screen is in it.
+
+    The screen follows the cursor down if output has gone past the bottom, and is kept within the buffer if it has shrunk.
+    */
+    fn track_view(&mut self) -> io::Result<(ScreenBufferInfo, Rect)> {
+        let csbi = try!(self.console.screen_buffer_info());
+        let rows = max(1, min(self.view.height(), csbi.size.y));
+        let mut top = self.view.top;
+        if csbi.cursor.y >= top + rows {
+            top = csbi.cursor.y - rows + 1;
+        }
+        let top = max(0, min(top, csbi.size.y - rows));
+        self.view = Rect::new(0, top, csbi.size.x - 1, top + rows - 1);
+        Ok((csbi, self.view))
+    }
+
+    fn mut_text_attrs<F, R>(&mut self, f: F) -> Result<R, io::Error>
+    where F: FnOnce(&mut u16) -> R {
+        let info = try!(self.console.screen_buffer_info());
+        let mut attrs = info.attrs;
+        let r = f(&mut attrs);
+        try!(self.console.set_text_attribute(attrs));
+        Ok(r)
+    }
+
+    /// Blank `len` cells from `start` with the current attributes.
+    fn erase(&mut self, csbi: &ScreenBufferInfo, start: Coord, len: u32) -> io::Result<()> {
+        try!(self.console.fill_attribute(csbi.attrs, len, start));
+        try!(self.console.fill_character(' ', len, start));
+        Ok(())
+    }
+}
+
+impl<WIn, B> AnsiInterpret for ConsoleInterpreter<WIn, B>
+where WIn: Write, B: ConsoleBackend {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.console.write_text(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.console.flush()
+    }
+
+    fn cuu_seq(&mut self, r: u16) -> Result<(), GenError> {
+        if r == 0 { return Ok(()); }
+
+        let (csbi, view) = try!(self.track_view());
+
+        let abs_y = csbi.cursor.y;
+        let abs_x = csbi.cursor.x;
+
+        let abs_y = max(view.top, abs_y.saturating_sub(r.value_as::<i16>().unwrap_or_saturate()));
+
+        try!(self.console.set_cursor_position(Coord::new(abs_x, abs_y)));
+        Ok(())
+    }
+
+    fn cud_seq(&mut self, r: u16) -> Result<(), GenError> {
+        if r == 0 { return Ok(()); }
+
+        let (csbi, view) = try!(self.track_view());
+
+        let abs_y = csbi.cursor.y;
+        let abs_x = csbi.cursor.x;
+
+        let abs_y = min(view.bottom, abs_y.saturating_add(r.value_as::<i16>().unwrap_or_saturate()));
+
+        try!(self.console.set_cursor_position(Coord::new(abs_x, abs_y)));
+        Ok(())
+    }
+
+    fn cuf_seq(&mut self, c: u16) -> Result<(), GenError> {
+        if c == 0 { return Ok(()); }
+
+        let csbi = try!(self.console.screen_buffer_info());
+
+        let abs_y = csbi.cursor.y;
+        let abs_x = csbi.cursor.x;
+
+        let abs_x = min(csbi.size.x - 1, abs_x.saturating_add(c.value_as::<i16>().unwrap_or_saturate()));
+
+        try!(self.console.set_cursor_position(Coord::new(abs_x, abs_y)));
+        Ok(())
+    }
+
+    fn cub_seq(&mut self, c: u16) -> Result<(), GenError> {
+        if c == 0 { return Ok(()); }
+
+        let csbi = try!(self.console.screen_buffer_info());
+
+        let abs_y = csbi.cursor.y;
+        let abs_x = csbi.cursor.x;
+
+        let abs_x = max(0, abs_x.saturating_sub(c.value_as::<i16>().unwrap_or_saturate()));
+
+        try!(self.console.set_cursor_position(Coord::new(abs_x, abs_y)));
+        Ok(())
+    }
+
+    fn cup_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
+        let x = c.saturating_sub(1);
+        let y = r.saturating_sub(1);
+
+        let (_, view) = try!(self.track_view());
+
+        let x = min(x, view.width().value_as::<u16>().unwrap_or_saturate() - 1);
+        let y = min(y, view.height().value_as::<u16>().unwrap_or_saturate() - 1);
+
+        let abs_x = x + view.left.value_as::<u16>().unwrap_or_saturate();
+        let abs_y = y + view.top.value_as::<u16>().unwrap_or_saturate();
+
+        let abs_pos = Coord {
+            x: abs_x.value_as::<i16>().unwrap_or_saturate(),
+            y: abs_y.value_as::<i16>().unwrap_or_saturate(),
+        };
+
+        try!(self.console.set_cursor_position(abs_pos));
+        Ok(())
+    }
+
+    fn ed_seq(&mut self, n: EraseDisplay) -> Result<(), GenError> {
+        use ansi::EraseDisplay::*;
+        let (csbi, view) = try!(self.track_view());
+
+        let width = view.width().value_as::<u32>().unwrap_or_saturate();
+        let (start, len) = match n {
+            TopToCursor => {
+                let start = Coord {
+                    x: 0,
+                    y: view.top,
+                };
+                let lines = (csbi.cursor.y - start.y).value_as::<u32>().unwrap_or_saturate();
+                let cols = (csbi.cursor.x + 1).value_as::<u32>().unwrap_or_saturate();
+                (start, lines * width + cols)
+            },
+            CursorToBottom => {
+                let start = csbi.cursor;
+                let lines = (view.bottom - start.y).value_as::<u32>().unwrap_or_saturate();
+                let cols = (csbi.size.x - start.x).value_as::<u32>().unwrap_or_saturate();
+                (start, lines * width + cols)
+            },
+            All => {
+                let start = Coord {
+                    x: 0,
+                    y: view.top,
+                };
+                let lines = view.height().value_as::<u32>().unwrap_or_saturate();
+                (start, lines * width)
+            },
+        };
+
+        try!(self.erase(&csbi, start, len));
+        Ok(())
+    }
+
+    fn el_seq(&mut self, n: EraseLine) -> Result<(), GenError> {
+        use ansi::EraseLine::*;
+        let csbi = try!(self.console.screen_buffer_info());
+
+        let (start, len) = match n {
+            StartToCursor => {
+                let start = Coord {
+                    x: 0,
+                    y: csbi.cursor.y,
+                };
+                let cols = csbi.cursor.x + 1;
+                let cols = cols.value_as::<u32>().unwrap_or_saturate();
+                (start, cols)
+            },
+            CursorToEnd => {
+                let start = csbi.cursor;
+                let cols = csbi.size.x - csbi.cursor.x;
+                let cols = cols.value_as::<u32>().unwrap_or_saturate();
+                (start, cols)
+            },
+            All => {
+                let start = Coord {
+                    x: 0,
+                    y: csbi.cursor.y,
+                };
+                let cols = csbi.size.x;
+                let cols = cols.value_as::<u32>().unwrap_or_saturate();
+                (start, cols)
+            },
+        };
+
+        try!(self.erase(&csbi, start, len));
+        Ok(())
+    }
+
+    fn sgr_attrs(&mut self, sgr: SgrAttrs) -> Result<(), GenError> {
+        let sgr: Vec<_> = sgr.collect();
+        try!(self.flush());
+        let (original, palette) = (self.original, self.palette);
+        let mut concealed = self.concealed;
+        try!(self.mut_text_attrs(|attrs| {
+            *attrs = apply_sgr_with_conceal(*attrs, original, &mut concealed, &sgr, &palette);
+        }));
+        self.concealed = concealed;
+        Ok(())
+    }
+
+    fn dsr_seq(&mut self) -> Result<(), GenError> {
+        let (csbi, view) = try!(self.track_view());
+
+        let abs_pos = csbi.cursor;
+
+        let rel_x = (abs_pos.x - view.left).value_as::<u16>().unwrap_or_saturate() + 1;
+        let rel_y = (abs_pos.y - view.top).value_as::<u16>().unwrap_or_saturate() + 1;
+
+        try!(self.responder.cursor_position(rel_y, rel_x));
+        Ok(())
+    }
+
+    fn scp_seq(&mut self) -> Result<(), GenError> {
+        self.scp = try!(self.save_cursor());
+        Ok(())
+    }
+
+    fn rcp_seq(&mut self) -> Result<(), GenError> {
+        let saved = self.scp;
+        self.restore_cursor(saved)
+    }
+
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        self.decsc = try!(self.save_cursor());
+        Ok(())
+    }
+
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        let saved = self.decsc;
+        self.restore_cursor(saved)
+    }
+
+    fn xtpushsgr_seq(&mut self, mask: StyleMask) -> Result<(), GenError> {
+        if self.sgr_stack.len() < MAX_PUSHED_STYLES {
+            let info = try!(self.console.screen_buffer_info());
+            self.sgr_stack.push((info.attrs, self.concealed, mask));
+        }
+        Ok(())
+    }
+
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> {
+        let (saved, concealed, mask) = match self.sgr_stack.pop() {
+            Some(v) => v,
+            None => return Ok(())
+        };
+
+        // The console only has colours, and uses foreground intensity for bold.
+        let mut bits = 0;
+        if mask.fg { bits |= FOREGROUND_WHITE; }
+        if mask.fg || mask.bold { bits |= FOREGROUND_INTENSITY; }
+        if mask.bg { bits |= BACKGROUND_WHITE | BACKGROUND_INTENSITY; }
+
+        try!(self.flush());
+        try!(self.mut_text_attrs(|attrs| {
+            *attrs = (*attrs & !bits) | (saved & bits);
+        }));
+        if mask.fg {
+            self.concealed = concealed;
+        }
+        Ok(())
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        try!(self.flush());
+        let original = self.original;
+        try!(self.mut_text_attrs(|attrs| {
+            *attrs = apply_sgr_with_palette(*attrs, original, &[SgrAttr::Reset], &Palette::default());
+        }));
+        self.concealed = None;
+        try!(self.ed_seq(EraseDisplay::All));
+        try!(self.cup_seq(1, 1));
+
+        self.scp = try!(self.save_cursor());
+        self.decsc = self.scp;
+        self.sgr_stack.clear();
+        Ok(())
+    }
+
+    fn set_cursor_style(&mut self, shape: CursorShape, _blinking: bool) -> Result<(), GenError> {
+        use ansi::CursorShape::*;
+
+        // The console cursor always blinks, and can only vary in how much of the cell it fills.  A thin underline is the closest we can get to a bar.
+        let size = match shape {
+            Block => 100,
+            Underline => 25,
+            Bar => 10,
+        };
+
+        let mut info = try!(self.console.cursor_info());
+        info.size = size;
+        try!(self.console.set_cursor_info(&info));
+        Ok(())
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), GenError> {
+        let mut info = try!(self.console.cursor_info());
+        info.visible = visible;
+        try!(self.console.set_cursor_info(&info));
+        Ok(())
+    }
+
+    fn set_line_wrap(&mut self, wrap: bool) -> Result<(), GenError> {
+        let mode = try!(self.console.mode());
+        let mode = if wrap {
+            mode | ENABLE_WRAP_AT_EOL_OUTPUT
+        } else {
+            mode & !ENABLE_WRAP_AT_EOL_OUTPUT
+        };
+        try!(self.console.set_mode(mode));
+        Ok(())
+    }
+
+    fn xtwinops_seq(&mut self, op: WindowOp) -> Result<(), GenError> {
+        use window::WindowOp::*;
+        let (csbi, view) = try!(self.track_view());
+        let win = csbi.window;
+
+        match op {
+            ResizeChars { rows, cols } => {
+                try!(self.flush());
+
+                // Nothing can be bigger than the screen, and checking that first stops a silly size from making a silly buffer.
+                let max_size = csbi.max_window;
+                let largest = try!(self.console.largest_window());
+                let pick = |n: Option<u16>, cur: i16, max: i16, largest: i16| match n {
+                    None => cur,
+                    Some(0) => max,
+                    Some(n) => min(n.value_as::<i16>().unwrap_or_saturate(), largest),
+                };
+                let cols = pick(cols, win.width(), max_size.x, largest.x);
+                let rows = pick(rows, view.height(), max_size.y, largest.y);
+                self.view = try!(resize_console(&mut self.console, &csbi, view, rows, cols));
+                Ok(())
+            },
+            ResizeLines(rows) => {
+                try!(self.flush());
+
+                let cols = win.width();
+                let largest = try!(self.console.largest_window());
+                let rows = min(rows.value_as::<i16>().unwrap_or_saturate(), largest.y);
+                self.view = try!(resize_console(&mut self.console, &csbi, view, rows, cols));
+                Ok(())
+            },
+            ReportTextAreaSize => {
+                let rows = view.height().value_as::<u16>().unwrap_or_saturate();
+                let cols = win.width().value_as::<u16>().unwrap_or_saturate();
+                try!(self.responder.window(&WindowReport::TextAreaSize { rows: rows, cols: cols }));
+                Ok(())
+            },
+            ReportScreenSize => {
+                let rows = csbi.max_window.y.value_as::<u16>().unwrap_or_saturate();
+                let cols = csbi.max_window.x.value_as::<u16>().unwrap_or_saturate();
+                try!(self.responder.window(&WindowReport::ScreenSize { rows: rows, cols: cols }));
+                Ok(())
+            },
+            ReportCellSize => {
+                let size = try!(self.console.font_size());
+                let height = size.y.value_as::<u16>().unwrap_or_saturate();
+                let width = size.x.value_as::<u16>().unwrap_or_saturate();
+                try!(self.responder.window(&WindowReport::CellSize { height: height, width: width }));
+                Ok(())
+            },
+            ReportTitle => {
+                let title = try!(self.console.title());
+                try!(self.responder.window(&WindowReport::Title(title)));
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn query_seq(&mut self, q: Query) -> Result<(), GenError> {
+        match q {
+            Query::CursorPosition => self.dsr_seq(),
+            Query::Status => rethrow!(self.responder.status(true)),
+            // Answer the same way conhost's own VT support does: a VT100 with no options.
+            Query::PrimaryDeviceAttributes => rethrow!(self.responder.primary_attributes(1, &[0])),
+            Query::SecondaryDeviceAttributes => rethrow!(self.responder.secondary_attributes(0, 0, 0)),
+            Query::TertiaryDeviceAttributes => rethrow!(self.responder.tertiary_attributes(0)),
+            Query::Mode { private: true, mode: 25 } => {
+                let info = try!(self.console.cursor_info());
+                let state = if info.visible { ModeState::Set } else { ModeState::Reset };
+                rethrow!(self.responder.mode(true, 25, state))
+            },
+            Query::Mode { private: true, mode: 7 } => {
+                let mode = try!(self.console.mode());
+                let state = if mode & ENABLE_WRAP_AT_EOL_OUTPUT != 0 { ModeState::Set } else { ModeState::Reset };
+                rethrow!(self.responder.mode(true, 7, state))
+            },
+            Query::Mode { private, mode } => rethrow!(self.responder.mode(private, mode, ModeState::NotRecognized)),
+            Query::Setting(_) => rethrow!(self.responder.setting(None)),
+            Query::Version => rethrow!(self.responder.version(concat!("ansi-interpreter(", env!("CARGO_PKG_VERSION"), ")"))),
+            Query::Capabilities(names) => {
+                for name in names {
+                    try!(self.responder.capability(&name, None));
+                }
+                Ok(())
+            },
+        }
+    }
+
+    fn osc_txt_seq(&mut self, n: u16, txt: &str) -> Result<(), GenError> {
+        match n {
+            0 | 2 => rethrow!(self.console.set_title(txt)),
+            _ => Ok(())
+        }
+    }
+
+    fn hvp_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
+        self.cup_seq(r, c)
+    }
+
+    fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
+        if !self.show_unknown {
+            return Ok(());
+        }
+        let mut bs = String::from("[UNK:");
+        for b in bytes {
+            use std::fmt::Write;
+            write!(bs, "{:02x}", b).unwrap();
+        }
+        bs.push(']');
+        try!(self.console.write_text(bs.as_bytes()));
+        Ok(())
+    }
+}
+
+/**
+Resize the console window to `rows` by `cols`, growing the buffer if it can't hold the new window, and return the new screen.
+
+The window is moved to the top of the current screen.  The buffer keeps its width in step with the window so that lines wrap where the application expects.
+*/
+fn resize_console<B>(console: &mut B, csbi: &ScreenBufferInfo, view: Rect, rows: i16, cols: i16) -> io::Result<Rect>
+where B: ConsoleBackend {
+    let rows = max(1, rows);
+    let cols = max(1, cols);
+    let win = csbi.window;
+
+    let size = Coord {
+        x: cols,
+        y: max(csbi.size.y, rows),
+    };
+
+    // The window has to fit inside the buffer at every step, so shrink it to fit both the old and new buffers first.
+    let top = min(view.top, size.y - rows);
+    let interim = Rect {
+        left: 0,
+        top: top,
+        right: min(win.width(), cols) - 1,
+        bottom: top + min(win.height(), rows) - 1,
+    };
+    let target = Rect {
+        left: 0,
+        top: top,
+        right: cols - 1,
+        bottom: top + rows - 1,
+    };
+
+    try!(console.set_window(interim));
+    try!(console.set_buffer_size(size));
+    try!(console.set_window(target));
+    Ok(target)
+}
@@ -0,0 +1,41 @@
+/*!
+Windows console text attributes, and the parts of driving a console that don't actually need Windows.
+
+`ConsoleInterpreter` does the translation from escape sequences to console operations against any `ConsoleBackend`: the real thing lives in the `win32` module, and `SimulatedConsole` is an in-memory stand-in for testing.
+
+The attribute bits and mode flags are the same as the Win32 `FOREGROUND_*`, `BACKGROUND_*`, `COMMON_LVB_*` and `ENABLE_*` constants; they're repeated here so that the mapping can be used and tested anywhere.
+*/
+pub use self::attrs::{apply_sgr, apply_sgr_with_conceal, apply_sgr_with_palette, attrs_to_style};
+pub use self::backend::{ConsoleBackend, Coord, CursorInfo, Rect, ScreenBufferInfo};
+pub use self::interp::ConsoleInterpreter;
+pub use self::render::render_cells;
+pub use self::sim::SimulatedConsole;
+
+mod attrs;
+mod backend;
+mod interp;
+mod render;
+mod sim;
+
+pub const FOREGROUND_BLUE: u16 = 0x0001;
+pub const FOREGROUND_GREEN: u16 = 0x0002;
+pub const FOREGROUND_RED: u16 = 0x0004;
+pub const FOREGROUND_INTENSITY: u16 = 0x0008;
+pub const FOREGROUND_WHITE: u16 = FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_BLUE;
+pub const FOREGROUND_SHIFT: usize = 0;
+
+pub const BACKGROUND_BLUE: u16 = 0x0010;
+pub const BACKGROUND_GREEN: u16 = 0x0020;
+pub const BACKGROUND_RED: u16 = 0x0040;
+pub const BACKGROUND_INTENSITY: u16 = 0x0080;
+pub const BACKGROUND_WHITE: u16 = BACKGROUND_RED | BACKGROUND_GREEN | BACKGROUND_BLUE;
+pub const BACKGROUND_SHIFT: usize = 4;
+
+pub const COMMON_LVB_TRAILING_BYTE: u16 = 0x0200;
+pub const COMMON_LVB_REVERSE_VIDEO: u16 = 0x4000;
+pub const COMMON_LVB_UNDERSCORE: u16 = 0x8000;
+
+pub const COLOR_ALL: u16 = FOREGROUND_WHITE | FOREGROUND_INTENSITY | BACKGROUND_WHITE | BACKGROUND_INTENSITY;
+
+pub const ENABLE_PROCESSED_OUTPUT: u32 = 0x0001;
+pub const ENABLE_WRAP_AT_EOL_OUTPUT: u32 = 0x0002;
@@ -0,0 +1,111 @@
+/*!
+Turning the contents of a console screen buffer back into escape sequences.
+*/
+use sgr::ColorDepth;
+use style::Style;
+use super::{attrs_to_style, COMMON_LVB_TRAILING_BYTE};
+
+/**
+Render a rectangle of console cells, `width` to a row, as text and escape sequences that draw the same thing on a terminal.
+
+This is for taking screenshots of console programs: the cells are the characters and attributes read out of a screen buffer, like `CHAR_INFO`, and `original` is the console's default attributes, which are drawn in the terminal's default colours.  Attributes go through `attrs_to_style`, so the output only uses the basic 16 colours.
+
+The output starts by clearing the screen and homing the cursor, and finishes with the attributes reset.  Blank cells that would look the same as cleared ones are skipped over with cursor movement rather than drawn.  Control characters are drawn as spaces, and the second half of a double-width character, marked with `COMMON_LVB_TRAILING_BYTE`, is left to the first half.
+*/
+pub fn render_cells(cells: &[(char, u16)], width: usize, original: u16) -> Vec<u8> {
+    let mut out = String::from("\x1b[H\x1b[2J");
+    if width == 0 {
+        return out.into_bytes();
+    }
+
+    let mut pen = Style::default();
+    for (y, row) in cells.chunks(width).enumerate() {
+        let cells: Vec<(char, Style, bool)> = row.iter()
+            .map(|&(ch, attrs)| {
+                let ch = if ch.is_control() { ' ' } else { ch };
+                let style = attrs_to_style(attrs, original);
+                (ch, style, attrs & COMMON_LVB_TRAILING_BYTE == 0 && is_blank(ch, &style))
+            })
+            .collect();
+
+        let mut x = match cells.iter().position(|&(_, _, blank)| !blank) {
+            Some(x) => x,
+            None => continue
+        };
+        let end = cells.iter().rposition(|&(_, _, blank)| !blank).unwrap() + 1;
+
+        if x == 0 {
+            out.push_str(&format!("\x1b[{}H", y + 1));
+        } else {
+            out.push_str(&format!("\x1b[{};{}H", y + 1, x + 1));
+        }
+
+        while x < end {
+            let skip = cells[x..end].iter().take_while(|&&(_, _, blank)| blank).count();
+            let jump = format!("\x1b[{}C", skip);
+            if jump.len() < skip {
+                out.push_str(&jump);
+                x += skip;
+                continue;
+            }
+
+            let (ch, mut style, blank) = cells[x];
+            if blank {
+                // No need to change colour just for a space.
+                style.fg = pen.fg;
+            }
+            if row[x].1 & COMMON_LVB_TRAILING_BYTE == 0 {
+                out.push_str(&pen.diff(&style, ColorDepth::Ansi16).to_string());
+                pen = style;
+                out.push(ch);
+            }
+            x += 1;
+        }
+    }
+
+    if pen != Style::default() {
+        out.push_str("\x1b[0m");
+    }
+    out.into_bytes()
+}
+
+/// Whether a cell looks just like one that's been cleared.
+fn is_blank(ch: char, style: &Style) -> bool {
+    ch == ' ' && Style { fg: style.fg, ..Style::default() } == *style
+}
+
+#[test]
+fn test_render_cells() {
+    use super::{BACKGROUND_RED, COMMON_LVB_REVERSE_VIDEO, FOREGROUND_INTENSITY, FOREGROUND_RED, FOREGROUND_WHITE};
+
+    fn render(rows: &[(&str, &[u16])], width: usize) -> String {
+        let mut cells = vec![];
+        for &(text, attrs) in rows {
+            let mut row: Vec<(char, u16)> = text.chars().zip(attrs.iter().cloned().chain(Some(FOREGROUND_WHITE).into_iter().cycle())).collect();
+            row.resize(width, (' ', FOREGROUND_WHITE));
+            cells.extend(row);
+        }
+        String::from_utf8(render_cells(&cells, width, FOREGROUND_WHITE)).unwrap()
+    }
+
+    const W: u16 = FOREGROUND_WHITE;
+    const R: u16 = FOREGROUND_RED;
+    const BR: u16 = FOREGROUND_RED | FOREGROUND_INTENSITY;
+    const ON_R: u16 = FOREGROUND_WHITE | BACKGROUND_RED;
+    const REV: u16 = FOREGROUND_WHITE | COMMON_LVB_REVERSE_VIDEO;
+
+    assert_eq!(render(&[], 10), "\x1b[H\x1b[2J");
+    assert_eq!(render(&[("", &[]), ("", &[])], 10), "\x1b[H\x1b[2J");
+    assert_eq!(render(&[("hello", &[])], 10), "\x1b[H\x1b[2J\x1b[1Hhello");
+    assert_eq!(render(&[("", &[]), ("  hi", &[])], 10), "\x1b[H\x1b[2J\x1b[2;3Hhi");
+    // Short gaps are cheaper to draw than to jump.
+    assert_eq!(render(&[("a   b", &[])], 10), "\x1b[H\x1b[2J\x1b[1Ha   b");
+    assert_eq!(render(&[("a     b", &[])], 10), "\x1b[H\x1b[2J\x1b[1Ha\x1b[5Cb");
+    // Coloured spaces show, but spaces in a different foreground don't.
+    assert_eq!(render(&[("ab c", &[R, R, ON_R, R])], 10), "\x1b[H\x1b[2J\x1b[1H\x1b[31mab\x1b[0;41m \x1b[0;31mc\x1b[0m");
+    assert_eq!(render(&[("a b", &[W, BR, W])], 10), "\x1b[H\x1b[2J\x1b[1Ha b");
+    assert_eq!(render(&[("x", &[REV]), ("y", &[BR])], 10), "\x1b[H\x1b[2J\x1b[1H\x1b[7mx\x1b[2H\x1b[0;91my\x1b[0m");
+    // Control characters are blanks, and double-width characters are only drawn once.
+    assert_eq!(render(&[("a\0b", &[])], 10), "\x1b[H\x1b[2J\x1b[1Ha b");
+    assert_eq!(render(&[("\u{4e2d}\u{4e2d}!", &[W, W | COMMON_LVB_TRAILING_BYTE])], 10), "\x1b[H\x1b[2J\x1b[1H\u{4e2d}!");
+}
@@ -0,0 +1,358 @@
+/*!
+An in-memory console screen buffer.
+*/
+use std::cmp::{max, min};
+use std::io;
+use std::str;
+use super::{ConsoleBackend, Coord, CursorInfo, Rect, ScreenBufferInfo};
+use super::{ENABLE_PROCESSED_OUTPUT, ENABLE_WRAP_AT_EOL_OUTPUT, FOREGROUND_WHITE};
+
+/**
+A console screen buffer that lives entirely in memory, for running `ConsoleInterpreter` somewhere other than Windows.
+
+It behaves like a Windows console with processed output: carriage return, line feed, backspace and tab move the cursor, text wraps at the end of a line if `ENABLE_WRAP_AT_EOL_OUTPUT` is set, and output past the bottom of the buffer scrolls the oldest line away.  Whenever the cursor moves, the window follows it.
+*/
+#[derive(Clone, Debug)]
+pub struct SimulatedConsole {
+    size: Coord,
+    window: Rect,
+    screen: Coord,
+    cells: Vec<(char, u16)>,
+    cursor: Coord,
+    attrs: u16,
+    cursor_info: CursorInfo,
+    mode: u32,
+    title: String,
+    font_size: Coord,
+    /// The start of a UTF-8 sequence split across writes.
+    partial: Vec<u8>,
+}
+
+impl SimulatedConsole {
+    /**
+    Create a console with a `cols` by `rows` window over a buffer with `buffer_rows` rows, much like a freshly opened Windows console.
+
+    The window starts at the top of the buffer, and is as big as the screen lets it get; use `set_max_window` to allow it to grow.
+    */
+    pub fn new(cols: i16, rows: i16, buffer_rows: i16) -> Self {
+        let cols = max(1, cols);
+        let rows = max(1, rows);
+        let buffer_rows = max(rows, buffer_rows);
+        SimulatedConsole {
+            size: Coord::new(cols, buffer_rows),
+            window: Rect::new(0, 0, cols - 1, rows - 1),
+            screen: Coord::new(cols, rows),
+            cells: vec![(' ', FOREGROUND_WHITE); cols as usize * buffer_rows as usize],
+            cursor: Coord::new(0, 0),
+            attrs: FOREGROUND_WHITE,
+            cursor_info: CursorInfo {
+                size: 25,
+                visible: true,
+            },
+            mode: ENABLE_PROCESSED_OUTPUT | ENABLE_WRAP_AT_EOL_OUTPUT,
+            title: String::new(),
+            font_size: Coord::new(8, 16),
+            partial: vec![],
+        }
+    }
+
+    /// Set the largest the window is allowed to be, as if the console were on a screen of that size.
+    pub fn set_max_window(&mut self, size: Coord) {
+        self.screen = size;
+    }
+
+    /// Set the size of a character cell in pixels, which is 8 by 16 to begin with.
+    pub fn set_font_size(&mut self, size: Coord) {
+        self.font_size = size;
+    }
+
+    pub fn size(&self) -> Coord {
+        self.size
+    }
+
+    pub fn window(&self) -> Rect {
+        self.window
+    }
+
+    pub fn cursor(&self) -> Coord {
+        self.cursor
+    }
+
+    pub fn attrs(&self) -> u16 {
+        self.attrs
+    }
+
+    /// The character and attributes at a position in the buffer.
+    pub fn cell(&self, pos: Coord) -> Option<(char, u16)> {
+        self.index(pos).map(|i| self.cells[i])
+    }
+
+    /// One row of the buffer as text, without trailing blanks.
+    pub fn row_text(&self, y: i16) -> String {
+        if y < 0 || y >= self.size.y {
+            return String::new();
+        }
+        let start = y as usize * self.size.x as usize;
+        let row = &self.cells[start..start + self.size.x as usize];
+        let text: String = row.iter().map(|&(ch, _)| ch).collect();
+        text.trim_end().to_owned()
+    }
+
+    /// The rows in the window as text, without trailing blanks.
+    pub fn window_text(&self) -> Vec<String> {
+        (self.window.top..self.window.bottom + 1).map(|y| self.row_text(y)).collect()
+    }
+
+    fn index(&self, pos: Coord) -> Option<usize> {
+        if pos.x < 0 || pos.y < 0 || pos.x >= self.size.x || pos.y >= self.size.y {
+            None
+        } else {
+            Some(pos.y as usize * self.size.x as usize + pos.x as usize)
+        }
+    }
+
+    /// Scroll the window just far enough to show the cursor.
+    fn show_cursor(&mut self) {
+        let height = self.window.height();
+        if self.cursor.y > self.window.bottom {
+            self.window.bottom = self.cursor.y;
+            self.window.top = self.cursor.y - height + 1;
+        } else if self.cursor.y < self.window.top {
+            self.window.top = self.cursor.y;
+            self.window.bottom = self.cursor.y + height - 1;
+        }
+    }
+
+    fn line_feed(&mut self) {
+        self.cursor.x = 0;
+        if self.cursor.y + 1 < self.size.y {
+            self.cursor.y += 1;
+        } else {
+            // Out of buffer; the oldest line falls off the top.
+            let width = self.size.x as usize;
+            self.cells.drain(..width);
+            let blank = (' ', self.attrs);
+            self.cells.extend((0..width).map(|_| blank));
+        }
+        self.show_cursor();
+    }
+
+    fn put_char(&mut self, ch: char) {
+        match ch {
+            '\r' => self.cursor.x = 0,
+            '\n' => self.line_feed(),
+            '\x08' => self.cursor.x = max(0, self.cursor.x - 1),
+            '\t' => self.cursor.x = min(self.size.x - 1, (self.cursor.x / 8 + 1) * 8),
+            '\x07' => (),
+            ch => {
+                if let Some(i) = self.index(self.cursor) {
+                    self.cells[i] = (ch, self.attrs);
+                }
+                if self.cursor.x + 1 < self.size.x {
+                    self.cursor.x += 1;
+                } else if self.mode & ENABLE_WRAP_AT_EOL_OUTPUT != 0 {
+                    self.line_feed();
+                }
+            }
+        }
+        self.show_cursor();
+    }
+
+    fn fill<F>(&mut self, len: u32, start: Coord, mut f: F) -> io::Result<u32>
+    where F: FnMut(&mut (char, u16)) {
+        let start = match self.index(start) {
+            Some(i) => i,
+            None => return Err(invalid("fill starts outside the buffer"))
+        };
+        let end = min(self.cells.len(), start + len as usize);
+        for cell in &mut self.cells[start..end] {
+            f(cell);
+        }
+        Ok((end - start) as u32)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl ConsoleBackend for SimulatedConsole {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = ::std::mem::take(&mut self.partial);
+        bytes.extend_from_slice(buf);
+
+        let mut rest = &bytes[..];
+        loop {
+            match str::from_utf8(rest) {
+                Ok(s) => {
+                    for ch in s.chars() {
+                        self.put_char(ch);
+                    }
+                    break;
+                },
+                Err(err) => {
+                    let (good, bad) = rest.split_at(err.valid_up_to());
+                    for ch in unsafe { str::from_utf8_unchecked(good) }.chars() {
+                        self.put_char(ch);
+                    }
+                    match err.error_len() {
+                        Some(n) => {
+                            self.put_char('\u{fffd}');
+                            rest = &bad[n..];
+                        },
+                        None => {
+                            // Cut off part-way through; wait for the rest.
+                            self.partial = bad.to_owned();
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn screen_buffer_info(&self) -> io::Result<ScreenBufferInfo> {
+        Ok(ScreenBufferInfo {
+            size: self.size,
+            cursor: self.cursor,
+            attrs: self.attrs,
+            window: self.window,
+            max_window: Coord::new(min(self.size.x, self.screen.x), min(self.size.y, self.screen.y)),
+        })
+    }
+
+    fn set_cursor_position(&mut self, pos: Coord) -> io::Result<()> {
+        if self.index(pos).is_none() {
+            return Err(invalid("cursor position outside the buffer"));
+        }
+        self.cursor = pos;
+        self.show_cursor();
+        Ok(())
+    }
+
+    fn set_text_attribute(&mut self, attrs: u16) -> io::Result<()> {
+        self.attrs = attrs;
+        Ok(())
+    }
+
+    fn fill_attribute(&mut self, attrs: u16, len: u32, start: Coord) -> io::Result<u32> {
+        self.fill(len, start, |cell| cell.1 = attrs)
+    }
+
+    fn fill_character(&mut self, ch: char, len: u32, start: Coord) -> io::Result<u32> {
+        self.fill(len, start, |cell| cell.0 = ch)
+    }
+
+    fn cursor_info(&self) -> io::Result<CursorInfo> {
+        Ok(self.cursor_info)
+    }
+
+    fn set_cursor_info(&mut self, info: &CursorInfo) -> io::Result<()> {
+        if info.size < 1 || info.size > 100 {
+            return Err(invalid("cursor size out of range"));
+        }
+        self.cursor_info = *info;
+        Ok(())
+    }
+
+    fn mode(&self) -> io::Result<u32> {
+        Ok(self.mode)
+    }
+
+    fn set_mode(&mut self, mode: u32) -> io::Result<()> {
+        self.mode = mode;
+        Ok(())
+    }
+
+    fn set_window(&mut self, window: Rect) -> io::Result<()> {
+        if window.left < 0 || window.top < 0
+            || window.right < window.left || window.bottom < window.top
+            || window.right >= self.size.x || window.bottom >= self.size.y
+        {
+            return Err(invalid("window doesn't fit in the buffer"));
+        }
+        if window.width() > self.screen.x || window.height() > self.screen.y {
+            return Err(invalid("window doesn't fit on the screen"));
+        }
+        self.window = window;
+        Ok(())
+    }
+
+    fn set_buffer_size(&mut self, size: Coord) -> io::Result<()> {
+        if size.x <= self.window.right || size.y <= self.window.bottom {
+            return Err(invalid("buffer can't be smaller than the window"));
+        }
+        let blank = (' ', self.attrs);
+        let mut cells = vec![blank; size.x as usize * size.y as usize];
+        for y in 0..min(size.y, self.size.y) {
+            for x in 0..min(size.x, self.size.x) {
+                cells[y as usize * size.x as usize + x as usize] = self.cells[y as usize * self.size.x as usize + x as usize];
+            }
+        }
+        self.cells = cells;
+        self.size = size;
+        self.cursor = Coord::new(min(self.cursor.x, size.x - 1), min(self.cursor.y, size.y - 1));
+        Ok(())
+    }
+
+    fn largest_window(&self) -> io::Result<Coord> {
+        Ok(self.screen)
+    }
+
+    fn font_size(&self) -> io::Result<Coord> {
+        Ok(self.font_size)
+    }
+
+    fn title(&self) -> io::Result<String> {
+        Ok(self.title.clone())
+    }
+
+    fn set_title(&mut self, title: &str) -> io::Result<()> {
+        self.title = title.to_owned();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_simulated_console() {
+    let mut con = SimulatedConsole::new(10, 3, 4);
+    con.write_text(b"ab\tc\r\nline two!!\xe2\x82").unwrap();
+    con.write_text(b"\xac!\n\xffx").unwrap();
+    assert_eq!(con.row_text(0), "ab      c");
+    // Wrapped straight after the last column.
+    assert_eq!(con.row_text(1), "line two!!");
+    assert_eq!(con.row_text(2), "\u{20ac}!");
+    assert_eq!(con.row_text(3), "\u{fffd}x");
+    assert_eq!(con.cursor(), Coord::new(2, 3));
+    assert_eq!(con.window(), Rect::new(0, 1, 9, 3));
+
+    // Off the bottom of the buffer.
+    con.write_text(b"\nend").unwrap();
+    assert_eq!(con.row_text(0), "line two!!");
+    assert_eq!(con.row_text(3), "end");
+
+    con.set_mode(ENABLE_PROCESSED_OUTPUT).unwrap();
+    con.write_text(b"\rabcdefghijkl").unwrap();
+    assert_eq!(con.row_text(3), "abcdefghil");
+
+    assert_eq!(con.fill_character('-', 100, Coord::new(6, 2)).unwrap(), 14);
+    assert_eq!(con.row_text(2), "\u{fffd}x    ----");
+    assert_eq!(con.row_text(3), "----------");
+    assert!(con.fill_character('-', 1, Coord::new(10, 0)).is_err());
+
+    assert!(con.set_window(Rect::new(0, 0, 9, 3)).is_err());
+    con.set_max_window(Coord::new(80, 25));
+    con.set_window(Rect::new(0, 0, 9, 3)).unwrap();
+    assert!(con.set_buffer_size(Coord::new(10, 3)).is_err());
+    assert!(con.set_buffer_size(Coord::new(4, 6)).is_err());
+    con.set_window(Rect::new(0, 0, 3, 3)).unwrap();
+    con.set_buffer_size(Coord::new(4, 6)).unwrap();
+    assert_eq!(con.row_text(0), "line");
+    assert_eq!(con.row_text(5), "");
+    assert_eq!(con.cursor(), Coord::new(3, 3));
+}
@@ -0,0 +1,186 @@
+/*!
+Keeping track of an interception of the standard streams, so that it can be undone.
+*/
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{self, Receiver, Sender, TryIter};
+use std::thread::{self, JoinHandle};
+
+/**
+A live interception of the standard streams, as returned by `intercept_stdio`.
+
+Output is passed through the interpreter by background threads until the handle is dropped or `join`ed, at which point the original streams are put back.  Use `detach` to leave the interception in place for the rest of the process's life.
+
+Errors hit by the background threads end that thread, and are reported through `errors` and `join` rather than panicking.
+*/
+pub struct InterceptHandle {
+    restore: Option<Box<FnMut() -> io::Result<()> + Send>>,
+    threads: Vec<JoinHandle<()>>,
+    errors: Receiver<io::Error>,
+    errors_tx: Sender<io::Error>,
+}
+
+impl InterceptHandle {
+    /**
+    Create a handle with nothing to restore and no threads.
+
+    Platform implementations add their forwarding threads with `pump`, and say how to undo the redirection with `on_restore`.
+    */
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        InterceptHandle {
+            restore: None,
+            threads: vec![],
+            errors: rx,
+            errors_tx: tx,
+        }
+    }
+
+    /**
+    Start a thread copying everything from `src` to `dst` until `src` runs dry or `dst` stops accepting input.
+
+    If `join` is false, the thread is left to finish on its own rather than waited for; this is for sources that can't be interrupted, such as the console.
+    */
+    pub fn pump<R, W>(&mut self, name: &str, src: R, dst: W, join: bool) -> io::Result<()>
+    where R: 'static + Read + Send, W: 'static + Write + Send {
+        let errors = self.errors_tx.clone();
+        let thread = try!(thread::Builder::new()
+            .name(String::from(name))
+            .spawn(move || {
+                if let Err(err) = pump(src, dst) {
+                    let _ = errors.send(err);
+                }
+            }));
+        if join {
+            self.threads.push(thread);
+        }
+        Ok(())
+    }
+
+    /// Set what to do to put the original streams back.  This will be called at most once.
+    pub fn on_restore<F>(&mut self, f: F)
+    where F: 'static + FnMut() -> io::Result<()> + Send {
+        self.restore = Some(Box::new(f));
+    }
+
+    /// Errors reported by the forwarding threads since the last time this was called.
+    pub fn errors(&self) -> TryIter<'_, io::Error> {
+        self.errors.try_iter()
+    }
+
+    /**
+    Flush anything the standard library is holding on to out to the interpreter.
+
+    This doesn't wait for the interpreter to catch up; for that, use `join`.
+    */
+    pub fn flush(&self) -> io::Result<()> {
+        try!(io::stdout().flush());
+        io::stderr().flush()
+    }
+
+    /**
+    Put the original streams back.  Output already written will still go through the interpreter, but nothing written from now on will.
+    */
+    pub fn restore(&mut self) -> io::Result<()> {
+        match self.restore.take() {
+            Some(mut restore) => {
+                try!(self.flush());
+                restore()
+            },
+            None => Ok(())
+        }
+    }
+
+    /**
+    Put the original streams back and wait for all intercepted output to be dealt with.
+
+    Returns the first error the forwarding threads ran into that hasn't already been picked up from `errors`.
+    */
+    pub fn join(mut self) -> io::Result<()> {
+        try!(self.restore());
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                throw!(io::Error::new(io::ErrorKind::Other, "intercept thread panicked"));
+            }
+        }
+        match self.errors.try_recv() {
+            Ok(err) => Err(err),
+            Err(_) => Ok(())
+        }
+    }
+
+    /// Leave the interception in place for as long as the process runs.
+    pub fn detach(mut self) {
+        self.restore = None;
+        self.threads.clear();
+    }
+}
+
+impl Default for InterceptHandle {
+    fn default() -> Self {
+        InterceptHandle::new()
+    }
+}
+
+impl Drop for InterceptHandle {
+    fn drop(&mut self) {
+        let _ = self.restore();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Copy everything from `src` to `dst`.  Either end going away is a normal way for this to finish.
+fn pump<R, W>(mut src: R, mut dst: W) -> io::Result<()>
+where R: Read, W: Write {
+    let mut buf = [0; 4096];
+    loop {
+        let bytes = match src.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(bytes) => bytes,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(err) => return Err(err)
+        };
+
+        match dst.write_all(&buf[..bytes]).and_then(|_| dst.flush()) {
+            Ok(()) => (),
+            Err(ref err) if err.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            Err(err) => return Err(err)
+        }
+    }
+}
+
+#[test]
+fn test_intercept_handle() {
+    use std::sync::{Arc, Mutex};
+
+    struct Failing;
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "nope"))
+        }
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    let restored = Arc::new(Mutex::new(0));
+    let mut handle = InterceptHandle::new();
+    handle.pump("test.ok", &b"hello"[..], io::sink(), true).unwrap();
+    handle.pump("test.fail", &b"hello"[..], Failing, true).unwrap();
+    handle.on_restore({ let restored = restored.clone(); move || {
+        *restored.lock().unwrap() += 1;
+        Ok(())
+    } });
+    handle.restore().unwrap();
+    handle.restore().unwrap();
+    assert_eq!(*restored.lock().unwrap(), 1);
+    assert_eq!(handle.join().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+    let mut handle = InterceptHandle::new();
+    handle.on_restore({ let restored = restored.clone(); move || {
+        *restored.lock().unwrap() += 1;
+        Ok(())
+    } });
+    drop(handle);
+    assert_eq!(*restored.lock().unwrap(), 2);
+}
@@ -7,17 +7,60 @@ pub use export::*;
 #[macro_use] mod macros;
 
 mod ansi;
+mod ansisys;
+mod auto;
+#[cfg(unix)]
+mod capture;
+mod charset;
+mod console;
+mod intercept;
+mod multi;
+mod palette;
+mod query;
+mod sgr;
+mod style;
+mod terminfo;
+mod theme;
 mod util;
+mod window;
+mod writer;
 
+#[cfg(unix)]
+mod unix;
 #[cfg(windows)]
 mod win32;
 
 mod export {
-    pub use ansi::{AnsiIntercept, EraseDisplay, EraseLine, AnsiInterpret};
+    pub use ansi::{AnsiIntercept, CursorShape, EraseDisplay, EraseLine, AnsiInterpret};
+    pub use ansisys::{AnsiSys, ScreenMode, UnknownScreenMode};
+    pub use auto::{AutoStream, IsTty, StreamMode};
+    pub use charset::{Charset, CharsetSlot, CharsetTranslator};
+    pub use console::{
+        apply_sgr, apply_sgr_with_conceal, apply_sgr_with_palette, attrs_to_style, render_cells,
+        ConsoleBackend, ConsoleInterpreter, Coord, CursorInfo, Rect, ScreenBufferInfo, SimulatedConsole,
+    };
+    pub use intercept::InterceptHandle;
+    pub use multi::{InterpGuard, MultiSourceIntercept, SourceIntercept, SourceInterpret};
+    pub use palette::{ColorReducer, Palette};
+    pub use query::{ModeState, Query, Responder};
+    pub use sgr::{Color, ColorDepth, SgrAttr, SgrAttrs, UnderlineStyle};
+    pub use style::{Style, StyleDiff, StyledInterpret, StyleMask, StyleTracker};
+    pub use terminfo::{tparm, InvalidCapability, InvalidTerminfo, Terminfo, TerminfoWriter};
+    pub use theme::{InvalidTheme, Theme};
+    pub use window::{UnknownWindowOp, WindowOp, WindowReport};
+    pub use writer::AnsiWriter;
 
+    #[cfg(unix)]
+    pub use capture::{capture_stdio, Captured, Event};
+    #[cfg(unix)]
+    pub use unix::{intercept_fds, intercept_stdio, intercept_stdio_with, InputWriter};
+    #[cfg(target_os = "linux")]
+    pub use unix::Session;
     #[cfg(windows)]
-    pub use win32::intercept_stdio;
+    pub use win32::{intercept_stdio, Win32Console};
 
-    #[cfg(not(windows))]
-    pub fn intercept_stdio() {}
+    #[cfg(not(any(unix, windows)))]
+    pub fn intercept_stdio() -> ::std::io::Result<InterceptHandle> {
+        Ok(InterceptHandle::new())
+    }
 }
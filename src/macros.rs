@@ -51,3 +51,18 @@ macro_rules! throw {
         return ::std::result::Result::Err(::std::convert::From::from($e))
     };
 }
+
+/**
+Implements the listed `AnsiInterpret` sequence methods by forwarding them, unchanged, to the interpreter in the named field.
+
+This is for interpreters that wrap another and only want to intercept a handful of methods.
+*/
+macro_rules! forward_seqs {
+    ($inner:ident; $(fn $name:ident(&mut self $(, $arg:ident: $ty:ty)*);)*) => {
+        $(
+            fn $name(&mut self $(, $arg: $ty)*) -> ::std::result::Result<(), ::ansi::GenError> {
+                self.$inner.$name($($arg),*)
+            }
+        )*
+    };
+}
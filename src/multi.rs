@@ -0,0 +1,143 @@
+/*!
+Feeding several streams, such as standard output and standard error, into one interpreter.
+*/
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use ansi::{AnsiInterpret, GenError, PartialSeq};
+
+/**
+An interpreter that wants to know which stream each event came from.
+*/
+pub trait SourceInterpret: AnsiInterpret {
+    /**
+    Called when events start arriving from a different source; everything up to the next call comes from `source`.
+    */
+    fn set_source(&mut self, source: usize) -> Result<(), GenError>;
+}
+
+/**
+Shares one interpreter between several streams of bytes, each with its own parser.
+
+Simply writing every stream into the same `AnsiIntercept` mixes up their escape sequences: half a sequence from one stream followed by some bytes from another turns into garbage.  Instead, each stream gets its own `SourceIntercept` from `source`, which holds on to its own partial sequences and only passes complete events on.  Sources are numbered however the caller likes; file descriptor numbers are an obvious choice.
+
+Each write to a source is handled in one go, so events from different sources are never interleaved any more finely than that.
+*/
+pub struct MultiSourceIntercept<I>
+where I: AnsiInterpret {
+    shared: Arc<Mutex<Shared<I>>>,
+}
+
+/// Tells an interpreter about a change of source.
+type TagFn<I> = fn(&mut I, usize) -> Result<(), GenError>;
+
+struct Shared<I>
+where I: AnsiInterpret {
+    interp: I,
+    /// Where the last events came from.
+    current: Option<usize>,
+    /// How to tell the interpreter about a change of source, if it wants to know.
+    tag: Option<TagFn<I>>,
+}
+
+impl<I> MultiSourceIntercept<I>
+where I: AnsiInterpret {
+    pub fn new(interp: I) -> Self {
+        MultiSourceIntercept::with_tag(interp, None)
+    }
+
+    fn with_tag(interp: I, tag: Option<TagFn<I>>) -> Self {
+        MultiSourceIntercept {
+            shared: Arc::new(Mutex::new(Shared {
+                interp: interp,
+                current: None,
+                tag: tag,
+            })),
+        }
+    }
+
+    /// Create a new stream feeding the interpreter.
+    pub fn source(&self, source: usize) -> SourceIntercept<I> {
+        SourceIntercept {
+            buffer: PartialSeq::new(),
+            source: source,
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Get at the interpreter, holding off all the sources until done.
+    pub fn lock(&self) -> InterpGuard<'_, I> {
+        InterpGuard(self.shared.lock().unwrap())
+    }
+
+    /// Get the interpreter back, provided all the sources have been dropped.
+    pub fn into_inner(self) -> Result<I, Self> {
+        match Arc::try_unwrap(self.shared) {
+            Ok(shared) => Ok(shared.into_inner().unwrap().interp),
+            Err(shared) => Err(MultiSourceIntercept { shared: shared })
+        }
+    }
+}
+
+impl<I> MultiSourceIntercept<I>
+where I: SourceInterpret {
+    /// Like `new`, but the interpreter is told which source events come from.
+    pub fn tagged(interp: I) -> Self {
+        MultiSourceIntercept::with_tag(interp, Some(I::set_source))
+    }
+}
+
+/// Access to the interpreter inside a `MultiSourceIntercept`.
+pub struct InterpGuard<'a, I>(MutexGuard<'a, Shared<I>>)
+where I: 'a + AnsiInterpret;
+
+impl<'a, I> ::std::ops::Deref for InterpGuard<'a, I>
+where I: 'a + AnsiInterpret {
+    type Target = I;
+    fn deref(&self) -> &I {
+        &self.0.interp
+    }
+}
+
+impl<'a, I> ::std::ops::DerefMut for InterpGuard<'a, I>
+where I: 'a + AnsiInterpret {
+    fn deref_mut(&mut self) -> &mut I {
+        &mut self.0.interp
+    }
+}
+
+/**
+One of the streams feeding a `MultiSourceIntercept`.
+*/
+pub struct SourceIntercept<I>
+where I: AnsiInterpret {
+    buffer: PartialSeq,
+    source: usize,
+    shared: Arc<Mutex<Shared<I>>>,
+}
+
+impl<I> SourceIntercept<I>
+where I: AnsiInterpret {
+    pub fn source(&self) -> usize {
+        self.source
+    }
+}
+
+impl<I> Write for SourceIntercept<I>
+where I: AnsiInterpret {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut shared = self.shared.lock().unwrap();
+        let shared = &mut *shared;
+        if shared.current != Some(self.source) {
+            if let Some(tag) = shared.tag {
+                try!(tag(&mut shared.interp, self.source)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err)));
+            }
+            shared.current = Some(self.source);
+        }
+        self.buffer.write(&mut shared.interp, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.shared.lock().unwrap().interp.flush()
+    }
+}
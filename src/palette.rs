@@ -0,0 +1,291 @@
+/*!
+Colour palettes, and squashing colours down to what an output can actually show.
+*/
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::io;
+use ansisys::ScreenMode;
+use ansi::{AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use charset::{Charset, CharsetSlot};
+use query::Query;
+use sgr::{self, Color, ColorDepth, SgrAttr, SgrAttrs};
+use style::StyleMask;
+use window::WindowOp;
+
+/**
+The RGB values of the 16 basic colours, in SGR order.
+
+Everything above 15 is the fixed xterm colour cube and greyscale ramp, so that part doesn't need storing.
+*/
+#[derive(Copy, Clone, Debug)]
+pub struct Palette {
+    colors: [(u8, u8, u8); 16],
+    /// The Oklab values of `colors`, so `reduce` doesn't have to keep working them out.
+    labs: [[f64; 3]; 16],
+}
+
+thread_local! {
+    /// The Oklab values of the colour cube and greyscale ramp, which are the same for every palette.
+    static FIXED_LABS: Vec<[f64; 3]> = (16..256).map(|n| oklab(Palette::fixed_rgb(n as u8))).collect();
+
+    static DEFAULT: Palette = Palette::default();
+}
+
+impl Palette {
+    pub fn new(colors: [(u8, u8, u8); 16]) -> Self {
+        let mut labs = [[0.0; 3]; 16];
+        for (lab, &rgb) in labs.iter_mut().zip(colors.iter()) {
+            *lab = oklab(rgb);
+        }
+        Palette {
+            colors: colors,
+            labs: labs,
+        }
+    }
+
+    /**
+    Run `f` with the default palette, without building a new one every time.
+    */
+    pub fn with_default<F, T>(f: F) -> T
+    where F: FnOnce(&Palette) -> T {
+        DEFAULT.with(f)
+    }
+
+    pub fn colors(&self) -> &[(u8, u8, u8); 16] {
+        &self.colors
+    }
+
+    /**
+    Work out the RGB value of a colour.
+
+    Returns `None` for `Default`, since only the terminal knows what that is.
+    */
+    pub fn rgb(&self, c: Color) -> Option<(u8, u8, u8)> {
+        match c {
+            Color::Default => None,
+            Color::Indexed(n) if n < 16 => Some(self.colors[n as usize]),
+            Color::Indexed(n) => Some(Palette::fixed_rgb(n)),
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+        }
+    }
+
+    /// The RGB value of one of the colours above 15.
+    fn fixed_rgb(n: u8) -> (u8, u8, u8) {
+        if n < 232 {
+            // The xterm 6x6x6 colour cube.
+            let level = |v: u8| if v == 0 { 0 } else { 55 + 40 * v };
+            let n = n - 16;
+            (level(n / 36), level((n / 6) % 6), level(n % 6))
+        } else {
+            // The greyscale ramp.
+            let v = 8 + 10 * (n - 232);
+            (v, v, v)
+        }
+    }
+
+    /**
+    Squash a colour down to the one that looks closest to it out of those an output with the given depth can show.
+    */
+    pub fn reduce(&self, c: Color, depth: ColorDepth) -> Color {
+        let candidates = match (c, depth) {
+            (_, ColorDepth::Monochrome) => return Color::Default,
+            (_, ColorDepth::TrueColor) => return c,
+            (Color::Default, _) => return c,
+            (Color::Indexed(n), ColorDepth::Ansi8) if n < 8 => return c,
+            (Color::Indexed(n), ColorDepth::Ansi16) if n < 16 => return c,
+            (Color::Indexed(_), ColorDepth::Indexed256) => return c,
+            (_, ColorDepth::Ansi8) => 0..8,
+            (_, ColorDepth::Ansi16) => 0..16,
+            // The basic colours are often redefined, so only pick them when asked to.
+            (_, ColorDepth::Indexed256) => 16..256,
+        };
+
+        let target = oklab(self.rgb(c).unwrap_or((0, 0, 0)));
+        let nearest = FIXED_LABS.with(|fixed| {
+            let dist = |n: u16| {
+                let lab = if n < 16 { self.labs[n as usize] } else { fixed[n as usize - 16] };
+                let d = |i: usize| (target[i] - lab[i]) * (target[i] - lab[i]);
+                d(0) + d(1) + d(2)
+            };
+            candidates
+                .map(|n| (n, dist(n)))
+                .min_by(|&(_, a), &(_, b)| a.partial_cmp(&b).unwrap_or(Ordering::Equal))
+                .map(|(n, _)| n)
+                .unwrap_or(7)
+        });
+        Color::Indexed(nearest as u8)
+    }
+}
+
+impl PartialEq for Palette {
+    fn eq(&self, other: &Palette) -> bool {
+        self.colors == other.colors
+    }
+}
+
+impl Eq for Palette {}
+
+impl Hash for Palette {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.colors.hash(state)
+    }
+}
+
+impl Default for Palette {
+    /**
+    The Windows console's classic colours, which are also a fair match for most terminals' defaults.
+    */
+    fn default() -> Self {
+        Palette::new([
+            (0x00, 0x00, 0x00), (0x80, 0x00, 0x00), (0x00, 0x80, 0x00), (0x80, 0x80, 0x00),
+            (0x00, 0x00, 0x80), (0x80, 0x00, 0x80), (0x00, 0x80, 0x80), (0xc0, 0xc0, 0xc0),
+            (0x80, 0x80, 0x80), (0xff, 0x00, 0x00), (0x00, 0xff, 0x00), (0xff, 0xff, 0x00),
+            (0x00, 0x00, 0xff), (0xff, 0x00, 0xff), (0x00, 0xff, 0xff), (0xff, 0xff, 0xff),
+        ])
+    }
+}
+
+/**
+Convert an sRGB colour to Oklab, where straight-line distance is a decent match for how different two colours look.
+*/
+fn oklab((r, g, b): (u8, u8, u8)) -> [f64; 3] {
+    fn linear(c: u8) -> f64 {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    }
+
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+#[test]
+fn test_palette_reduce() {
+    use sgr::Color::*;
+    use sgr::ColorDepth::*;
+
+    let p = Palette::default();
+    assert_eq!(p.reduce(Indexed(3), Ansi16), Indexed(3));
+    assert_eq!(p.reduce(Indexed(196), Ansi16), Indexed(9));
+    assert_eq!(p.reduce(Indexed(16), Ansi16), Indexed(0));
+    assert_eq!(p.reduce(Indexed(255), Ansi16), Indexed(15));
+    assert_eq!(p.reduce(Rgb(0, 0x90, 0), Ansi16), Indexed(2));
+    assert_eq!(p.reduce(Default, Ansi16), Default);
+    assert_eq!(p.reduce(Indexed(9), Ansi8), Indexed(1));
+    assert_eq!(p.reduce(Indexed(15), Ansi8), Indexed(7));
+    assert_eq!(p.reduce(Rgb(255, 135, 0), Indexed256), Indexed(208));
+    assert_eq!(p.reduce(Rgb(0x80, 0x80, 0x80), Indexed256), Indexed(244));
+    assert_eq!(p.reduce(Indexed(1), Indexed256), Indexed(1));
+    assert_eq!(p.reduce(Rgb(1, 2, 3), TrueColor), Rgb(1, 2, 3));
+    assert_eq!(p.reduce(Indexed(1), Monochrome), Default);
+
+    // A palette where "red" is really orange should attract orange.
+    let mut colors = *p.colors();
+    colors[1] = (0xff, 0x87, 0x00);
+    assert_eq!(Palette::new(colors).reduce(Rgb(250, 140, 10), Ansi8), Indexed(1));
+}
+
+/**
+Wraps another interpreter, rewriting the colours in SGR sequences down to a given depth before passing them on.
+*/
+pub struct ColorReducer<I>
+where I: AnsiInterpret {
+    interp: I,
+    palette: Palette,
+    depth: ColorDepth,
+}
+
+impl<I> ColorReducer<I>
+where I: AnsiInterpret {
+    pub fn new(interp: I, depth: ColorDepth) -> Self {
+        ColorReducer::with_palette(interp, depth, Palette::default())
+    }
+
+    pub fn with_palette(interp: I, depth: ColorDepth, palette: Palette) -> Self {
+        ColorReducer {
+            interp: interp,
+            palette: palette,
+            depth: depth,
+        }
+    }
+
+    pub fn get_ref(&self) -> &I {
+        &self.interp
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.interp
+    }
+
+    pub fn into_inner(self) -> I {
+        self.interp
+    }
+}
+
+impl<I> AnsiInterpret for ColorReducer<I>
+where I: AnsiInterpret {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.interp.write_text(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interp.flush()
+    }
+
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> {
+        use sgr::SgrAttr::*;
+        let (palette, depth) = (self.palette, self.depth);
+        let attrs: Vec<SgrAttr> = attrs
+            .map(|attr| match attr {
+                Foreground(c) => Foreground(palette.reduce(c, depth)),
+                Background(c) => Background(palette.reduce(c, depth)),
+                UnderlineColor(c) => UnderlineColor(palette.reduce(c, depth)),
+                attr => attr
+            })
+            .collect();
+        let ps = sgr::sgr_params(&attrs);
+        if ps.len() == 0 {
+            return Ok(());
+        }
+        self.interp.sgr_attrs(sgr::sgr_attrs(&ps))
+    }
+
+    forward_seqs! { interp;
+        fn cuu_seq(&mut self, r: u16);
+        fn cud_seq(&mut self, r: u16);
+        fn cuf_seq(&mut self, c: u16);
+        fn cub_seq(&mut self, c: u16);
+        fn cup_seq(&mut self, r: u16, c: u16);
+        fn ed_seq(&mut self, n: EraseDisplay);
+        fn el_seq(&mut self, n: EraseLine);
+        fn xtpushsgr_seq(&mut self, mask: StyleMask);
+        fn xtpopsgr_seq(&mut self);
+        fn dsr_seq(&mut self);
+        fn scp_seq(&mut self);
+        fn rcp_seq(&mut self);
+        fn decsc_seq(&mut self);
+        fn decrc_seq(&mut self);
+        fn ris_seq(&mut self);
+        fn deckpam_seq(&mut self);
+        fn deckpnm_seq(&mut self);
+        fn decaln_seq(&mut self);
+        fn scs_seq(&mut self, slot: CharsetSlot, set: Charset);
+        fn ls_seq(&mut self, slot: CharsetSlot);
+        fn ss_seq(&mut self, slot: CharsetSlot);
+        fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool);
+        fn set_cursor_visible(&mut self, visible: bool);
+        fn set_line_wrap(&mut self, wrap: bool);
+        fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool);
+        fn xtwinops_seq(&mut self, op: WindowOp);
+        fn query_seq(&mut self, q: Query);
+        fn osc_txt_seq(&mut self, n: u16, txt: &str);
+        fn hvp_seq(&mut self, r: u16, c: u16);
+        fn other_seq(&mut self, bytes: &[u8]);
+    }
+}
@@ -0,0 +1,204 @@
+/*!
+Queries sent by applications to the terminal, and the replies the terminal sends back.
+*/
+use std::io::{self, Write};
+use window::WindowReport;
+
+/**
+A request for information from the terminal.
+*/
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+pub enum Query {
+    /// DSR 6 (`CSI 6 n`): where is the cursor?
+    CursorPosition,
+    /// DSR 5 (`CSI 5 n`): are you working?
+    Status,
+    /// DA1 (`CSI c`).
+    PrimaryDeviceAttributes,
+    /// DA2 (`CSI > c`).
+    SecondaryDeviceAttributes,
+    /// DA3 (`CSI = c`).
+    TertiaryDeviceAttributes,
+    /// DECRQM (`CSI ? Ps $ p` for DEC private modes, `CSI Ps $ p` for ANSI modes).
+    Mode { private: bool, mode: u16 },
+    /// DECRQSS (`DCS $ q Pt ST`): what is the current value of the setting selected by `Pt`?
+    Setting(String),
+    /// XTVERSION (`CSI > q`): what is the terminal's name and version?
+    Version,
+    /// XTGETTCAP (`DCS + q Pt ST`): what are the values of these termcap/terminfo capabilities?
+    Capabilities(Vec<String>),
+}
+
+/**
+The state of a mode, as reported in reply to DECRQM.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum ModeState {
+    NotRecognized = 0,
+    Set = 1,
+    Reset = 2,
+    PermanentlySet = 3,
+    PermanentlyReset = 4,
+}
+
+/**
+Writes correctly formatted replies to queries.
+
+The wrapped writer should be whatever the application reads terminal input from.
+*/
+pub struct Responder<W>
+where W: Write {
+    out: W,
+}
+
+impl<W> Responder<W>
+where W: Write {
+    pub fn new(out: W) -> Self {
+        Responder {
+            out: out,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Reply to DSR 6 with a 1-based cursor position.
+    pub fn cursor_position(&mut self, row: u16, col: u16) -> io::Result<()> {
+        write!(self.out, "\x1b[{};{}R", row, col)
+    }
+
+    /// Reply to DSR 5.
+    pub fn status(&mut self, ok: bool) -> io::Result<()> {
+        write!(self.out, "\x1b[{}n", if ok { 0 } else { 3 })
+    }
+
+    /// Reply to DA1 with the conformance level (*e.g.* 1 for a VT100, 62 for a VT220) followed by any supported features.
+    pub fn primary_attributes(&mut self, class: u16, features: &[u16]) -> io::Result<()> {
+        try!(write!(self.out, "\x1b[?{}", class));
+        for f in features {
+            try!(write!(self.out, ";{}", f));
+        }
+        write!(self.out, "c")
+    }
+
+    /// Reply to DA2 with the terminal type, firmware version and ROM cartridge number.
+    pub fn secondary_attributes(&mut self, kind: u16, version: u16, rom: u16) -> io::Result<()> {
+        write!(self.out, "\x1b[>{};{};{}c", kind, version, rom)
+    }
+
+    /// Reply to DA3 with the terminal's unit ID.
+    pub fn tertiary_attributes(&mut self, unit_id: u32) -> io::Result<()> {
+        write!(self.out, "\x1bP!|{:08X}\x1b\\", unit_id)
+    }
+
+    /// Reply to DECRQM.
+    pub fn mode(&mut self, private: bool, mode: u16, state: ModeState) -> io::Result<()> {
+        write!(self.out, "\x1b[{}{};{}$y", if private { "?" } else { "" }, mode, state as u8)
+    }
+
+    /// Reply to DECRQSS.  `None` means the request was not understood.
+    pub fn setting(&mut self, value: Option<&str>) -> io::Result<()> {
+        match value {
+            Some(value) => write!(self.out, "\x1bP1$r{}\x1b\\", value),
+            None => write!(self.out, "\x1bP0$r\x1b\\"),
+        }
+    }
+
+    /// Reply to XTVERSION.
+    pub fn version(&mut self, version: &str) -> io::Result<()> {
+        write!(self.out, "\x1bP>|{}\x1b\\", version)
+    }
+
+    /// Reply to XTGETTCAP for a single capability.  `None` means the capability is not supported.
+    pub fn capability(&mut self, name: &str, value: Option<&str>) -> io::Result<()> {
+        match value {
+            Some(value) => write!(self.out, "\x1bP1+r{}={}\x1b\\", HexStr(name), HexStr(value)),
+            None => write!(self.out, "\x1bP0+r{}\x1b\\", HexStr(name)),
+        }
+    }
+
+    /// Reply to one of the XTWINOPS reports.
+    pub fn window(&mut self, report: &WindowReport) -> io::Result<()> {
+        write!(self.out, "{}", report)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+struct HexStr<'a>(&'a str);
+
+impl<'a> ::std::fmt::Display for HexStr<'a> {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        for b in self.0.bytes() {
+            try!(write!(fmt, "{:02X}", b));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_responder() {
+    fn reply<F>(f: F) -> String where F: FnOnce(&mut Responder<&mut Vec<u8>>) -> io::Result<()> {
+        let mut buf = vec![];
+        f(&mut Responder::new(&mut buf)).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    assert_eq!(reply(|r| r.cursor_position(3, 7)), "\x1b[3;7R");
+    assert_eq!(reply(|r| r.status(true)), "\x1b[0n");
+    assert_eq!(reply(|r| r.primary_attributes(62, &[22])), "\x1b[?62;22c");
+    assert_eq!(reply(|r| r.secondary_attributes(0, 10, 1)), "\x1b[>0;10;1c");
+    assert_eq!(reply(|r| r.tertiary_attributes(0xbeef)), "\x1bP!|0000BEEF\x1b\\");
+    assert_eq!(reply(|r| r.mode(true, 25, ModeState::Reset)), "\x1b[?25;2$y");
+    assert_eq!(reply(|r| r.setting(None)), "\x1bP0$r\x1b\\");
+    assert_eq!(reply(|r| r.setting(Some("0m"))), "\x1bP1$r0m\x1b\\");
+    assert_eq!(reply(|r| r.capability("Co", Some("256"))), "\x1bP1+r436F=323536\x1b\\");
+    assert_eq!(reply(|r| r.capability("TN", None)), "\x1bP0+r544E\x1b\\");
+}
+
+/**
+Decode the hex-encoded, `;`-separated capability names of an XTGETTCAP request.
+*/
+pub fn decode_capability_names(bytes: &[u8]) -> Option<Vec<String>> {
+    fn nibble(b: u8) -> Option<u8> {
+        match b {
+            b'0'...b'9' => Some(b - b'0'),
+            b'a'...b'f' => Some(b - b'a' + 10),
+            b'A'...b'F' => Some(b - b'A' + 10),
+            _ => None
+        }
+    }
+
+    let mut names = vec![];
+    for name in bytes.split(|&b| b == b';') {
+        if name.len() % 2 != 0 {
+            return None;
+        }
+        let mut decoded = Vec::with_capacity(name.len() / 2);
+        for pair in name.chunks(2) {
+            let hi = match nibble(pair[0]) { Some(n) => n, None => return None };
+            let lo = match nibble(pair[1]) { Some(n) => n, None => return None };
+            decoded.push((hi << 4) | lo);
+        }
+        match String::from_utf8(decoded) {
+            Ok(name) => names.push(name),
+            Err(_) => return None
+        }
+    }
+    Some(names)
+}
+
+#[test]
+fn test_decode_capability_names() {
+    assert_eq!(decode_capability_names(b"436F"), Some(vec![String::from("Co")]));
+    assert_eq!(decode_capability_names(b"544E;736d637570"), Some(vec![String::from("TN"), String::from("smcup")]));
+    assert_eq!(decode_capability_names(b"436"), None);
+    assert_eq!(decode_capability_names(b"43zz"), None);
+}
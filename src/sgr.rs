@@ -0,0 +1,465 @@
+/*!
+Decoding of Select Graphic Rendition (`CSI ... m`) parameters into typed attributes.
+*/
+use std::fmt;
+use palette::Palette;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum Color {
+    /// The terminal's default colour for whatever is being coloured.
+    Default,
+    /// An entry in the terminal's palette.  0–7 are the basic colours, 8–15 their bright versions.
+    Indexed(u8),
+    /// A direct 24-bit colour.
+    Rgb(u8, u8, u8),
+}
+
+/**
+How many colours an output can show.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash)]
+pub enum ColorDepth {
+    /// No colour at all; everything is drawn in the default colours.
+    Monochrome,
+    /// Just the 8 basic colours.
+    Ansi8,
+    /// The 8 basic colours and their bright versions.
+    Ansi16,
+    /// The xterm 256-colour palette.
+    Indexed256,
+    /// Direct 24-bit colour.
+    TrueColor,
+}
+
+impl Color {
+    /**
+    Squash this colour down to the nearest one an output with the given depth can show, assuming the default palette.
+    */
+    pub fn reduce(self, depth: ColorDepth) -> Color {
+        Palette::with_default(|palette| palette.reduce(self, depth))
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum UnderlineStyle {
+    None,
+    Single,
+    Double,
+    Curly,
+    Dotted,
+    Dashed,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum SgrAttr {
+    Reset,
+    Bold,
+    Faint,
+    Italic,
+    /// Set the underline style; `UnderlineStyle::None` turns underlining off.
+    Underline(UnderlineStyle),
+    SlowBlink,
+    RapidBlink,
+    Reverse,
+    Conceal,
+    CrossedOut,
+    /// Select a font: 0 is the primary font, 1–9 are the alternatives.
+    Font(u8),
+    Fraktur,
+    /// Neither bold nor faint.
+    NormalIntensity,
+    /// Neither italic nor fraktur.
+    NotItalic,
+    NotBlinking,
+    NotReversed,
+    Reveal,
+    NotCrossedOut,
+    Foreground(Color),
+    Background(Color),
+    Framed,
+    Encircled,
+    Overlined,
+    /// Neither framed nor encircled.
+    NotFramed,
+    NotOverlined,
+    UnderlineColor(Color),
+    Superscript,
+    Subscript,
+    NotSuperscriptOrSubscript,
+    /// Anything we don't recognise, including extended colours we couldn't make sense of.
+    Unknown(u16),
+}
+
+/**
+A single SGR parameter.  `sub` is set if the parameter was introduced by a `:`, making it a sub-parameter of the one before it.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct SgrParam {
+    pub value: Option<u16>,
+    pub sub: bool,
+}
+
+/**
+Iterator over the attributes in an SGR sequence.
+*/
+#[derive(Clone, Debug)]
+pub struct SgrAttrs<'a> {
+    ps: &'a [SgrParam],
+}
+
+/**
+Decode an SGR sequence's parameters.  An empty parameter list should already have been replaced with a lone `0`.
+*/
+pub fn sgr_attrs<'a>(ps: &'a [SgrParam]) -> SgrAttrs<'a> {
+    SgrAttrs {
+        ps: ps,
+    }
+}
+
+impl<'a> SgrAttrs<'a> {
+    /**
+    Pull off the next parameter along with any sub-parameters attached to it.  Defaulted parameters are zero.
+    */
+    fn next_param(&mut self) -> Option<(u16, &'a [SgrParam])> {
+        let (first, tail) = match self.ps.split_first() {
+            Some(v) => v,
+            None => return None
+        };
+        let subs = tail.iter().take_while(|p| p.sub).count();
+        let (subs, tail) = tail.split_at(subs);
+        self.ps = tail;
+        Some((first.value.unwrap_or(0), subs))
+    }
+
+    fn next_n(&mut self) -> Option<u16> {
+        self.next_param().map(|(n, _)| n)
+    }
+
+    /**
+    Decode the colour following a 38, 48 or 58.  Returns `None` if the colour is malformed or not one we can represent, having consumed its parameters either way.
+
+    This accepts the legacy form, where the colour is given by the following parameters (`38;5;n` and `38;2;r;g;b`), and the ITU T.416 form, where it is given by sub-parameters (`38:5:n` and `38:2:cs:r:g:b`).  The colour space id in the latter is ignored, and may be left out entirely as many programs do.
+    */
+    fn extended_color(&mut self, subs: &[SgrParam]) -> Option<Color> {
+        fn channel(n: u16) -> Option<u8> {
+            if n <= 255 { Some(n as u8) } else { None }
+        }
+
+        if subs.len() > 0 {
+            let arg = |i: usize| subs.get(i).map(|p| p.value.unwrap_or(0));
+            match arg(0) {
+                Some(5) => arg(1).and_then(channel).map(Color::Indexed),
+                Some(2) => {
+                    let rgb = if subs.len() >= 5 { 2 } else { 1 };
+                    match (arg(rgb).and_then(channel), arg(rgb + 1).and_then(channel), arg(rgb + 2).and_then(channel)) {
+                        (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                        _ => None
+                    }
+                },
+                _ => None
+            }
+        } else {
+            match self.next_n() {
+                Some(5) => self.next_n().and_then(channel).map(Color::Indexed),
+                Some(2) => {
+                    let r = self.next_n().and_then(channel);
+                    let g = self.next_n().and_then(channel);
+                    let b = self.next_n().and_then(channel);
+                    match (r, g, b) {
+                        (Some(r), Some(g), Some(b)) => Some(Color::Rgb(r, g, b)),
+                        _ => None
+                    }
+                },
+                _ => None
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for SgrAttrs<'a> {
+    type Item = SgrAttr;
+
+    fn next(&mut self) -> Option<SgrAttr> {
+        use self::SgrAttr::*;
+
+        let (n, subs) = match self.next_param() {
+            Some(v) => v,
+            None => return None
+        };
+
+        Some(match n {
+            0 => Reset,
+            1 => Bold,
+            2 => Faint,
+            3 => Italic,
+            4 => match subs.first().map(|p| p.value.unwrap_or(0)) {
+                None | Some(1) => Underline(UnderlineStyle::Single),
+                Some(0) => Underline(UnderlineStyle::None),
+                Some(2) => Underline(UnderlineStyle::Double),
+                Some(3) => Underline(UnderlineStyle::Curly),
+                Some(4) => Underline(UnderlineStyle::Dotted),
+                Some(5) => Underline(UnderlineStyle::Dashed),
+                Some(_) => Unknown(4)
+            },
+            5 => SlowBlink,
+            6 => RapidBlink,
+            7 => Reverse,
+            8 => Conceal,
+            9 => CrossedOut,
+            n @ 10...19 => Font((n - 10) as u8),
+            20 => Fraktur,
+            21 => Underline(UnderlineStyle::Double),
+            22 => NormalIntensity,
+            23 => NotItalic,
+            24 => Underline(UnderlineStyle::None),
+            25 => NotBlinking,
+            27 => NotReversed,
+            28 => Reveal,
+            29 => NotCrossedOut,
+            n @ 30...37 => Foreground(Color::Indexed((n - 30) as u8)),
+            38 => match self.extended_color(subs) {
+                Some(c) => Foreground(c),
+                None => Unknown(38)
+            },
+            39 => Foreground(Color::Default),
+            n @ 40...47 => Background(Color::Indexed((n - 40) as u8)),
+            48 => match self.extended_color(subs) {
+                Some(c) => Background(c),
+                None => Unknown(48)
+            },
+            49 => Background(Color::Default),
+            51 => Framed,
+            52 => Encircled,
+            53 => Overlined,
+            54 => NotFramed,
+            55 => NotOverlined,
+            58 => match self.extended_color(subs) {
+                Some(c) => UnderlineColor(c),
+                None => Unknown(58)
+            },
+            59 => UnderlineColor(Color::Default),
+            73 => Superscript,
+            74 => Subscript,
+            75 => NotSuperscriptOrSubscript,
+            n @ 90...97 => Foreground(Color::Indexed((n - 90 + 8) as u8)),
+            n @ 100...107 => Background(Color::Indexed((n - 100 + 8) as u8)),
+            n => Unknown(n)
+        })
+    }
+}
+
+#[test]
+fn test_sgr_attrs() {
+    use self::SgrAttr::*;
+    use self::Color::*;
+
+    fn attrs(ns: &[u16]) -> Vec<SgrAttr> {
+        let ps: Vec<_> = ns.iter().map(|&n| SgrParam { value: Some(n), sub: false }).collect();
+        sgr_attrs(&ps).collect()
+    }
+
+    assert_eq!(attrs(&[0]), vec![Reset]);
+    assert_eq!(attrs(&[1, 31, 42]), vec![Bold, Foreground(Indexed(1)), Background(Indexed(2))]);
+    assert_eq!(attrs(&[91, 107, 39, 49]), vec![Foreground(Indexed(9)), Background(Indexed(15)), Foreground(Default), Background(Default)]);
+    assert_eq!(attrs(&[38, 5, 208, 4]), vec![Foreground(Indexed(208)), Underline(UnderlineStyle::Single)]);
+    assert_eq!(attrs(&[21, 24]), vec![Underline(UnderlineStyle::Double), Underline(UnderlineStyle::None)]);
+    assert_eq!(attrs(&[48, 5, 17, 58, 5, 1, 59]), vec![Background(Indexed(17)), UnderlineColor(Indexed(1)), UnderlineColor(Default)]);
+    assert_eq!(attrs(&[38, 2, 1, 2, 3, 1]), vec![Foreground(Rgb(1, 2, 3)), Bold]);
+    assert_eq!(attrs(&[38, 2, 1, 2, 300, 1]), vec![Unknown(38), Bold]);
+    assert_eq!(attrs(&[38, 5]), vec![Unknown(38)]);
+    assert_eq!(attrs(&[38, 5, 256]), vec![Unknown(38)]);
+    assert_eq!(attrs(&[10, 19, 20, 53, 73, 26]), vec![Font(0), Font(9), Fraktur, Overlined, Superscript, Unknown(26)]);
+}
+
+#[test]
+fn test_sgr_attrs_colon_form() {
+    use self::SgrAttr::*;
+    use self::Color::*;
+
+    // Each parameter is (value, introduced by ':').
+    fn attrs(ps: &[(Option<u16>, bool)]) -> Vec<SgrAttr> {
+        let ps: Vec<_> = ps.iter().map(|&(value, sub)| SgrParam { value: value, sub: sub }).collect();
+        sgr_attrs(&ps).collect()
+    }
+
+    // 38:5:208
+    assert_eq!(attrs(&[(Some(38), false), (Some(5), true), (Some(208), true)]),
+        vec![Foreground(Indexed(208))]);
+    // 48:2::10:20:30;1
+    assert_eq!(attrs(&[(Some(48), false), (Some(2), true), (None, true), (Some(10), true), (Some(20), true), (Some(30), true), (Some(1), false)]),
+        vec![Background(Rgb(10, 20, 30)), Bold]);
+    // 38:2:0:10:20:30, with an explicit colour space
+    assert_eq!(attrs(&[(Some(38), false), (Some(2), true), (Some(0), true), (Some(10), true), (Some(20), true), (Some(30), true)]),
+        vec![Foreground(Rgb(10, 20, 30))]);
+    // 58:2:255:0:0, without a colour space
+    assert_eq!(attrs(&[(Some(58), false), (Some(2), true), (Some(255), true), (Some(0), true), (Some(0), true)]),
+        vec![UnderlineColor(Rgb(255, 0, 0))]);
+    // 38:2:1 is too short, and must not eat the following 31
+    assert_eq!(attrs(&[(Some(38), false), (Some(2), true), (Some(1), true), (Some(31), false)]),
+        vec![Unknown(38), Foreground(Indexed(1))]);
+    // 4:3;58:2::255:0:0, a red squiggle
+    assert_eq!(attrs(&[(Some(4), false), (Some(3), true), (Some(58), false), (Some(2), true), (None, true), (Some(255), true), (Some(0), true), (Some(0), true)]),
+        vec![Underline(UnderlineStyle::Curly), UnderlineColor(Rgb(255, 0, 0))]);
+    // 4:0 and 4:5
+    assert_eq!(attrs(&[(Some(4), false), (Some(0), true), (Some(4), false), (Some(5), true)]),
+        vec![Underline(UnderlineStyle::None), Underline(UnderlineStyle::Dashed)]);
+    // 4:9 isn't a style we know
+    assert_eq!(attrs(&[(Some(4), false), (Some(9), true)]), vec![Unknown(4)]);
+    // ;; is a reset
+    assert_eq!(attrs(&[(Some(1), false), (None, false)]), vec![Bold, Reset]);
+}
+
+impl fmt::Display for Color {
+    /**
+    Formats the colour as the parameters that follow 38, 48 or 58.  `Default` has no such representation and formats as nothing.
+    */
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Color::Default => Ok(()),
+            Color::Indexed(n) => write!(fmt, "5;{}", n),
+            Color::Rgb(r, g, b) => write!(fmt, "2;{};{};{}", r, g, b),
+        }
+    }
+}
+
+impl fmt::Display for SgrAttr {
+    /**
+    Formats the attribute as the SGR parameters that would select it.
+    */
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let mut ps = vec![];
+        encode(*self, &mut ps);
+        for (i, p) in ps.iter().enumerate() {
+            if i > 0 {
+                try!(fmt.write_str(if p.sub { ":" } else { ";" }));
+            }
+            try!(write!(fmt, "{}", p.value.unwrap_or(0)));
+        }
+        Ok(())
+    }
+}
+
+/**
+Append the SGR parameters that would select `attr` to `ps`.
+*/
+fn encode(attr: SgrAttr, ps: &mut Vec<SgrParam>) {
+    use self::SgrAttr::*;
+
+    fn param(value: u16) -> SgrParam {
+        SgrParam { value: Some(value), sub: false }
+    }
+
+    fn color(ps: &mut Vec<SgrParam>, c: Color, base: u16, bright_base: Option<u16>) {
+        match (c, bright_base) {
+            (Color::Default, _) => ps.push(param(base + 1)),
+            (Color::Indexed(n @ 0...7), Some(_)) => ps.push(param(base - 8 + n as u16)),
+            (Color::Indexed(n @ 8...15), Some(bb)) => ps.push(param(bb + n as u16 - 8)),
+            (Color::Indexed(n), _) => ps.extend(&[param(base), param(5), param(n as u16)]),
+            (Color::Rgb(r, g, b), _) => ps.extend(&[param(base), param(2), param(r as u16), param(g as u16), param(b as u16)]),
+        }
+    }
+
+    let n = match attr {
+        Reset => 0,
+        Bold => 1,
+        Faint => 2,
+        Italic => 3,
+        Underline(UnderlineStyle::None) => 24,
+        Underline(UnderlineStyle::Single) => 4,
+        Underline(UnderlineStyle::Double) => 21,
+        Underline(style) => {
+            let sub = match style {
+                UnderlineStyle::Curly => 3,
+                UnderlineStyle::Dotted => 4,
+                _ => 5,
+            };
+            ps.extend(&[param(4), SgrParam { value: Some(sub), sub: true }]);
+            return;
+        },
+        SlowBlink => 5,
+        RapidBlink => 6,
+        Reverse => 7,
+        Conceal => 8,
+        CrossedOut => 9,
+        Font(n) => 10 + n as u16,
+        Fraktur => 20,
+        NormalIntensity => 22,
+        NotItalic => 23,
+        NotBlinking => 25,
+        NotReversed => 27,
+        Reveal => 28,
+        NotCrossedOut => 29,
+        Foreground(c) => return color(ps, c, 38, Some(90)),
+        Background(c) => return color(ps, c, 48, Some(100)),
+        Framed => 51,
+        Encircled => 52,
+        Overlined => 53,
+        NotFramed => 54,
+        NotOverlined => 55,
+        UnderlineColor(c) => return color(ps, c, 58, None),
+        Superscript => 73,
+        Subscript => 74,
+        NotSuperscriptOrSubscript => 75,
+        Unknown(n) => n,
+    };
+    ps.push(param(n));
+}
+
+/**
+Whether an attribute can be encoded again without changing what comes after it.
+
+Extended colours we couldn't decode (`Unknown(38)` and friends) can't be, since on their own they would swallow the parameters that follow them.  Nor can an underline style we don't know (`Unknown(4)`), which without its sub-parameter would come back as a plain single underline.
+*/
+pub fn is_reencodable(attr: SgrAttr) -> bool {
+    match attr {
+        SgrAttr::Unknown(4) | SgrAttr::Unknown(38) | SgrAttr::Unknown(48) | SgrAttr::Unknown(58) => false,
+        _ => true
+    }
+}
+
+/**
+Encode attributes back into SGR parameters, suitable for handing to `sgr_attrs`.  Anything `is_reencodable` rejects is dropped.
+*/
+pub fn sgr_params(attrs: &[SgrAttr]) -> Vec<SgrParam> {
+    let mut ps = vec![];
+    for &attr in attrs {
+        if is_reencodable(attr) {
+            encode(attr, &mut ps);
+        }
+    }
+    ps
+}
+
+#[test]
+fn test_sgr_attr_display() {
+    use self::SgrAttr::*;
+    use self::Color::*;
+
+    assert_eq!(Reset.to_string(), "0");
+    assert_eq!(Foreground(Indexed(1)).to_string(), "31");
+    assert_eq!(Foreground(Indexed(9)).to_string(), "91");
+    assert_eq!(Foreground(Indexed(208)).to_string(), "38;5;208");
+    assert_eq!(Foreground(Default).to_string(), "39");
+    assert_eq!(Background(Indexed(7)).to_string(), "47");
+    assert_eq!(Background(Indexed(15)).to_string(), "107");
+    assert_eq!(Background(Default).to_string(), "49");
+    assert_eq!(UnderlineColor(Indexed(1)).to_string(), "58;5;1");
+    assert_eq!(UnderlineColor(Default).to_string(), "59");
+    assert_eq!(Foreground(Rgb(1, 2, 3)).to_string(), "38;2;1;2;3");
+    assert_eq!(Font(3).to_string(), "13");
+    assert_eq!(Underline(UnderlineStyle::Curly).to_string(), "4:3");
+    assert_eq!(Underline(UnderlineStyle::Dashed).to_string(), "4:5");
+
+    // Everything we can decode should format back to the same parameters.
+    for n in 0..108 {
+        let attrs: Vec<_> = sgr_attrs(&[SgrParam { value: Some(n), sub: false }]).collect();
+        if attrs.len() == 1 && attrs[0] != Unknown(n) {
+            assert_eq!(attrs[0].to_string(), n.to_string());
+        }
+    }
+
+    // And everything should survive being encoded again.
+    let attrs = [Bold, Foreground(Rgb(1, 2, 3)), Unknown(38), Underline(UnderlineStyle::Curly), Unknown(4), Background(Indexed(9))];
+    let ps = sgr_params(&attrs);
+    assert_eq!(sgr_attrs(&ps).collect::<Vec<_>>(), [Bold, Foreground(Rgb(1, 2, 3)), Underline(UnderlineStyle::Curly), Background(Indexed(9))]);
+}
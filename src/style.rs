@@ -0,0 +1,520 @@
+/*!
+Pen state tracking.
+
+Rather than have every interpreter that cares about colour re-implement SGR state, `StyleTracker` keeps track of the current `Style` and hands it out alongside each run of text.
+*/
+use std::fmt;
+use std::io;
+use ansi::{AnsiInterpret, GenError};
+use sgr::{Color, ColorDepth, SgrAttr, SgrAttrs, UnderlineStyle};
+
+/**
+The complete set of SGR-controlled attributes in effect for a piece of text.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub underline_color: Color,
+    pub underline: UnderlineStyle,
+    pub bold: bool,
+    pub faint: bool,
+    pub italic: bool,
+    pub fraktur: bool,
+    pub blink: bool,
+    pub rapid_blink: bool,
+    pub reverse: bool,
+    pub conceal: bool,
+    pub crossed_out: bool,
+    pub overline: bool,
+    pub framed: bool,
+    pub encircled: bool,
+    pub superscript: bool,
+    pub subscript: bool,
+    /// 0 is the primary font, 1–9 are the alternatives.
+    pub font: u8,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            fg: Color::Default,
+            bg: Color::Default,
+            underline_color: Color::Default,
+            underline: UnderlineStyle::None,
+            bold: false,
+            faint: false,
+            italic: false,
+            fraktur: false,
+            blink: false,
+            rapid_blink: false,
+            reverse: false,
+            conceal: false,
+            crossed_out: false,
+            overline: false,
+            framed: false,
+            encircled: false,
+            superscript: false,
+            subscript: false,
+            font: 0,
+        }
+    }
+}
+
+impl Style {
+    /**
+    Update this style with a single SGR attribute.  Unknown attributes are ignored.
+    */
+    pub fn apply(&mut self, attr: SgrAttr) {
+        use sgr::SgrAttr::*;
+        match attr {
+            Reset => *self = Style::default(),
+            Bold => self.bold = true,
+            Faint => self.faint = true,
+            Italic => self.italic = true,
+            Underline(u) => self.underline = u,
+            SlowBlink => self.blink = true,
+            RapidBlink => self.rapid_blink = true,
+            Reverse => self.reverse = true,
+            Conceal => self.conceal = true,
+            CrossedOut => self.crossed_out = true,
+            Font(n) => self.font = n,
+            Fraktur => self.fraktur = true,
+            NormalIntensity => {
+                self.bold = false;
+                self.faint = false;
+            },
+            NotItalic => {
+                self.italic = false;
+                self.fraktur = false;
+            },
+            NotBlinking => {
+                self.blink = false;
+                self.rapid_blink = false;
+            },
+            NotReversed => self.reverse = false,
+            Reveal => self.conceal = false,
+            NotCrossedOut => self.crossed_out = false,
+            Foreground(c) => self.fg = c,
+            Background(c) => self.bg = c,
+            Framed => self.framed = true,
+            Encircled => self.encircled = true,
+            Overlined => self.overline = true,
+            NotFramed => {
+                self.framed = false;
+                self.encircled = false;
+            },
+            NotOverlined => self.overline = false,
+            UnderlineColor(c) => self.underline_color = c,
+            Superscript => {
+                self.superscript = true;
+                self.subscript = false;
+            },
+            Subscript => {
+                self.superscript = false;
+                self.subscript = true;
+            },
+            NotSuperscriptOrSubscript => {
+                self.superscript = false;
+                self.subscript = false;
+            },
+            Unknown(_) => (),
+        }
+    }
+}
+
+#[test]
+fn test_style_apply() {
+    use sgr::SgrAttr::*;
+
+    let mut style = Style::default();
+    for &attr in &[Bold, Faint, Italic, Foreground(Color::Indexed(1)), Underline(UnderlineStyle::Curly), Unknown(26)] {
+        style.apply(attr);
+    }
+    assert!(style.bold && style.faint && style.italic);
+    assert_eq!(style.fg, Color::Indexed(1));
+    assert_eq!(style.underline, UnderlineStyle::Curly);
+
+    style.apply(NormalIntensity);
+    assert!(!style.bold && !style.faint && style.italic);
+
+    style.apply(Reset);
+    assert_eq!(style, Style::default());
+}
+
+/**
+Which parts of a `Style` to save with XTPUSHSGR.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct StyleMask {
+    pub bold: bool,
+    pub faint: bool,
+    pub italic: bool,
+    /// Covers both single and double underlining, and any other underline style.
+    pub underline: bool,
+    pub blink: bool,
+    pub reverse: bool,
+    pub conceal: bool,
+    pub crossed_out: bool,
+    pub fg: bool,
+    pub bg: bool,
+    /// Everything the XTPUSHSGR parameters have no way to select, such as the underline colour; only set when pushing everything.
+    pub others: bool,
+}
+
+impl StyleMask {
+    pub fn all() -> Self {
+        StyleMask {
+            bold: true,
+            faint: true,
+            italic: true,
+            underline: true,
+            blink: true,
+            reverse: true,
+            conceal: true,
+            crossed_out: true,
+            fg: true,
+            bg: true,
+            others: true,
+        }
+    }
+
+    pub fn none() -> Self {
+        StyleMask {
+            bold: false,
+            faint: false,
+            italic: false,
+            underline: false,
+            blink: false,
+            reverse: false,
+            conceal: false,
+            crossed_out: false,
+            fg: false,
+            bg: false,
+            others: false,
+        }
+    }
+}
+
+impl<'a> From<&'a [u16]> for StyleMask {
+    /**
+    Decode the parameters of an XTPUSHSGR.  No parameters at all means everything; parameters we don't recognise are ignored.
+    */
+    fn from(ns: &'a [u16]) -> StyleMask {
+        if ns.len() == 0 {
+            return StyleMask::all();
+        }
+        let mut mask = StyleMask::none();
+        for &n in ns {
+            match n {
+                1 => mask.bold = true,
+                2 => mask.faint = true,
+                3 => mask.italic = true,
+                4 | 21 => mask.underline = true,
+                5 => mask.blink = true,
+                7 => mask.reverse = true,
+                8 => mask.conceal = true,
+                9 => mask.crossed_out = true,
+                30 => mask.fg = true,
+                31 => mask.bg = true,
+                _ => ()
+            }
+        }
+        mask
+    }
+}
+
+impl Style {
+    /**
+    Put back the parts of a saved style selected by `mask`, leaving everything else alone.
+    */
+    pub fn restore(&mut self, saved: &Style, mask: StyleMask) {
+        if mask.others {
+            *self = Style {
+                bold: self.bold,
+                faint: self.faint,
+                italic: self.italic,
+                underline: self.underline,
+                blink: self.blink,
+                rapid_blink: self.rapid_blink,
+                reverse: self.reverse,
+                conceal: self.conceal,
+                crossed_out: self.crossed_out,
+                fg: self.fg,
+                bg: self.bg,
+                ..*saved
+            };
+        }
+        if mask.bold { self.bold = saved.bold; }
+        if mask.faint { self.faint = saved.faint; }
+        if mask.italic { self.italic = saved.italic; }
+        if mask.underline { self.underline = saved.underline; }
+        if mask.blink {
+            self.blink = saved.blink;
+            self.rapid_blink = saved.rapid_blink;
+        }
+        if mask.reverse { self.reverse = saved.reverse; }
+        if mask.conceal { self.conceal = saved.conceal; }
+        if mask.crossed_out { self.crossed_out = saved.crossed_out; }
+        if mask.fg { self.fg = saved.fg; }
+        if mask.bg { self.bg = saved.bg; }
+    }
+}
+
+#[test]
+fn test_style_restore() {
+    let saved = Style { bold: true, fg: Color::Indexed(1), underline_color: Color::Indexed(2), ..Style::default() };
+    let current = Style { italic: true, fg: Color::Indexed(4), bg: Color::Indexed(5), ..Style::default() };
+
+    let mut style = current;
+    style.restore(&saved, StyleMask::from(&[30, 99][..]));
+    assert_eq!(style, Style { fg: Color::Indexed(1), ..current });
+
+    let mut style = current;
+    style.restore(&saved, StyleMask::from(&[][..]));
+    assert_eq!(style, saved);
+}
+
+impl Style {
+    /**
+    This style with its colours squashed down to the given depth.
+    */
+    pub fn reduce(&self, depth: ColorDepth) -> Style {
+        Style {
+            fg: self.fg.reduce(depth),
+            bg: self.bg.reduce(depth),
+            underline_color: self.underline_color.reduce(depth),
+            ..*self
+        }
+    }
+
+    /**
+    Work out the shortest SGR sequence that takes an output from this style to `target`, at the given colour depth.
+
+    Attributes are turned off with their targeted resets (22, 23, 24, 39, 49, *etc.*) unless starting over from a full reset would be shorter.
+    */
+    pub fn diff(&self, target: &Style, depth: ColorDepth) -> StyleDiff {
+        let from = self.reduce(depth);
+        let to = target.reduce(depth);
+        if from == to {
+            return StyleDiff { attrs: vec![] };
+        }
+
+        let mut attrs = vec![];
+        changes(&from, &to, &mut attrs);
+
+        let mut reset = vec![SgrAttr::Reset];
+        changes(&Style::default(), &to, &mut reset);
+
+        if params_len(&reset) < params_len(&attrs) {
+            attrs = reset;
+        }
+        StyleDiff { attrs: attrs }
+    }
+}
+
+/**
+Push the attributes needed to get from `from` to `to` without a full reset.
+*/
+fn changes(from: &Style, to: &Style, out: &mut Vec<SgrAttr>) {
+    use sgr::SgrAttr::*;
+
+    /*
+    Attributes that share a single reset: if anything has to be turned off, reset the lot and turn the rest back on.
+    */
+    fn group(out: &mut Vec<SgrAttr>, from: &[bool], to: &[bool], on: &[SgrAttr], off: SgrAttr) {
+        let clear = from.iter().zip(to).any(|(&f, &t)| f && !t);
+        if clear {
+            out.push(off);
+        }
+        for ((&f, &t), &attr) in from.iter().zip(to).zip(on) {
+            if t && (clear || !f) {
+                out.push(attr);
+            }
+        }
+    }
+
+    group(out, &[from.bold, from.faint], &[to.bold, to.faint], &[Bold, Faint], NormalIntensity);
+    group(out, &[from.italic, from.fraktur], &[to.italic, to.fraktur], &[Italic, Fraktur], NotItalic);
+    if from.underline != to.underline { out.push(Underline(to.underline)); }
+    group(out, &[from.blink, from.rapid_blink], &[to.blink, to.rapid_blink], &[SlowBlink, RapidBlink], NotBlinking);
+    group(out, &[from.reverse], &[to.reverse], &[Reverse], NotReversed);
+    group(out, &[from.conceal], &[to.conceal], &[Conceal], Reveal);
+    group(out, &[from.crossed_out], &[to.crossed_out], &[CrossedOut], NotCrossedOut);
+    if from.font != to.font { out.push(Font(to.font)); }
+    if from.fg != to.fg { out.push(Foreground(to.fg)); }
+    if from.bg != to.bg { out.push(Background(to.bg)); }
+    group(out, &[from.framed, from.encircled], &[to.framed, to.encircled], &[Framed, Encircled], NotFramed);
+    group(out, &[from.overline], &[to.overline], &[Overlined], NotOverlined);
+    if from.underline_color != to.underline_color { out.push(UnderlineColor(to.underline_color)); }
+    if (from.superscript, from.subscript) != (to.superscript, to.subscript) {
+        out.push(match (to.superscript, to.subscript) {
+            (true, _) => Superscript,
+            (_, true) => Subscript,
+            _ => NotSuperscriptOrSubscript
+        });
+    }
+}
+
+/// The length of the parameters for a list of attributes, including separators.
+fn params_len(attrs: &[SgrAttr]) -> usize {
+    attrs.iter().map(|a| a.to_string().len() + 1).sum()
+}
+
+/**
+The attributes needed to move from one `Style` to another.
+
+The `Display` implementation produces the complete SGR sequence, or nothing at all if the styles were the same.
+*/
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+pub struct StyleDiff {
+    attrs: Vec<SgrAttr>,
+}
+
+impl StyleDiff {
+    pub fn attrs(&self) -> &[SgrAttr] {
+        &self.attrs
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.attrs.is_empty()
+    }
+}
+
+impl fmt::Display for StyleDiff {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.attrs.is_empty() {
+            return Ok(());
+        }
+        try!(write!(fmt, "\x1b["));
+        for (i, attr) in self.attrs.iter().enumerate() {
+            if i > 0 {
+                try!(write!(fmt, ";"));
+            }
+            try!(write!(fmt, "{}", attr));
+        }
+        write!(fmt, "m")
+    }
+}
+
+#[test]
+fn test_style_diff() {
+    use sgr::ColorDepth::*;
+
+    let plain = Style::default();
+    let bold = Style { bold: true, ..plain };
+    let bold_red = Style { fg: Color::Indexed(1), ..bold };
+    let faint_red = Style { bold: false, faint: true, ..bold_red };
+    let fancy = Style { italic: true, underline: UnderlineStyle::Curly, bg: Color::Rgb(255, 135, 0), ..faint_red };
+
+    assert_eq!(plain.diff(&plain, TrueColor).to_string(), "");
+    assert_eq!(plain.diff(&bold_red, TrueColor).to_string(), "\x1b[1;31m");
+    assert_eq!(bold.diff(&bold_red, TrueColor).to_string(), "\x1b[31m");
+    assert_eq!(bold_red.diff(&bold, TrueColor).to_string(), "\x1b[39m");
+    assert_eq!(bold_red.diff(&faint_red, TrueColor).to_string(), "\x1b[22;2m");
+    assert_eq!(faint_red.diff(&plain, TrueColor).to_string(), "\x1b[0m");
+    assert_eq!(fancy.diff(&Style { italic: false, underline: UnderlineStyle::None, ..fancy }, TrueColor).to_string(), "\x1b[23;24m");
+    assert_eq!(fancy.diff(&faint_red, TrueColor).to_string(), "\x1b[0;2;31m");
+    assert_eq!(fancy.diff(&Style { faint: false, ..fancy }, TrueColor).to_string(), "\x1b[22m");
+    assert_eq!(faint_red.diff(&fancy, TrueColor).to_string(), "\x1b[3;4:3;48;2;255;135;0m");
+    assert_eq!(faint_red.diff(&fancy, Indexed256).to_string(), "\x1b[3;4:3;48;5;208m");
+    assert_eq!(faint_red.diff(&fancy, Ansi16).to_string(), "\x1b[3;4:3;101m");
+    assert_eq!(plain.diff(&bold_red, Monochrome).to_string(), "\x1b[1m");
+}
+
+/**
+Something that renders text in a given style, and doesn't care about anything else.
+*/
+pub trait StyledInterpret {
+    fn write_styled(&mut self, buf: &[u8], style: &Style) -> io::Result<usize>;
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// How deep the XTPUSHSGR stack can get; this is the same limit xterm uses.
+pub const MAX_PUSHED_STYLES: usize = 10;
+
+/**
+Tracks the pen through SGR sequences, passing each run of text on to a `StyledInterpret` along with the style it should be drawn in.
+
+The pen is also saved and restored by DECSC/DECRC and XTPUSHSGR/XTPOPSGR.  All other sequences are dropped.
+*/
+pub struct StyleTracker<I>
+where I: StyledInterpret {
+    interp: I,
+    style: Style,
+    saved: Style,
+    stack: Vec<(Style, StyleMask)>,
+}
+
+impl<I> StyleTracker<I>
+where I: StyledInterpret {
+    pub fn new(interp: I) -> Self {
+        StyleTracker {
+            interp: interp,
+            style: Style::default(),
+            saved: Style::default(),
+            stack: vec![],
+        }
+    }
+
+    pub fn style(&self) -> &Style {
+        &self.style
+    }
+
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.interp
+    }
+
+    pub fn into_inner(self) -> I {
+        self.interp
+    }
+}
+
+impl<I> AnsiInterpret for StyleTracker<I>
+where I: StyledInterpret {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.interp.write_styled(buf, &self.style)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interp.flush()
+    }
+
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> {
+        for attr in attrs {
+            self.style.apply(attr);
+        }
+        Ok(())
+    }
+
+    fn xtpushsgr_seq(&mut self, mask: StyleMask) -> Result<(), GenError> {
+        if self.stack.len() < MAX_PUSHED_STYLES {
+            self.stack.push((self.style, mask));
+        }
+        Ok(())
+    }
+
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> {
+        if let Some((saved, mask)) = self.stack.pop() {
+            self.style.restore(&saved, mask);
+        }
+        Ok(())
+    }
+
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        self.saved = self.style;
+        Ok(())
+    }
+
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        self.style = self.saved;
+        Ok(())
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        self.style = Style::default();
+        self.saved = Style::default();
+        self.stack.clear();
+        Ok(())
+    }
+}
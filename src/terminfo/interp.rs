@@ -0,0 +1,536 @@
+/*!
+Writing interpreted events back out in a particular terminal's dialect.
+*/
+use std::io::{self, Write};
+use ansisys::ScreenMode;
+use ansi::{parse_csi, AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use palette::Palette;
+use query::Query;
+use sgr::{Color, ColorDepth, SgrAttrs, UnderlineStyle};
+use style::{Style, StyleMask, MAX_PUSHED_STYLES};
+use super::Terminfo;
+
+/**
+An interpreter that writes every event out again using the capabilities of a terminal described by terminfo, rather than assuming it speaks ECMA-48.
+
+Where the terminal has a capability for something, such as `cup` for cursor positioning or `setaf` for colours, that's what gets written.  Where it doesn't, the writer makes do with what it has if it can: positioning the cursor by moving it from `home`, erasing a whole line as two halves, or resetting the attributes with `sgr0` to turn off one that has no capability of its own.  Anything else is dropped.
+
+Colours are squashed down to what `colors` says the terminal can show.  Terminals with `setrgbf` and `setrgbb`, or with `colors` of 2^24 or more for direct colour, get RGB colours as they are.
+
+Text is written unchanged, so character set designations are ignored; wrap the writer in a `CharsetTranslator` to have line drawing characters written as Unicode.  Alternate screens (mode 1049 and friends) use `smcup` and `rmcup`, and some common sequences the parser passes on as unknown, such as inserting and deleting lines, are translated too.  Padding in capabilities is left out, since terminals these days don't need it.
+*/
+pub struct TerminfoWriter<W>
+where W: Write {
+    out: W,
+    info: Terminfo,
+    palette: Palette,
+    depth: ColorDepth,
+    /// Whether `setaf` and `setab` take RGB values packed into one number.
+    direct: bool,
+    style: Style,
+    sgr_stack: Vec<(Style, StyleMask)>,
+}
+
+impl<W> TerminfoWriter<W>
+where W: Write {
+    pub fn new(out: W, info: Terminfo) -> Self {
+        let colors = info.get_number("colors").unwrap_or(0);
+        let direct = colors >= 0x100_0000;
+        let depth = if direct || (info.get_string("setrgbf").is_some() && info.get_string("setrgbb").is_some()) {
+            ColorDepth::TrueColor
+        } else if colors >= 256 {
+            ColorDepth::Indexed256
+        } else if colors >= 16 {
+            ColorDepth::Ansi16
+        } else if colors >= 8 {
+            ColorDepth::Ansi8
+        } else {
+            ColorDepth::Monochrome
+        };
+        TerminfoWriter {
+            out: out,
+            info: info,
+            palette: Palette::default(),
+            depth: depth,
+            direct: direct,
+            style: Style::default(),
+            sgr_stack: vec![],
+        }
+    }
+
+    /**
+    Set the palette used to pick the closest colour the terminal has.  This should match the terminal's actual colour table.
+    */
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn terminfo(&self) -> &Terminfo {
+        &self.info
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.info.get_string(name).is_some()
+    }
+
+    /// Write a capability, if the terminal has it and it expands properly.  Returns whether anything was written.
+    fn put(&mut self, name: &str, params: &[i32]) -> io::Result<bool> {
+        let cap = match self.info.expand(name, params) {
+            Some(Ok(cap)) => cap,
+            _ => return Ok(false)
+        };
+        try!(self.out.write_all(&without_padding(&cap)));
+        Ok(true)
+    }
+
+    /// Do something `n` times, with the capability that takes a count if there is one, or else the one that does it once.
+    fn put_n(&mut self, many: &str, one: &str, n: u16) -> io::Result<bool> {
+        if n == 0 {
+            return Ok(true);
+        }
+        if (n > 1 || !self.has(one)) && try!(self.put(many, &[n as i32])) {
+            return Ok(true);
+        }
+        self.put_times(one, n)
+    }
+
+    /// Write a capability that takes no parameters `n` times over.
+    fn put_times(&mut self, name: &str, n: u16) -> io::Result<bool> {
+        for _ in 0..n {
+            if !try!(self.put(name, &[])) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// The closest the terminal can get to `style`, leaving out anything it has no way to turn on.
+    fn supported(&self, style: Style) -> Style {
+        let color = |indexed, rgb, c| if self.has(indexed) || self.has(rgb) { self.reduce(c) } else { Color::Default };
+        Style {
+            fg: color("setaf", "setrgbf", style.fg),
+            bg: color("setab", "setrgbb", style.bg),
+            underline: if self.has("smul") { style.underline } else { UnderlineStyle::None },
+            bold: style.bold && self.has("bold"),
+            faint: style.faint && self.has("dim"),
+            italic: style.italic && self.has("sitm"),
+            blink: style.blink && self.has("blink"),
+            rapid_blink: style.rapid_blink && self.has("blink"),
+            reverse: style.reverse && self.has("rev"),
+            conceal: style.conceal && self.has("invis"),
+            crossed_out: style.crossed_out && self.has("smxx"),
+            ..style
+        }
+    }
+
+    /// Get the terminal from the current style to `target`.
+    fn set_style(&mut self, target: Style) -> io::Result<()> {
+        let target = self.supported(target);
+        let mut cur = self.style;
+        if cur == target {
+            return Ok(());
+        }
+
+        // Most attributes can only be turned off by turning everything off.
+        let off = |was: bool, is: bool, cap: Option<&str>| was && !is && match cap {
+            Some(cap) => !self.has(cap),
+            None => true
+        };
+        let reset = off(cur.bold, target.bold, None)
+            || off(cur.faint, target.faint, None)
+            || off(cur.blink || cur.rapid_blink, target.blink || target.rapid_blink, None)
+            || off(cur.reverse, target.reverse, None)
+            || off(cur.conceal, target.conceal, None)
+            || off(cur.italic, target.italic, Some("ritm"))
+            || off(cur.underline != UnderlineStyle::None, target.underline != UnderlineStyle::None, Some("rmul"))
+            || off(cur.crossed_out, target.crossed_out, Some("rmxx"))
+            || off(cur.fg != Color::Default, target.fg != Color::Default, Some("op"))
+            || off(cur.bg != Color::Default, target.bg != Color::Default, Some("op"));
+        if reset {
+            try!(self.put("sgr0", &[]));
+            cur = Style::default();
+        }
+
+        let changes = [
+            (cur.bold, target.bold, "bold", ""),
+            (cur.faint, target.faint, "dim", ""),
+            (cur.italic, target.italic, "sitm", "ritm"),
+            (cur.underline != UnderlineStyle::None, target.underline != UnderlineStyle::None, "smul", "rmul"),
+            (cur.blink || cur.rapid_blink, target.blink || target.rapid_blink, "blink", ""),
+            (cur.reverse, target.reverse, "rev", ""),
+            (cur.conceal, target.conceal, "invis", ""),
+            (cur.crossed_out, target.crossed_out, "smxx", "rmxx"),
+        ];
+        for &(was, is, on, off) in &changes {
+            if is && !was {
+                try!(self.put(on, &[]));
+            } else if was && !is {
+                try!(self.put(off, &[]));
+            }
+        }
+
+        // `op` resets both colours at once.
+        if (cur.fg != target.fg && target.fg == Color::Default) || (cur.bg != target.bg && target.bg == Color::Default) {
+            try!(self.put("op", &[]));
+            cur.fg = Color::Default;
+            cur.bg = Color::Default;
+        }
+        if cur.fg != target.fg {
+            try!(self.put_color("setaf", "setrgbf", target.fg));
+        }
+        if cur.bg != target.bg {
+            try!(self.put_color("setab", "setrgbb", target.bg));
+        }
+
+        self.style = target;
+        Ok(())
+    }
+
+    fn reduce(&self, c: Color) -> Color {
+        let c = self.palette.reduce(c, self.depth);
+        match c {
+            // Direct colour terminals only keep the first 8 palette entries.
+            Color::Indexed(n) if self.direct && n >= 8 => match self.palette.rgb(c) {
+                Some((r, g, b)) => Color::Rgb(r, g, b),
+                None => c
+            },
+            _ => c
+        }
+    }
+
+    fn put_color(&mut self, indexed: &str, rgb: &str, c: Color) -> io::Result<bool> {
+        match c {
+            Color::Default => Ok(true),
+            Color::Indexed(n) => self.put(indexed, &[n as i32]),
+            Color::Rgb(r, g, b) if self.direct => self.put(indexed, &[(r as i32) << 16 | (g as i32) << 8 | b as i32]),
+            Color::Rgb(r, g, b) => self.put(rgb, &[r as i32, g as i32, b as i32]),
+        }
+    }
+
+    /// Translate the handful of unparsed control sequences that terminfo has capabilities for.
+    fn csi_seq(&mut self, private: bool, params: &[u16], final_byte: u8) -> io::Result<bool> {
+        let n = params.first().cloned().unwrap_or(0).max(1);
+        match (private, final_byte) {
+            (true, b'h') | (true, b'l') => {
+                let cap = if final_byte == b'h' { "smcup" } else { "rmcup" };
+                match params {
+                    [47] | [1047] | [1049] => self.put(cap, &[]),
+                    _ => Ok(false)
+                }
+            },
+            (true, _) => Ok(false),
+            (false, b'@') => self.put_n("ich", "ich1", n),
+            (false, b'P') => self.put_n("dch", "dch1", n),
+            (false, b'L') => self.put_n("il", "il1", n),
+            (false, b'M') => self.put_n("dl", "dl1", n),
+            (false, b'X') => self.put("ech", &[n as i32]),
+            (false, b'S') => self.put_n("indn", "ind", n),
+            (false, b'T') => self.put_n("rin", "ri", n),
+            (false, b'Z') => self.put_times("cbt", n),
+            (false, b'G') | (false, b'`') => self.put("hpa", &[n as i32 - 1]),
+            (false, b'd') => self.put("vpa", &[n as i32 - 1]),
+            (false, b'r') => {
+                let lines = self.info.get_number("lines").unwrap_or(24);
+                let top = params.first().cloned().unwrap_or(0).max(1) as i32;
+                let bottom = match params.get(1).cloned().unwrap_or(0) {
+                    0 => lines,
+                    b => b as i32,
+                };
+                self.put("csr", &[top - 1, bottom - 1])
+            },
+            _ => Ok(false)
+        }
+    }
+}
+
+impl<W> AnsiInterpret for TerminfoWriter<W>
+where W: Write {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn cuu_seq(&mut self, r: u16) -> Result<(), GenError> {
+        try!(self.put_n("cuu", "cuu1", r));
+        Ok(())
+    }
+
+    fn cud_seq(&mut self, r: u16) -> Result<(), GenError> {
+        try!(self.put_n("cud", "cud1", r));
+        Ok(())
+    }
+
+    fn cuf_seq(&mut self, c: u16) -> Result<(), GenError> {
+        try!(self.put_n("cuf", "cuf1", c));
+        Ok(())
+    }
+
+    fn cub_seq(&mut self, c: u16) -> Result<(), GenError> {
+        try!(self.put_n("cub", "cub1", c));
+        Ok(())
+    }
+
+    fn cup_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
+        let (r, c) = (r.max(1) - 1, c.max(1) - 1);
+        if try!(self.put("cup", &[r as i32, c as i32])) {
+            return Ok(());
+        }
+        if self.has("vpa") && self.has("hpa") {
+            try!(self.put("vpa", &[r as i32]));
+            try!(self.put("hpa", &[c as i32]));
+            return Ok(());
+        }
+        if try!(self.put("home", &[])) {
+            try!(self.put_n("cud", "cud1", r));
+            try!(self.put_n("cuf", "cuf1", c));
+        }
+        Ok(())
+    }
+
+    fn ed_seq(&mut self, n: EraseDisplay) -> Result<(), GenError> {
+        match n {
+            EraseDisplay::CursorToBottom => {
+                try!(self.put("ed", &[]));
+            },
+            // There's no capability for this.
+            EraseDisplay::TopToCursor => (),
+            EraseDisplay::All => {
+                if ["sc", "rc", "home", "ed"].iter().all(|cap| self.has(cap)) {
+                    for cap in &["sc", "home", "ed", "rc"] {
+                        try!(self.put(cap, &[]));
+                    }
+                } else {
+                    // This homes the cursor as well, but it's the best there is.
+                    try!(self.put("clear", &[]));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn el_seq(&mut self, n: EraseLine) -> Result<(), GenError> {
+        match n {
+            EraseLine::CursorToEnd => {
+                try!(self.put("el", &[]));
+            },
+            EraseLine::StartToCursor => {
+                try!(self.put("el1", &[]));
+            },
+            EraseLine::All => {
+                try!(self.put("el1", &[]));
+                try!(self.put("el", &[]));
+            },
+        }
+        Ok(())
+    }
+
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> {
+        let mut style = self.style;
+        for attr in attrs {
+            style.apply(attr);
+        }
+        rethrow!(self.set_style(style))
+    }
+
+    fn xtpushsgr_seq(&mut self, mask: StyleMask) -> Result<(), GenError> {
+        if self.sgr_stack.len() < MAX_PUSHED_STYLES {
+            self.sgr_stack.push((self.style, mask));
+        }
+        Ok(())
+    }
+
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> {
+        let (saved, mask) = match self.sgr_stack.pop() {
+            Some(v) => v,
+            None => return Ok(())
+        };
+        let mut style = self.style;
+        style.restore(&saved, mask);
+        rethrow!(self.set_style(style))
+    }
+
+    fn dsr_seq(&mut self) -> Result<(), GenError> {
+        // By convention, `u7` is the cursor position request and `u6` the format of the reply.
+        try!(self.put("u7", &[]));
+        Ok(())
+    }
+
+    fn scp_seq(&mut self) -> Result<(), GenError> {
+        try!(self.put("sc", &[]));
+        Ok(())
+    }
+
+    fn rcp_seq(&mut self) -> Result<(), GenError> {
+        try!(self.put("rc", &[]));
+        Ok(())
+    }
+
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        self.scp_seq()
+    }
+
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        self.rcp_seq()
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        if !try!(self.put("rs1", &[])) {
+            try!(self.put("sgr0", &[]));
+            try!(self.put("clear", &[]));
+        }
+        self.style = Style::default();
+        self.sgr_stack.clear();
+        Ok(())
+    }
+
+    fn deckpam_seq(&mut self) -> Result<(), GenError> {
+        try!(self.put("smkx", &[]));
+        Ok(())
+    }
+
+    fn deckpnm_seq(&mut self) -> Result<(), GenError> {
+        try!(self.put("rmkx", &[]));
+        Ok(())
+    }
+
+    fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool) -> Result<(), GenError> {
+        let n = match shape {
+            CursorShape::Block => 1,
+            CursorShape::Underline => 3,
+            CursorShape::Bar => 5,
+        } + if blinking { 0 } else { 1 };
+        try!(self.put("Ss", &[n]));
+        Ok(())
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), GenError> {
+        try!(self.put(if visible { "cnorm" } else { "civis" }, &[]));
+        Ok(())
+    }
+
+    fn set_line_wrap(&mut self, wrap: bool) -> Result<(), GenError> {
+        try!(self.put(if wrap { "smam" } else { "rmam" }, &[]));
+        Ok(())
+    }
+
+    fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool) -> Result<(), GenError> {
+        match mode {
+            ScreenMode::LineWrap => self.set_line_wrap(set),
+            _ => Ok(())
+        }
+    }
+
+    fn query_seq(&mut self, q: Query) -> Result<(), GenError> {
+        match q {
+            Query::CursorPosition => self.dsr_seq(),
+            // And `u9` is the device attributes request.
+            Query::PrimaryDeviceAttributes => {
+                try!(self.put("u9", &[]));
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    fn osc_txt_seq(&mut self, n: u16, txt: &str) -> Result<(), GenError> {
+        if (n == 0 || n == 2) && self.has("tsl") && self.has("fsl") {
+            try!(self.put("tsl", &[0]));
+            try!(self.out.write_all(txt.as_bytes()));
+            try!(self.put("fsl", &[]));
+        }
+        Ok(())
+    }
+
+    fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
+        if let Some((private, params, final_byte)) = parse_csi(bytes) {
+            try!(self.csi_seq(private, &params, final_byte));
+        }
+        Ok(())
+    }
+}
+
+/// Take out the `$<...>` delays in a capability.
+fn without_padding(cap: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(cap.len());
+    let mut i = 0;
+    while i < cap.len() {
+        if cap[i..].starts_with(b"$<") {
+            if let Some(end) = cap[i..].iter().position(|&b| b == b'>') {
+                i += end + 1;
+                continue;
+            }
+        }
+        out.push(cap[i]);
+        i += 1;
+    }
+    out
+}
+
+#[test]
+fn test_terminfo_writer() {
+    use ansi::AnsiIntercept;
+    use super::compile;
+
+    fn rewrite(info: &Terminfo, input: &str) -> String {
+        let mut out = AnsiIntercept::new(TerminfoWriter::new(vec![], info.clone()));
+        out.write_all(input.as_bytes()).unwrap();
+        String::from_utf8(out.get_ref().get_ref().clone()).unwrap()
+    }
+
+    // A terminal that isn't ECMA-48 at all.
+    let adm = Terminfo::parse(&compile("adm", false, &[], &[("lines", 24)], &[
+        ("cup", "\x1b=%p1%' '%+%c%p2%' '%+%c$<2>"), ("home", "\x1e"), ("cuf1", "\x0c"), ("cud1", "\n"),
+        ("el", "\x1bT"), ("clear", "\x1a$<5>"), ("rev", "\x1bG4"), ("sgr0", "\x1bG0"), ("sc", "\x1b7"),
+    ])).unwrap();
+    assert_eq!(rewrite(&adm, "\x1b[2;3Hhi\x1b[K\x1b[2J"), "\x1b=!\"hi\x1bT\x1a");
+    assert_eq!(rewrite(&adm, "\x1b[7mx\x1b[27my\x1b[1;4mz\x1b[0m"), "\x1bG4x\x1bG0yz");
+    assert_eq!(rewrite(&adm, "\x1b[1J\x1b[6n\x1b[?1049h\x1b[2L\x1b]0;t\x07."), ".");
+
+    // Without `cup`, the cursor goes home and walks from there.
+    let dumb = Terminfo::parse(&compile("dumb", false, &[], &[], &[("home", "\x1e"), ("cuf1", "\x0c"), ("cud1", "\n")])).unwrap();
+    assert_eq!(rewrite(&dumb, "\x1b[3;2H"), "\x1e\n\n\x0c");
+
+    // Something much like xterm, with a few extended capabilities.
+    let xterm = Terminfo::parse(&compile("xterm-ish", false, &[], &[("colors", 8), ("lines", 24)], &[
+        ("cup", "\x1b[%i%p1%d;%p2%dH"), ("cuu", "\x1b[%p1%dA"), ("cuu1", "\x1b[A"), ("ed", "\x1b[J"), ("el", "\x1b[K"),
+        ("el1", "\x1b[1K"), ("home", "\x1b[H"), ("sc", "\x1b7"), ("rc", "\x1b8"), ("clear", "\x1b[H\x1b[2J"),
+        ("bold", "\x1b[1m"), ("sitm", "\x1b[3m"), ("ritm", "\x1b[23m"), ("smul", "\x1b[4m"), ("rmul", "\x1b[24m"),
+        ("sgr0", "\x1b(B\x1b[m"), ("op", "\x1b[39;49m"), ("setaf", "\x1b[3%p1%dm"), ("setab", "\x1b[4%p1%dm"),
+        ("smcup", "\x1b[?1049h"), ("rmcup", "\x1b[?1049l"), ("il", "\x1b[%p1%dL"), ("il1", "\x1b[L"), ("csr", "\x1b[%i%p1%d;%p2%dr"),
+        ("u7", "\x1b[6n"), ("u9", "\x1b[c"), ("Ss", "\x1b[%p1%d q"), ("civis", "\x1b[?25l"), ("cbt", "\x1b[Z"),
+    ])).unwrap();
+    assert_eq!(rewrite(&xterm, "\x1b[A\x1b[3A\x1b[2J\x1b[2K"), "\x1b[A\x1b[3A\x1b7\x1b[H\x1b[J\x1b8\x1b[1K\x1b[K");
+    assert_eq!(rewrite(&xterm, "\x1b[1;3;4;91;44ma\x1b[23;24mb\x1b[39mc\x1b[22md"),
+        "\x1b[1m\x1b[3m\x1b[4m\x1b[31m\x1b[44ma\x1b[23m\x1b[24mb\x1b[39;49m\x1b[44mc\x1b(B\x1b[m\x1b[44md");
+    assert_eq!(rewrite(&xterm, "\x1b[38;2;0;128;0m\x1b[#{\x1b[1;35mx\x1b[#}y"), "\x1b[32m\x1b[1m\x1b[35mx\x1b(B\x1b[m\x1b[32my");
+    assert_eq!(rewrite(&xterm, "\x1b[?1049h\x1b[L\x1b[3L\x1b[5r\x1b[?1049l"), "\x1b[?1049h\x1b[L\x1b[3L\x1b[5;24r\x1b[?1049l");
+    assert_eq!(rewrite(&xterm, "\x1b[6n\x1b[c\x1b[>c\x1b[4 q\x1b[?25l\x1b[?25h"), "\x1b[6n\x1b[c\x1b[4 q\x1b[?25l");
+    assert_eq!(rewrite(&xterm, "\x1b[?25;1049h\x1b[?1049;25l"), "\x1b[?1049h\x1b[?1049l\x1b[?25l");
+    assert_eq!(rewrite(&xterm, "\x1b[Z\x1b[3Z"), "\x1b[Z\x1b[Z\x1b[Z\x1b[Z");
+
+    // RGB colours go through as they are to terminals that can take them.
+    let direct = Terminfo::parse(&compile("direct", true, &[], &[("colors", 0x100_0000)], &[
+        ("setaf", "\x1b[%?%p1%{8}%<%t3%p1%d%e38:2::%p1%{65536}%/%d:%p1%{256}%/%{255}%&%d:%p1%{255}%&%d%;m"),
+    ])).unwrap();
+    assert_eq!(rewrite(&direct, "\x1b[38;2;1;2;3ma\x1b[31mb\x1b[91mc"), "\x1b[38:2::1:2:3ma\x1b[31mb\x1b[38:2::255:0:0mc");
+    let rgb = Terminfo::parse(&compile("rgb", false, &[], &[("colors", 256)], &[
+        ("setaf", "\x1b[38;5;%p1%dm"), ("setab", "\x1b[48;5;%p1%dm"),
+        ("setrgbf", "\x1b[38;2;%p1%d;%p2%d;%p3%dm"), ("setrgbb", "\x1b[48;2;%p1%d;%p2%d;%p3%dm"),
+    ])).unwrap();
+    assert_eq!(rewrite(&rgb, "\x1b[38;2;1;2;3;41m"), "\x1b[38;2;1;2;3m\x1b[48;5;1m");
+}
@@ -0,0 +1,459 @@
+/*!
+Reading compiled terminfo entries, and writing escape sequences the way they say a terminal wants them.
+*/
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use self::names::{BOOL_NAMES, NUM_NAMES, STR_NAMES};
+
+pub use self::interp::TerminfoWriter;
+pub use self::param::{tparm, InvalidCapability};
+
+mod interp;
+mod names;
+mod param;
+
+/// The magic number of the original format, with 16-bit numbers.
+const MAGIC_LEGACY: u16 = 0o432;
+/// The magic number of the format ncurses 6.1 introduced, with 32-bit numbers.
+const MAGIC_32BIT: u16 = 0o1036;
+
+marker_error! {
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct InvalidTerminfo
+    impl {
+        desc {"invalid compiled terminfo entry"}
+    }
+}
+
+/**
+The capabilities of one terminal, as read from a compiled terminfo entry.
+
+Both the original format and the one with 32-bit numbers are understood, along with the extended capabilities that ncurses appends to an entry, such as `Ss` or `RGB`.  Standard and extended capabilities are looked up the same way, by their short names.  Cancelled capabilities are treated as missing.
+*/
+#[derive(Clone, Debug, Default)]
+pub struct Terminfo {
+    names: Vec<String>,
+    bools: HashSet<String>,
+    numbers: HashMap<String, i32>,
+    strings: HashMap<String, Vec<u8>>,
+}
+
+impl Terminfo {
+    /**
+    Find and read the entry for the terminal named by `TERM`.
+    */
+    pub fn from_env() -> io::Result<Terminfo> {
+        match env::var("TERM") {
+            Ok(term) => Terminfo::from_name(&term),
+            Err(_) => Err(io::Error::new(io::ErrorKind::NotFound, "TERM is not set"))
+        }
+    }
+
+    /**
+    Find and read the entry for the terminal `name`.
+
+    This looks in the same places curses does: the directory named by `TERMINFO`, `~/.terminfo`, the directories listed in `TERMINFO_DIRS`, and then `/etc/terminfo`, `/lib/terminfo`, `/usr/share/terminfo` and `/usr/lib/terminfo`.  Entries can be filed under their first letter, or its hex code as on macOS.
+    */
+    pub fn from_name(name: &str) -> io::Result<Terminfo> {
+        if name.is_empty() || name.contains('/') || name.starts_with('.') {
+            throw!(io::Error::new(io::ErrorKind::InvalidInput, "invalid terminal name"));
+        }
+        let first = name.as_bytes()[0];
+        for dir in search_dirs() {
+            for sub in &[(first as char).to_string(), format!("{:02x}", first)] {
+                let path = dir.join(sub).join(name);
+                if path.is_file() {
+                    return Terminfo::from_path(path);
+                }
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("no terminfo entry for {:?}", name)))
+    }
+
+    /// Read the compiled entry at `path`.
+    pub fn from_path<P>(path: P) -> io::Result<Terminfo>
+    where P: AsRef<Path> {
+        let mut data = vec![];
+        try!(try!(File::open(path)).read_to_end(&mut data));
+        Terminfo::parse(&data).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Parse a compiled entry.
+    pub fn parse(data: &[u8]) -> Result<Terminfo, InvalidTerminfo> {
+        let mut r = Reader { data: data, pos: 0 };
+        let magic = try!(r.u16());
+        let wide = match magic {
+            MAGIC_LEGACY => false,
+            MAGIC_32BIT => true,
+            _ => throw!(InvalidTerminfo)
+        };
+        let names_size = try!(r.count());
+        let bool_count = try!(r.count());
+        let num_count = try!(r.count());
+        let str_count = try!(r.count());
+        let table_size = try!(r.count());
+
+        let names = try!(r.bytes(names_size));
+        let names = String::from_utf8_lossy(names.split(|&b| b == 0).next().unwrap_or(&[]));
+        let mut info = Terminfo {
+            names: names.split('|').map(String::from).collect(),
+            ..Terminfo::default()
+        };
+
+        let bools = try!(r.bytes(bool_count));
+        r.align();
+        let numbers = try!(r.numbers(num_count, wide));
+        let offsets = try!(r.numbers(str_count, false));
+        let table = try!(r.bytes(table_size));
+        for (&name, &b) in BOOL_NAMES.iter().zip(bools) {
+            info.set_bool(name, b);
+        }
+        for (&name, &n) in NUM_NAMES.iter().zip(&numbers) {
+            info.set_number(name, n);
+        }
+        for (&name, &offset) in STR_NAMES.iter().zip(&offsets) {
+            if let Some(s) = try!(string_at(table, offset)) {
+                info.strings.insert(String::from(name), s.to_vec());
+            }
+        }
+
+        // Extended capabilities, if there are any, follow on from the string table with their own header.
+        r.align();
+        if r.pos < data.len() {
+            try!(info.parse_extended(&mut r, wide));
+        }
+        Ok(info)
+    }
+
+    /**
+    Read the extended capabilities.  These are laid out like the standard ones, except that their names are kept in the string table too, after the values.
+    */
+    fn parse_extended(&mut self, r: &mut Reader, wide: bool) -> Result<(), InvalidTerminfo> {
+        let bool_count = try!(r.count());
+        let num_count = try!(r.count());
+        let str_count = try!(r.count());
+        let _offset_count = try!(r.count());
+        let table_size = try!(r.count());
+
+        let bools = try!(r.bytes(bool_count));
+        r.align();
+        let numbers = try!(r.numbers(num_count, wide));
+        let offsets = try!(r.numbers(str_count, false));
+        let name_offsets = try!(r.numbers(bool_count + num_count + str_count, false));
+        let table = try!(r.bytes(table_size));
+
+        // The names start just after the last value.
+        let mut values = vec![];
+        let mut names_start = 0;
+        for &offset in &offsets {
+            let value = try!(string_at(table, offset));
+            if let Some(value) = value {
+                names_start = names_start.max(offset as usize + value.len() + 1);
+            }
+            values.push(value);
+        }
+        let names_table = &table[names_start.min(table.len())..];
+        let mut names = vec![];
+        for &offset in &name_offsets {
+            match try!(string_at(names_table, offset)) {
+                Some(name) => names.push(String::from_utf8_lossy(name).into_owned()),
+                None => throw!(InvalidTerminfo)
+            }
+        }
+
+        let (bool_names, rest) = names.split_at(bool_count);
+        let (num_names, str_names) = rest.split_at(num_count);
+        for (name, &b) in bool_names.iter().zip(bools) {
+            self.set_bool(name, b);
+        }
+        for (name, &n) in num_names.iter().zip(&numbers) {
+            self.set_number(name, n);
+        }
+        for (name, value) in str_names.iter().zip(values) {
+            match value {
+                Some(value) => {
+                    self.strings.insert(name.clone(), value.to_vec());
+                },
+                None => {
+                    self.strings.remove(name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn set_bool(&mut self, name: &str, b: u8) {
+        if b == 1 {
+            self.bools.insert(String::from(name));
+        } else {
+            self.bools.remove(name);
+        }
+    }
+
+    fn set_number(&mut self, name: &str, n: i32) {
+        if n >= 0 {
+            self.numbers.insert(String::from(name), n);
+        } else {
+            self.numbers.remove(name);
+        }
+    }
+
+    /// The names the terminal goes by, starting with the one the entry is filed under and usually ending with a description.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Whether the terminal has a boolean capability, such as `am` or `bce`.
+    pub fn get_bool(&self, name: &str) -> bool {
+        self.bools.contains(name)
+    }
+
+    /// The value of a numeric capability, such as `colors` or `cols`.
+    pub fn get_number(&self, name: &str) -> Option<i32> {
+        self.numbers.get(name).cloned()
+    }
+
+    /// The value of a string capability, such as `cup` or `smcup`, with its parameters and padding not filled in.
+    pub fn get_string(&self, name: &str) -> Option<&[u8]> {
+        self.strings.get(name).map(|s| &**s)
+    }
+
+    /**
+    Expand a string capability with the given parameters, as with `tparm`.
+
+    Returns `None` if the terminal doesn't have the capability.
+    */
+    pub fn expand(&self, name: &str, params: &[i32]) -> Option<Result<Vec<u8>, InvalidCapability>> {
+        self.get_string(name).map(|cap| tparm(cap, params))
+    }
+}
+
+/// Where to look for compiled entries, in order.
+fn search_dirs() -> Vec<PathBuf> {
+    let defaults = ["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo", "/usr/lib/terminfo"];
+    let mut dirs = vec![];
+    if let Some(dir) = env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+    if let Some(home) = env::var_os("HOME") {
+        dirs.push(Path::new(&home).join(".terminfo"));
+    }
+    if let Some(list) = env::var_os("TERMINFO_DIRS") {
+        for dir in env::split_paths(&list) {
+            // An empty entry stands for the system directories.
+            if dir.as_os_str().is_empty() {
+                dirs.extend(defaults.iter().map(PathBuf::from));
+            } else {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs.extend(defaults.iter().map(PathBuf::from));
+    dirs
+}
+
+/// The NUL-terminated string at `offset` in `table`.  Negative offsets mean the capability is missing or cancelled.
+fn string_at(table: &[u8], offset: i32) -> Result<Option<&[u8]>, InvalidTerminfo> {
+    if offset < 0 {
+        return Ok(None);
+    }
+    let rest = match table.get(offset as usize..) {
+        Some(rest) => rest,
+        None => throw!(InvalidTerminfo)
+    };
+    match rest.iter().position(|&b| b == 0) {
+        Some(end) => Ok(Some(&rest[..end])),
+        None => Err(InvalidTerminfo)
+    }
+}
+
+/// Reads the little-endian fields of a compiled entry.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], InvalidTerminfo> {
+        match self.data.get(self.pos..self.pos + len) {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            },
+            None => Err(InvalidTerminfo)
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16, InvalidTerminfo> {
+        let b = try!(self.bytes(2));
+        Ok(b[0] as u16 | (b[1] as u16) << 8)
+    }
+
+    /// A size or count from a header, which can't be negative.
+    fn count(&mut self) -> Result<usize, InvalidTerminfo> {
+        let n = try!(self.u16()) as i16;
+        if n < 0 {
+            throw!(InvalidTerminfo);
+        }
+        Ok(n as usize)
+    }
+
+    /// A run of signed numbers, 16 or 32 bits wide.
+    fn numbers(&mut self, count: usize, wide: bool) -> Result<Vec<i32>, InvalidTerminfo> {
+        let width = if wide { 4 } else { 2 };
+        let bytes = try!(self.bytes(count * width));
+        Ok(bytes.chunks(width)
+            .map(|b| if wide {
+                (b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24) as i32
+            } else {
+                (b[0] as u16 | (b[1] as u16) << 8) as i16 as i32
+            })
+            .collect())
+    }
+
+    /// Skip the padding byte that keeps what follows at an even offset.
+    fn align(&mut self) {
+        if self.pos % 2 == 1 {
+            self.pos += 1;
+        }
+    }
+}
+
+/**
+Build a compiled entry, for testing.  Capabilities with a standard name go in the standard sections and anything else is extended.
+*/
+#[cfg(test)]
+fn compile(names: &str, wide: bool, bools: &[&str], numbers: &[(&str, i32)], strings: &[(&str, &str)]) -> Vec<u8> {
+    fn put16(out: &mut Vec<u8>, n: i32) {
+        out.push(n as u8);
+        out.push((n >> 8) as u8);
+    }
+    fn put_number(out: &mut Vec<u8>, n: i32, wide: bool) {
+        put16(out, n);
+        if wide {
+            put16(out, n >> 16);
+        }
+    }
+    fn align(out: &mut Vec<u8>) {
+        if out.len() % 2 == 1 {
+            out.push(0);
+        }
+    }
+    let index = |list: &[&str], name: &str| list.iter().position(|&n| n == name);
+
+    let std_bools: Vec<usize> = bools.iter().filter_map(|&b| index(BOOL_NAMES, b)).collect();
+    let std_numbers: Vec<(usize, i32)> = numbers.iter().filter_map(|&(k, v)| index(NUM_NAMES, k).map(|i| (i, v))).collect();
+    let std_strings: Vec<(usize, &str)> = strings.iter().filter_map(|&(k, v)| index(STR_NAMES, k).map(|i| (i, v))).collect();
+    let ext_bools: Vec<&str> = bools.iter().cloned().filter(|&b| index(BOOL_NAMES, b).is_none()).collect();
+    let ext_numbers: Vec<(&str, i32)> = numbers.iter().cloned().filter(|&(k, _)| index(NUM_NAMES, k).is_none()).collect();
+    let ext_strings: Vec<(&str, &str)> = strings.iter().cloned().filter(|&(k, _)| index(STR_NAMES, k).is_none()).collect();
+
+    let bool_count = std_bools.iter().map(|&i| i + 1).max().unwrap_or(0);
+    let num_count = std_numbers.iter().map(|&(i, _)| i + 1).max().unwrap_or(0);
+    let str_count = std_strings.iter().map(|&(i, _)| i + 1).max().unwrap_or(0);
+    let mut offsets = vec![-1; str_count];
+    let mut table = vec![];
+    for &(i, s) in &std_strings {
+        offsets[i] = table.len() as i32;
+        table.extend(s.bytes());
+        table.push(0);
+    }
+
+    let mut out = vec![];
+    put16(&mut out, if wide { MAGIC_32BIT } else { MAGIC_LEGACY } as i32);
+    for &n in &[names.len() + 1, bool_count, num_count, str_count, table.len()] {
+        put16(&mut out, n as i32);
+    }
+    out.extend(names.bytes());
+    out.push(0);
+    out.extend((0..bool_count).map(|i| std_bools.contains(&i) as u8));
+    align(&mut out);
+    for i in 0..num_count {
+        let n = std_numbers.iter().find(|&&(j, _)| j == i).map_or(-1, |&(_, n)| n);
+        put_number(&mut out, n, wide);
+    }
+    for &offset in &offsets {
+        put16(&mut out, offset);
+    }
+    out.extend(table);
+
+    if ext_bools.is_empty() && ext_numbers.is_empty() && ext_strings.is_empty() {
+        return out;
+    }
+    align(&mut out);
+    let mut values = vec![];
+    let mut value_offsets = vec![];
+    for &(_, v) in &ext_strings {
+        value_offsets.push(values.len() as i32);
+        values.extend(v.bytes());
+        values.push(0);
+    }
+    let ext_names: Vec<&str> = ext_bools.iter().cloned()
+        .chain(ext_numbers.iter().map(|&(k, _)| k))
+        .chain(ext_strings.iter().map(|&(k, _)| k))
+        .collect();
+    let mut names = vec![];
+    let mut name_offsets = vec![];
+    for name in ext_names {
+        name_offsets.push(names.len() as i32);
+        names.extend(name.bytes());
+        names.push(0);
+    }
+    for &n in &[ext_bools.len(), ext_numbers.len(), ext_strings.len(), ext_strings.len() + name_offsets.len(), values.len() + names.len()] {
+        put16(&mut out, n as i32);
+    }
+    out.extend(ext_bools.iter().map(|_| 1));
+    align(&mut out);
+    for &(_, n) in &ext_numbers {
+        put_number(&mut out, n, wide);
+    }
+    for &offset in value_offsets.iter().chain(&name_offsets) {
+        put16(&mut out, offset);
+    }
+    out.extend(values);
+    out.extend(names);
+    out
+}
+
+#[test]
+fn test_terminfo_parse() {
+    for &wide in &[false, true] {
+        let colors = if wide { 0x1000000 } else { 256 };
+        let cup = ("cup", "\x1b[%i%p1%d;%p2%dH");
+        let data = compile("test|a test terminal", wide, &["am", "xenl", "AX"], &[("cols", 80), ("colors", colors), ("U8", 1)],
+            &[cup, ("sgr0", "\x1b[m"), ("Ss", "\x1b[%p1%d q"), ("Se", "\x1b[2 q")]);
+        let standard = compile("test|a test terminal", wide, &["am", "xenl"], &[("cols", 80), ("colors", colors)],
+            &[cup, ("sgr0", "\x1b[m")]);
+        let info = Terminfo::parse(&data).unwrap();
+        assert_eq!(info.names(), &["test", "a test terminal"]);
+        assert!(info.get_bool("am") && info.get_bool("xenl") && info.get_bool("AX"));
+        assert!(!info.get_bool("bw") && !info.get_bool("XT"));
+        assert_eq!(info.get_number("cols"), Some(80));
+        assert_eq!(info.get_number("colors"), Some(if wide { 0x1000000 } else { 256 }));
+        assert_eq!(info.get_number("U8"), Some(1));
+        assert_eq!(info.get_number("lines"), None);
+        assert_eq!(info.get_string("sgr0"), Some(&b"\x1b[m"[..]));
+        assert_eq!(info.get_string("Se"), Some(&b"\x1b[2 q"[..]));
+        assert_eq!(info.get_string("clear"), None);
+        assert_eq!(info.expand("cup", &[0, 9]), Some(Ok(b"\x1b[1;10H".to_vec())));
+        assert_eq!(info.expand("Ss", &[4]), Some(Ok(b"\x1b[4 q".to_vec())));
+        assert_eq!(info.expand("smcup", &[]), None);
+
+        // Anything cut short is rejected, unless it's the extended capabilities that have gone.
+        for len in 0..data.len() {
+            if len == standard.len() || len == standard.len() + 1 {
+                assert!(Terminfo::parse(&data[..len]).unwrap().get_string("Ss").is_none());
+            } else {
+                assert_eq!(Terminfo::parse(&data[..len]).unwrap_err(), InvalidTerminfo);
+            }
+        }
+    }
+
+    let mut data = compile("test", false, &[], &[], &[]);
+    data[0] = 0;
+    assert_eq!(Terminfo::parse(&data).unwrap_err(), InvalidTerminfo);
+}
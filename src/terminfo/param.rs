@@ -0,0 +1,304 @@
+/*!
+Filling in the parameters of terminfo string capabilities.
+*/
+use std::error::Error;
+
+marker_error! {
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct InvalidCapability
+    impl {
+        desc {"invalid parameterized capability"}
+    }
+}
+
+/**
+Expand a parameterized string capability, such as `cup` or `setaf`, with numeric parameters, the way curses' `tparm` does.
+
+This understands the whole `%` language from terminfo(5): pushing parameters and constants, arithmetic and logic, variables, conditionals and `printf`-style output.  Static and dynamic variables alike only last for the one expansion, and string parameters aren't supported, so `%s` and `%l` treat their argument as a number written out in decimal.  Popping from an empty stack gives 0, and widths or precisions over 1024 make the capability invalid.
+
+Padding, written as `$<5>` and the like, is left in for the caller to deal with.
+*/
+pub fn tparm(cap: &[u8], params: &[i32]) -> Result<Vec<u8>, InvalidCapability> {
+    let mut params: Vec<i32> = params.iter().cloned().chain(Some(0).into_iter().cycle()).take(9).collect();
+    let mut stack: Vec<i32> = vec![];
+    let mut vars = [0; 52];
+    let mut out = vec![];
+
+    let mut i = 0;
+    while i < cap.len() {
+        let b = cap[i];
+        i += 1;
+        if b != b'%' {
+            out.push(b);
+            continue;
+        }
+
+        let op = match cap.get(i) {
+            Some(&op) => op,
+            None => throw!(InvalidCapability)
+        };
+        i += 1;
+        match op {
+            b'%' => out.push(b'%'),
+            b'c' => out.push(pop(&mut stack) as u8),
+            b'p' => {
+                let n = match cap.get(i) {
+                    Some(&n @ b'1'...b'9') => (n - b'1') as usize,
+                    _ => throw!(InvalidCapability)
+                };
+                i += 1;
+                stack.push(params[n]);
+            },
+            b'P' | b'g' => {
+                let var = match cap.get(i) {
+                    Some(&v @ b'a'...b'z') => (v - b'a') as usize,
+                    Some(&v @ b'A'...b'Z') => 26 + (v - b'A') as usize,
+                    _ => throw!(InvalidCapability)
+                };
+                i += 1;
+                if op == b'P' {
+                    vars[var] = pop(&mut stack);
+                } else {
+                    stack.push(vars[var]);
+                }
+            },
+            b'\'' => {
+                match (cap.get(i), cap.get(i + 1)) {
+                    (Some(&c), Some(&b'\'')) => stack.push(c as i32),
+                    _ => throw!(InvalidCapability)
+                }
+                i += 2;
+            },
+            b'{' => {
+                let end = match cap[i..].iter().position(|&b| b == b'}') {
+                    Some(end) => i + end,
+                    None => throw!(InvalidCapability)
+                };
+                let n = match ::std::str::from_utf8(&cap[i..end]).ok().and_then(|s| s.parse().ok()) {
+                    Some(n) => n,
+                    None => throw!(InvalidCapability)
+                };
+                stack.push(n);
+                i = end + 1;
+            },
+            b'l' => {
+                let n = pop(&mut stack);
+                stack.push(n.to_string().len() as i32);
+            },
+            b'+' | b'-' | b'*' | b'/' | b'm' | b'&' | b'|' | b'^' | b'=' | b'>' | b'<' | b'A' | b'O' => {
+                let y = pop(&mut stack);
+                let x = pop(&mut stack);
+                stack.push(match op {
+                    b'+' => x.wrapping_add(y),
+                    b'-' => x.wrapping_sub(y),
+                    b'*' => x.wrapping_mul(y),
+                    b'/' => if y == 0 { 0 } else { x.wrapping_div(y) },
+                    b'm' => if y == 0 { 0 } else { x.wrapping_rem(y) },
+                    b'&' => x & y,
+                    b'|' => x | y,
+                    b'^' => x ^ y,
+                    b'=' => (x == y) as i32,
+                    b'>' => (x > y) as i32,
+                    b'<' => (x < y) as i32,
+                    b'A' => (x != 0 && y != 0) as i32,
+                    _ => (x != 0 || y != 0) as i32,
+                });
+            },
+            b'!' => {
+                let x = pop(&mut stack);
+                stack.push((x == 0) as i32);
+            },
+            b'~' => {
+                let x = pop(&mut stack);
+                stack.push(!x);
+            },
+            b'i' => {
+                params[0] = params[0].wrapping_add(1);
+                params[1] = params[1].wrapping_add(1);
+            },
+            b'?' | b';' => (),
+            b't' => {
+                if pop(&mut stack) == 0 {
+                    // Go on to whatever comes after the next `%e` or the end of the conditional.
+                    i = skip_branch(cap, i, true);
+                }
+            },
+            // Reached the end of a branch that was taken.
+            b'e' => i = skip_branch(cap, i, false),
+            _ => {
+                let (spec, end) = match Spec::parse(cap, i - 1) {
+                    Some(spec) => spec,
+                    None => throw!(InvalidCapability)
+                };
+                i = end;
+                out.extend(spec.format(pop(&mut stack)).bytes());
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn pop(stack: &mut Vec<i32>) -> i32 {
+    stack.pop().unwrap_or(0)
+}
+
+/**
+Find where to carry on from when skipping the rest of a branch of a conditional that starts at `i`.
+
+This is just past the `%;` that ends the conditional, or if `to_else` is set, just past the next `%e` of the same conditional if that comes first.  Nested conditionals are skipped over whole.
+*/
+fn skip_branch(cap: &[u8], mut i: usize, to_else: bool) -> usize {
+    let mut depth = 0;
+    while i + 1 < cap.len() {
+        if cap[i] != b'%' {
+            i += 1;
+            continue;
+        }
+        match cap[i + 1] {
+            b'?' => depth += 1,
+            b';' if depth == 0 => return i + 2,
+            b';' => depth -= 1,
+            b'e' if depth == 0 && to_else => return i + 2,
+            _ => ()
+        }
+        i += 2;
+    }
+    cap.len()
+}
+
+/// The widest a conversion can be padded to, since the capability might not come from anywhere trustworthy.
+const MAX_WIDTH: usize = 1024;
+
+/// Read the decimal number starting at `i`, if any, giving `None` if it's over `MAX_WIDTH`.
+fn number(cap: &[u8], i: &mut usize) -> Option<usize> {
+    let mut n: usize = 0;
+    while let Some(&d @ b'0'...b'9') = cap.get(*i) {
+        n = n.saturating_mul(10).saturating_add((d - b'0') as usize);
+        *i += 1;
+    }
+    if n <= MAX_WIDTH { Some(n) } else { None }
+}
+
+/// A `printf`-style conversion: `%[[:]flags][width[.precision]][doxXs]`.
+#[derive(Copy, Clone, Default)]
+struct Spec {
+    left: bool,
+    sign: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: u8,
+}
+
+impl Spec {
+    /// Parse the conversion after the `%` at `start`, returning it and the index just past it.
+    fn parse(cap: &[u8], start: usize) -> Option<(Spec, usize)> {
+        let mut spec = Spec::default();
+        let mut i = start;
+        if cap.get(i) == Some(&b':') {
+            i += 1;
+        }
+        loop {
+            match cap.get(i) {
+                Some(&b'-') => spec.left = true,
+                Some(&b'+') => spec.sign = true,
+                Some(&b' ') => spec.space = true,
+                Some(&b'#') => spec.alternate = true,
+                _ => break
+            }
+            i += 1;
+        }
+        if cap.get(i) == Some(&b'0') {
+            spec.zero = true;
+        }
+        spec.width = match number(cap, &mut i) {
+            Some(width) => width,
+            None => return None
+        };
+        if cap.get(i) == Some(&b'.') {
+            i += 1;
+            spec.precision = match number(cap, &mut i) {
+                Some(precision) => Some(precision),
+                None => return None
+            };
+        }
+        match cap.get(i) {
+            Some(&conv @ b'd') | Some(&conv @ b'o') | Some(&conv @ b'x') | Some(&conv @ b'X') | Some(&conv @ b's') => {
+                spec.conv = conv;
+                Some((spec, i + 1))
+            },
+            _ => None
+        }
+    }
+
+    fn format(&self, n: i32) -> String {
+        let (sign, digits, prefix) = match self.conv {
+            b'd' | b's' => {
+                let sign = if n < 0 { "-" } else if self.sign { "+" } else if self.space { " " } else { "" };
+                (sign, (n as i64).abs().to_string(), "")
+            },
+            b'o' => ("", format!("{:o}", n as u32), if self.alternate && n != 0 { "0" } else { "" }),
+            b'x' => ("", format!("{:x}", n as u32), if self.alternate && n != 0 { "0x" } else { "" }),
+            _ => ("", format!("{:X}", n as u32), if self.alternate && n != 0 { "0X" } else { "" }),
+        };
+
+        let digits = match self.precision {
+            Some(p) if self.conv == b's' => digits.chars().take(p).collect(),
+            Some(p) if p > digits.len() => format!("{}{}", "0".repeat(p - digits.len()), digits),
+            _ => digits
+        };
+        let len = sign.len() + prefix.len() + digits.len();
+        let pad = self.width.saturating_sub(len);
+        if self.left {
+            format!("{}{}{}{}", sign, prefix, digits, " ".repeat(pad))
+        } else if self.zero && self.precision.is_none() {
+            format!("{}{}{}{}", sign, prefix, "0".repeat(pad), digits)
+        } else {
+            format!("{}{}{}{}", " ".repeat(pad), sign, prefix, digits)
+        }
+    }
+}
+
+#[test]
+fn test_tparm() {
+    fn expand(cap: &str, params: &[i32]) -> String {
+        String::from_utf8(tparm(cap.as_bytes(), params).unwrap()).unwrap()
+    }
+
+    // Some real capabilities.
+    assert_eq!(expand("\x1b[%i%p1%d;%p2%dH", &[2, 4]), "\x1b[3;5H");
+    assert_eq!(expand("%i%p1%d;%p2%d", &[i32::MAX, 0]), "-2147483648;1");
+    assert_eq!(expand("\x1b[%p1%dA", &[7]), "\x1b[7A");
+    let setaf = "\x1b[%?%p1%{8}%<%t3%p1%d%e%p1%{16}%<%t9%p1%{8}%-%d%e38;5;%p1%d%;m";
+    assert_eq!(expand(setaf, &[1]), "\x1b[31m");
+    assert_eq!(expand(setaf, &[9]), "\x1b[91m");
+    assert_eq!(expand(setaf, &[208]), "\x1b[38;5;208m");
+    let direct = "\x1b[%?%p1%{8}%<%t3%p1%d%e38:2::%p1%{65536}%/%d:%p1%{256}%/%{255}%&%d:%p1%{255}%&%d%;m";
+    assert_eq!(expand(direct, &[0xff8700]), "\x1b[38:2::255:135:0m");
+    assert_eq!(expand("\x1b[%p1%d q", &[]), "\x1b[0 q");
+    assert_eq!(expand("\x1b=%p1%' '%+%c%p2%' '%+%c", &[1, 2]), "\x1b=!\"");
+
+    // Variables, nested conditionals and the rest of the operators.
+    assert_eq!(expand("%p1%Pa%p2%Pz%gz%ga%-%d", &[3, 10]), "7");
+    assert_eq!(expand("%?%p1%t%?%p2%tA%eB%;%eC%;!", &[1, 0]), "B!");
+    assert_eq!(expand("%?%p1%t%?%p2%tA%eB%;%eC%;!", &[0, 1]), "C!");
+    assert_eq!(expand("%{7}%{2}%m%{7}%{2}%/%{0}%/%p1%!%p1%~%d%d%d%d", &[0]), "-1101");
+    assert_eq!(expand("%{6}%{3}%&%{6}%{3}%|%{6}%{3}%^%d%d%d", &[]), "572");
+    assert_eq!(expand("%p1%l%d %p1%{5}%>%p1%{5}%=%O%d", &[12345]), "5 1");
+
+    // printf conversions.
+    assert_eq!(expand("%p1%3d|%p1%:-3d|%p1%03d|%p1%.2d|%p1%:+d|%p1% d", &[5]), "  5|5  |005|05|+5| 5");
+    assert_eq!(expand("%p1%x %p1%X %p1%#x %p1%o %p1%#o", &[255]), "ff FF 0xff 377 0377");
+    assert_eq!(expand("%p1%d %p1%5d", &[-12]), "-12   -12");
+    assert_eq!(expand("100%% $<5>", &[]), "100% $<5>");
+
+    assert_eq!(tparm(b"%p0%d", &[]), Err(InvalidCapability));
+    assert_eq!(tparm(b"%z", &[]), Err(InvalidCapability));
+    assert_eq!(tparm(b"%{12", &[]), Err(InvalidCapability));
+    assert_eq!(tparm(b"%", &[]), Err(InvalidCapability));
+    assert_eq!(expand("%p1%1024d", &[1]).len(), 1024);
+    assert_eq!(tparm(b"%p1%1025d", &[]), Err(InvalidCapability));
+    assert_eq!(tparm(b"%p1%.99999999999999999999999d", &[]), Err(InvalidCapability));
+}
@@ -0,0 +1,298 @@
+/*!
+Colour themes: the concrete RGB values a terminal uses for its palette and default colours.
+*/
+use std::error::Error;
+use palette::Palette;
+use sgr::Color;
+
+/**
+Everything needed to turn a `Color` into RGB.
+
+Only the 16 basic colours vary between themes; indices 16–255 are always the xterm colour cube and greyscale ramp.
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub struct Theme {
+    pub palette: Palette,
+    pub foreground: (u8, u8, u8),
+    pub background: (u8, u8, u8),
+    pub cursor: (u8, u8, u8),
+}
+
+marker_error! {
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct InvalidTheme
+    impl {
+        desc {"invalid theme"}
+    }
+}
+
+/// Unpack a list of `0xrrggbb` values.
+fn palette(colors: [u32; 16]) -> Palette {
+    let mut rgb = [(0, 0, 0); 16];
+    for (c, &v) in rgb.iter_mut().zip(colors.iter()) {
+        *c = unpack(v);
+    }
+    Palette::new(rgb)
+}
+
+fn unpack(v: u32) -> (u8, u8, u8) {
+    ((v >> 16) as u8, (v >> 8) as u8, v as u8)
+}
+
+impl Theme {
+    /// xterm's defaults: black on white.
+    pub fn xterm() -> Self {
+        Theme {
+            palette: palette([
+                0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5,
+                0x7f7f7f, 0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+            ]),
+            foreground: unpack(0x000000),
+            background: unpack(0xffffff),
+            cursor: unpack(0x000000),
+        }
+    }
+
+    /// The IBM VGA text mode colours, brown and all.
+    pub fn vga() -> Self {
+        Theme {
+            palette: palette([
+                0x000000, 0xaa0000, 0x00aa00, 0xaa5500, 0x0000aa, 0xaa00aa, 0x00aaaa, 0xaaaaaa,
+                0x555555, 0xff5555, 0x55ff55, 0xffff55, 0x5555ff, 0xff55ff, 0x55ffff, 0xffffff,
+            ]),
+            foreground: unpack(0xaaaaaa),
+            background: unpack(0x000000),
+            cursor: unpack(0xaaaaaa),
+        }
+    }
+
+    /// The Windows console before Windows 10 1709.
+    pub fn windows_console() -> Self {
+        Theme {
+            palette: Palette::default(),
+            foreground: unpack(0xc0c0c0),
+            background: unpack(0x000000),
+            cursor: unpack(0xc0c0c0),
+        }
+    }
+
+    /// Campbell, the Windows console and Windows Terminal default since Windows 10 1709.
+    pub fn campbell() -> Self {
+        Theme {
+            palette: palette([
+                0x0c0c0c, 0xc50f1f, 0x13a10e, 0xc19c00, 0x0037da, 0x881798, 0x3a96dd, 0xcccccc,
+                0x767676, 0xe74856, 0x16c60c, 0xf9f1a5, 0x3b78ff, 0xb4009e, 0x61d6d6, 0xf2f2f2,
+            ]),
+            foreground: unpack(0xcccccc),
+            background: unpack(0x0c0c0c),
+            cursor: unpack(0xffffff),
+        }
+    }
+
+    /// Solarized, dark variant.
+    pub fn solarized_dark() -> Self {
+        Theme {
+            palette: Theme::solarized_palette(),
+            foreground: unpack(0x839496),
+            background: unpack(0x002b36),
+            cursor: unpack(0x93a1a1),
+        }
+    }
+
+    /// Solarized, light variant.
+    pub fn solarized_light() -> Self {
+        Theme {
+            palette: Theme::solarized_palette(),
+            foreground: unpack(0x657b83),
+            background: unpack(0xfdf6e3),
+            cursor: unpack(0x586e75),
+        }
+    }
+
+    fn solarized_palette() -> Palette {
+        // The bright colours are where Solarized keeps its monotones.
+        palette([
+            0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5,
+            0x002b36, 0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+        ])
+    }
+
+    /**
+    Work out the RGB value of a colour used for text.
+    */
+    pub fn foreground_rgb(&self, c: Color) -> (u8, u8, u8) {
+        self.palette.rgb(c).unwrap_or(self.foreground)
+    }
+
+    /**
+    Work out the RGB value of a colour used for a background.
+    */
+    pub fn background_rgb(&self, c: Color) -> (u8, u8, u8) {
+        self.palette.rgb(c).unwrap_or(self.background)
+    }
+
+    /**
+    Set a colour by name, as used in theme files: `color0` through `color15`, `foreground`, `background` or `cursor`.
+    */
+    fn set(&mut self, name: &str, rgb: (u8, u8, u8)) -> Result<(), InvalidTheme> {
+        match name {
+            "foreground" => self.foreground = rgb,
+            "background" => self.background = rgb,
+            "cursor" | "cursorColor" => self.cursor = rgb,
+            _ if name.starts_with("color") => {
+                let n: usize = match name[5..].parse() {
+                    Ok(n) if n < 16 => n,
+                    _ => return Err(InvalidTheme)
+                };
+                let mut colors = *self.palette.colors();
+                colors[n] = rgb;
+                self.palette = Palette::new(colors);
+            },
+            _ => return Err(InvalidTheme)
+        }
+        Ok(())
+    }
+
+    /**
+    Override colours from a simple `key = value` listing, one per line.
+
+    Keys are `color0` through `color15`, `foreground`, `background` and `cursor`; values are `#rgb`, `#rrggbb` or X11 `rgb:r/g/b`.  Blank lines and lines starting with `#` are ignored.
+    */
+    pub fn load_key_values(&mut self, src: &str) -> Result<(), InvalidTheme> {
+        for line in src.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return Err(InvalidTheme)
+            };
+            let rgb = match parse_rgb(value) {
+                Some(rgb) => rgb,
+                None => return Err(InvalidTheme)
+            };
+            try!(self.set(key, rgb));
+        }
+        Ok(())
+    }
+
+    /**
+    Override colours from X resources, such as an `~/.Xresources` file.
+
+    Any resource ending in `colorN`, `foreground`, `background` or `cursorColor` is used regardless of what it's qualified with, so `*color1`, `XTerm*color1` and `URxvt.color1` all work.  Everything else, including preprocessor lines, is skipped.
+    */
+    pub fn load_xresources(&mut self, src: &str) -> Result<(), InvalidTheme> {
+        for line in src.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('!') || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, ':');
+            let resource = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => continue
+            };
+            let name = resource.rsplit(&['*', '.'][..]).next().unwrap_or(resource);
+            match name {
+                "foreground" | "background" | "cursorColor" => (),
+                // Watch out for xterm's `colorBD` and friends.
+                _ if name.starts_with("color") && name[5..].parse::<u8>().map(|n| n < 16).unwrap_or(false) => (),
+                _ => continue
+            }
+            let rgb = match parse_rgb(value) {
+                Some(rgb) => rgb,
+                None => return Err(InvalidTheme)
+            };
+            try!(self.set(name, rgb));
+        }
+        Ok(())
+    }
+}
+
+/**
+Parse a colour in one of the forms X11 understands: `#rgb`, `#rrggbb` or `rgb:r/g/b` with one to four hex digits per channel.
+*/
+fn parse_rgb(s: &str) -> Option<(u8, u8, u8)> {
+    fn channel(s: &str) -> Option<u8> {
+        // `from_str_radix` would take a leading `+` too.
+        if s.is_empty() || s.len() > 4 || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let v = match u32::from_str_radix(s, 16) {
+            Ok(v) => v,
+            Err(_) => return None
+        };
+        // Scale so that the largest value for this many digits becomes 255.
+        let max = (1u32 << (4 * s.len())) - 1;
+        Some(((v * 255 + max / 2) / max) as u8)
+    }
+
+    if let Some(hex) = s.strip_prefix('#') {
+        // Check before slicing, so a multi-byte character can't be cut in half.
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let n = match hex.len() {
+            3 => 1,
+            6 => 2,
+            _ => return None
+        };
+        match (channel(&hex[..n]), channel(&hex[n..2 * n]), channel(&hex[2 * n..])) {
+            (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+            _ => None
+        }
+    } else if let Some(rgb) = s.strip_prefix("rgb:") {
+        let mut parts = rgb.split('/');
+        match (parts.next().and_then(channel), parts.next().and_then(channel), parts.next().and_then(channel), parts.next()) {
+            (Some(r), Some(g), Some(b), None) => Some((r, g, b)),
+            _ => None
+        }
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_parse_rgb() {
+    assert_eq!(parse_rgb("#ff8000"), Some((255, 128, 0)));
+    assert_eq!(parse_rgb("#f80"), Some((255, 136, 0)));
+    assert_eq!(parse_rgb("rgb:ff/80/00"), Some((255, 128, 0)));
+    assert_eq!(parse_rgb("rgb:ffff/8080/0"), Some((255, 128, 0)));
+    assert_eq!(parse_rgb("#ff80"), None);
+    assert_eq!(parse_rgb("#gg0000"), None);
+    assert_eq!(parse_rgb("rgb:ff/80"), None);
+    assert_eq!(parse_rgb("red"), None);
+    assert_eq!(parse_rgb("#aé"), None);
+    assert_eq!(parse_rgb("rgb:é/0/0"), None);
+    assert_eq!(parse_rgb("rgb:+f/0/0"), None);
+}
+
+#[test]
+fn test_theme() {
+    let theme = Theme::xterm();
+    assert_eq!(theme.foreground_rgb(Color::Default), (0, 0, 0));
+    assert_eq!(theme.background_rgb(Color::Default), (255, 255, 255));
+    assert_eq!(theme.foreground_rgb(Color::Indexed(1)), (0xcd, 0, 0));
+    assert_eq!(theme.foreground_rgb(Color::Indexed(208)), (255, 135, 0));
+    assert_eq!(theme.foreground_rgb(Color::Indexed(232)), (8, 8, 8));
+    assert_eq!(Theme::campbell().foreground_rgb(Color::Indexed(208)), (255, 135, 0));
+
+    let mut theme = Theme::vga();
+    theme.load_key_values("# mine\n\nforeground = #ffffff\ncolor1=rgb:80/00/00\n").unwrap();
+    assert_eq!(theme.foreground, (255, 255, 255));
+    assert_eq!(theme.palette.colors()[1], (0x80, 0, 0));
+    assert_eq!(theme.palette.colors()[2], (0, 0xaa, 0));
+    assert_eq!(theme.load_key_values("color16 = #000000"), Err(InvalidTheme));
+    assert_eq!(theme.load_key_values("colour1 = #000000"), Err(InvalidTheme));
+    assert_eq!(theme.load_key_values("color1 = red"), Err(InvalidTheme));
+
+    let mut theme = Theme::xterm();
+    theme.load_xresources("! Solarized\n#define S_base03 #002b36\n*background: #002b36\nXTerm*color4: #268bd2\nURxvt.cursorColor:#93a1a1\nXTerm*faceName: Mono\nXTerm*colorBD: white\n").unwrap();
+    assert_eq!(theme.background, (0, 0x2b, 0x36));
+    assert_eq!(theme.palette.colors()[4], (0x26, 0x8b, 0xd2));
+    assert_eq!(theme.cursor, (0x93, 0xa1, 0xa1));
+}
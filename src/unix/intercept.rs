@@ -0,0 +1,175 @@
+/*!
+Redirecting file descriptors 0, 1 and 2 through pipes.
+
+Each of standard output and standard error gets a pipe `dup2`ed over it, with a thread reading the other end into its own `AnsiIntercept`; whatever the interpreter writes goes to a copy of the original descriptor.  Standard input gets the same treatment in the other direction, so that interpreters have somewhere to put their replies to queries.  Since a read from the original standard input could wait for ever, that thread also watches a second pipe, which gets closed when the originals are put back.
+*/
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use ansi::{AnsiIntercept, AnsiInterpret};
+use intercept::InterceptHandle;
+use writer::AnsiWriter;
+use super::{cvt, libc};
+
+/**
+Intercept standard output and standard error, passing everything through unchanged.
+
+Nothing here ever answers a query, so standard input is left alone.  See `intercept_stdio_with`.
+*/
+pub fn intercept_stdio() -> io::Result<InterceptHandle> {
+    intercept_fds(&[1, 2], |_, out, _| AnsiWriter::new(out))
+}
+
+/**
+Intercept the standard streams, running standard output and standard error through interpreters created by `make_interp`.
+
+`make_interp` is called once for each output stream with its descriptor number, the stream as it was before being intercepted, and an `InputWriter` for answering queries.  Streams that aren't open are left alone.
+
+Output goes through the interpreters until the returned handle is dropped or `join`ed.  After that, the process reads the original standard input directly again; input already taken from it for the process, but not yet read by the process, is lost.
+*/
+pub fn intercept_stdio_with<F, I>(make_interp: F) -> io::Result<InterceptHandle>
+where F: FnMut(RawFd, File, InputWriter) -> I, I: 'static + AnsiInterpret + Send {
+    intercept_fds(&[0, 1, 2], make_interp)
+}
+
+/**
+Intercept just some of the standard descriptors.  If standard input isn't one of them, the `InputWriter`s are already closed.
+*/
+pub fn intercept_fds<F, I>(fds: &[RawFd], mut make_interp: F) -> io::Result<InterceptHandle>
+where F: FnMut(RawFd, File, InputWriter) -> I, I: 'static + AnsiInterpret + Send {
+    let mut handle = InterceptHandle::new();
+
+    // Keep copies of the originals to put back later.  This is set up first so that a failure part-way through undoes everything.
+    let mut saved = vec![];
+    for &fd in fds {
+        if let Some(file) = try!(dup(fd)) {
+            saved.push((fd, file));
+        }
+    }
+    let originals = try!(saved.iter()
+        .map(|&(fd, ref file)| file.try_clone().map(|file| (fd, file)))
+        .collect::<io::Result<Vec<_>>>());
+    let (wake_read, wake_write) = try!(pipe());
+    let mut wake_write = Some(wake_write);
+    handle.on_restore(move || {
+        let restored = saved.iter()
+            .try_for_each(|&(fd, ref file)| cvt(unsafe { libc::dup2(file.as_raw_fd(), fd) }).map(|_| ()));
+        // This wakes up the thread copying standard input, so that it can be joined even if putting it back failed.
+        drop(wake_write.take());
+        restored
+    });
+
+    let input = InputWriter(Arc::new(Mutex::new(None)));
+    let mut installs = vec![];
+    for (fd, original) in originals {
+        let (read, write) = try!(pipe());
+        if fd == 0 {
+            *input.0.lock().unwrap() = Some(write);
+            let src = Interruptible {
+                src: original,
+                wake: try!(wake_read.try_clone()),
+            };
+            try!(handle.pump("ansi_interpreter.stdin", src, InputPump(input.clone()), true));
+            installs.push((fd, read));
+        } else {
+            let interp = AnsiIntercept::new(make_interp(fd, original, input.clone()));
+            let name = if fd == 1 { "ansi_interpreter.stdout" } else { "ansi_interpreter.stderr" };
+            try!(handle.pump(name, read, interp, true));
+            installs.push((fd, write));
+        }
+    }
+
+    // Anything the standard library is holding on to was meant for the originals.
+    try!(handle.flush());
+
+    // Once these are closed, the pipes only stay open as long as the standard descriptors point at them.
+    for (fd, file) in installs {
+        try!(cvt(unsafe { libc::dup2(file.as_raw_fd(), fd) }));
+    }
+
+    Ok(handle)
+}
+
+/**
+Writes to the intercepted standard input, as though it had come from the original.  This is how interpreters answer queries.
+
+Once the original standard input runs dry, the pipe is closed so that the process sees the end of it, and writes fail with `BrokenPipe`.
+*/
+#[derive(Clone)]
+pub struct InputWriter(Arc<Mutex<Option<File>>>);
+
+impl Write for InputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self.0.lock().unwrap() {
+            Some(ref mut file) => file.write(buf),
+            None => Err(io::Error::new(io::ErrorKind::BrokenPipe, "standard input is closed"))
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self.0.lock().unwrap() {
+            Some(ref mut file) => file.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+/// Copies of the input writer would keep the pipe open, so the thread copying the original standard input closes it explicitly when it's done.
+struct InputPump(InputWriter);
+
+impl Write for InputPump {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Drop for InputPump {
+    fn drop(&mut self) {
+        *(self.0).0.lock().unwrap() = None;
+    }
+}
+
+/// The original standard input, as read by the thread copying it.  Reads report the end of the input as soon as `wake` is closed, rather than waiting for more.
+struct Interruptible {
+    src: File,
+    wake: File,
+}
+
+impl Read for Interruptible {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut fds = [
+            libc::pollfd { fd: self.src.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.wake.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+        ];
+        try!(cvt(unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) }));
+        if fds[1].revents != 0 {
+            return Ok(0);
+        }
+        self.src.read(buf)
+    }
+}
+
+/// Duplicate one of the standard descriptors, if it's open.  The copy isn't inherited by child processes.
+fn dup(fd: RawFd) -> io::Result<Option<File>> {
+    match cvt(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 3) }) {
+        Ok(new) => Ok(Some(unsafe { File::from_raw_fd(new) })),
+        Err(ref err) if err.raw_os_error() == Some(libc::EBADF) => Ok(None),
+        Err(err) => Err(err)
+    }
+}
+
+/// Create a pipe whose ends aren't inherited by child processes; `dup2` clears that for the copies that become standard descriptors.
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds = [0; 2];
+    try!(cvt(unsafe { libc::pipe(fds.as_mut_ptr()) }));
+    let (read, write) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+    for file in &[&read, &write] {
+        try!(cvt(unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) }));
+    }
+    Ok((read, write))
+}
@@ -0,0 +1,32 @@
+/*!
+Putting an interpreter between the process and its standard streams on Unix.
+
+Unlike the Windows console, a Unix terminal already understands escape sequences, so the interesting interpreters here are the ones that change them: stripping them for a log file, or squashing colours for a terminal that can't show them.
+*/
+#![cfg(unix)]
+extern crate libc;
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+pub use self::intercept::{intercept_fds, intercept_stdio, intercept_stdio_with, InputWriter};
+#[cfg(target_os = "linux")]
+pub use self::pty::Session;
+
+mod intercept;
+#[cfg(target_os = "linux")]
+mod pty;
+
+/// Turn a C-style `-1` failure into the error from `errno`.
+fn cvt(r: libc::c_int) -> io::Result<libc::c_int> {
+    if r == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(r)
+    }
+}
+
+/// Whether `fd` is a terminal.
+pub fn is_tty(fd: RawFd) -> bool {
+    unsafe { libc::isatty(fd) == 1 }
+}
@@ -0,0 +1,756 @@
+/*!
+Running a child process in a pseudo-terminal, and keeping track of what its screen looks like.
+*/
+use std::ffi::CStr;
+use std::fs::{File, OpenOptions};
+use std::cmp::{max, min};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use ansisys::ScreenMode;
+use ansi::{parse_csi, AnsiIntercept, AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use charset::{Charset, CharsetSlot, CharsetTranslator};
+use console::{ConsoleBackend, ConsoleInterpreter, Coord, CursorInfo, Rect, ScreenBufferInfo, SimulatedConsole};
+use console::ENABLE_WRAP_AT_EOL_OUTPUT;
+use query::Query;
+use sgr::SgrAttrs;
+use style::StyleMask;
+use window::WindowOp;
+use super::{cvt, libc};
+
+/// How long the output has to stay quiet after the child exits before `wait` stops waiting for the rest of it.
+const QUIET: Duration = Duration::from_millis(100);
+
+/// The terminal, and whether the child has finished writing to it.
+struct Screen {
+    term: AnsiIntercept<Terminal>,
+    done: bool,
+}
+
+impl Screen {
+    fn console(&self) -> &SimulatedConsole {
+        &self.term.get_ref().interp.get_ref().get_ref().console
+    }
+
+    fn text(&self) -> String {
+        self.console().window_text().join("\n")
+    }
+}
+
+/**
+A child process running in a pseudo-terminal, for driving interactive programs from tests the way `expect` does.
+
+Everything the child writes is run through a `ConsoleInterpreter` over a `SimulatedConsole` the size of the terminal, which also answers the child's queries, such as cursor position reports and device attributes.  The screen follows terminal rules rather than Windows console ones, so full-screen programs work too: line feed doesn't return to the first column, wrapping waits until there's another character to write, and the alternate screen (modes 47, 1047 and 1049), scroll regions, reverse index, and inserting, deleting and erasing lines and characters are all understood.  Sequences it doesn't understand are dropped.
+
+The child inherits the environment as usual, including `TERM`; set that on the `Command` if the program needs to be told what it's talking to.
+*/
+pub struct Session {
+    child: Child,
+    master: File,
+    shared: Arc<(Mutex<Screen>, Condvar)>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Session {
+    /**
+    Start `command` with a `cols` by `rows` terminal as its standard input, output and error, and as its controlling terminal.
+    */
+    pub fn spawn(mut command: Command, cols: u16, rows: u16) -> io::Result<Session> {
+        let master = try!(open_master());
+        let size = libc::winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        try!(cvt(unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) }));
+
+        let slave = try!(OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(try!(slave_name(&master))));
+        command
+            .stdin(Stdio::from(try!(slave.try_clone())))
+            .stdout(Stdio::from(try!(slave.try_clone())))
+            .stderr(Stdio::from(slave));
+        unsafe {
+            command.pre_exec(|| {
+                // A new session has no controlling terminal, so the one on standard input can become it.
+                try!(cvt(libc::setsid()));
+                try!(cvt(libc::ioctl(0, libc::TIOCSCTTY, 0)));
+                Ok(())
+            });
+        }
+        let child = try!(command.spawn());
+        // Dropping the command closes our copies of the slave, so reads from the master fail once the child's are gone.
+        drop(command);
+
+        let cols = cols.min(i16::MAX as u16) as i16;
+        let rows = rows.min(i16::MAX as u16) as i16;
+        let screen = try!(TermScreen::new(SimulatedConsole::new(cols, rows, rows)));
+        let mut interp = ConsoleInterpreter::new(try!(master.try_clone()), screen);
+        interp.set_show_unknown(false);
+        let shared = Arc::new((Mutex::new(Screen {
+            term: AnsiIntercept::new(Terminal { interp: CharsetTranslator::new(interp) }),
+            done: false,
+        }), Condvar::new()));
+
+        let reader = {
+            let (master, shared) = (try!(master.try_clone()), shared.clone());
+            try!(thread::Builder::new()
+                .name(String::from("ansi_interpreter.pty"))
+                .spawn(move || read_output(master, &shared)))
+        };
+
+        Ok(Session {
+            child: child,
+            master: master,
+            shared: shared,
+            reader: Some(reader),
+        })
+    }
+
+    /// Type `keys` into the terminal.  Control keys are control characters, so Enter is `"\r"` and Ctrl-C is `"\x03"`.
+    pub fn send_keys(&mut self, keys: &str) -> io::Result<()> {
+        try!(self.master.write_all(keys.as_bytes()));
+        self.master.flush()
+    }
+
+    /**
+    Wait until `pattern` appears somewhere on the screen, as given by `screen_text`.
+
+    Fails with `TimedOut` if that takes longer than `timeout`, or `UnexpectedEof` if the child stops writing first.  Either way, the message includes what the screen looked like.
+    */
+    pub fn wait_for_text(&self, pattern: &str, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let (ref lock, ref cvar) = *self.shared;
+        let mut screen = lock.lock().unwrap();
+        loop {
+            let text = screen.text();
+            if text.contains(pattern) {
+                return Ok(());
+            }
+            if screen.done {
+                throw!(io::Error::new(io::ErrorKind::UnexpectedEof,
+                    format!("output ended without {:?} appearing; the screen was:\n{}", pattern, text)));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                throw!(io::Error::new(io::ErrorKind::TimedOut,
+                    format!("timed out waiting for {:?}; the screen was:\n{}", pattern, text)));
+            }
+            screen = cvar.wait_timeout(screen, deadline - now).unwrap().0;
+        }
+    }
+
+    /// The rows of the screen without trailing blanks, joined with newlines.
+    pub fn screen_text(&self) -> String {
+        self.shared.0.lock().unwrap().text()
+    }
+
+    /// Where the cursor is on the screen, counting from zero.
+    pub fn cursor(&self) -> Coord {
+        self.shared.0.lock().unwrap().console().cursor()
+    }
+
+    /// A copy of the screen, for looking at colours and the like.
+    pub fn console(&self) -> SimulatedConsole {
+        self.shared.0.lock().unwrap().console().clone()
+    }
+
+    pub fn child(&self) -> &Child {
+        &self.child
+    }
+
+    pub fn child_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+
+    /// Wait for the child to exit, and for everything it wrote to reach the screen.
+    pub fn wait(&mut self) -> io::Result<ExitStatus> {
+        let status = try!(self.child.wait());
+        try!(self.finish_reading());
+        Ok(status)
+    }
+
+    /**
+    Wait for the reader thread to catch up with what the child wrote.
+
+    The thread only finishes once nothing has the terminal open any more, which a background process the child left behind can put off for ever.  So once the output has gone quiet, the thread is left to finish on its own.
+    */
+    fn finish_reading(&mut self) -> io::Result<()> {
+        let done = {
+            let (ref lock, ref cvar) = *self.shared;
+            let mut screen = lock.lock().unwrap();
+            while !screen.done {
+                let (next, timeout) = cvar.wait_timeout(screen, QUIET).unwrap();
+                screen = next;
+                if timeout.timed_out() {
+                    break;
+                }
+            }
+            screen.done
+        };
+        if done {
+            if let Some(reader) = self.reader.take() {
+                if reader.join().is_err() {
+                    throw!(io::Error::new(io::ErrorKind::Other, "pty reader thread panicked"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let running = self.child.try_wait().ok() == Some(None);
+        if running || !self.shared.0.lock().unwrap().done {
+            // The child leads its own process group, so this gets whatever it left running in the background too.
+            unsafe { libc::kill(-(self.child.id() as libc::pid_t), libc::SIGKILL) };
+        }
+        let _ = self.wait();
+    }
+}
+
+/**
+A terminal screen, kept in a `SimulatedConsole`.
+
+The console does the storing and the control characters that mean the same thing either way; this does line feeds, wrapping and scrolling the way a terminal does, along with the things a console can't do at all.  Positions are in the buffer, which is exactly as big as the screen.
+*/
+struct TermScreen {
+    console: SimulatedConsole,
+    /// Whether to wrap at the end of a line; the console itself never does.
+    wrap: bool,
+    /// The last column has been written to, and the next character goes on the next line.
+    wrap_pending: bool,
+    /// The top and bottom rows that scroll, if not the whole screen.
+    margins: Option<(i16, i16)>,
+    /// The normal screen, while the alternate screen is showing.
+    main: Option<SimulatedConsole>,
+}
+
+impl TermScreen {
+    fn new(mut console: SimulatedConsole) -> io::Result<TermScreen> {
+        let mode = try!(console.mode());
+        try!(console.set_mode(mode & !ENABLE_WRAP_AT_EOL_OUTPUT));
+        Ok(TermScreen {
+            console: console,
+            wrap: mode & ENABLE_WRAP_AT_EOL_OUTPUT != 0,
+            wrap_pending: false,
+            margins: None,
+            main: None,
+        })
+    }
+
+    fn set_scroll_region(&mut self, region: Option<(i16, i16)>) {
+        let rows = self.console.size().y;
+        self.margins = region.and_then(|(top, bottom)| {
+            let (top, bottom) = (max(0, top), min(rows - 1, bottom));
+            if top < bottom { Some((top, bottom)) } else { None }
+        });
+    }
+
+    /// The scroll region, or the whole screen if there isn't one.
+    fn region(&self) -> (i16, i16) {
+        self.margins.unwrap_or((0, self.console.size().y - 1))
+    }
+
+    /// The scroll region, as long as the cursor is inside it.
+    fn cursor_region(&self) -> Option<(i16, i16)> {
+        let (top, bottom) = self.region();
+        let y = self.console.cursor().y;
+        if top <= y && y <= bottom { Some((top, bottom)) } else { None }
+    }
+
+    fn move_cursor(&mut self, pos: Coord) {
+        let _ = self.console.set_cursor_position(pos);
+        self.wrap_pending = false;
+    }
+
+    fn copy_cell(&mut self, from: Coord, to: Coord) {
+        if let Some((ch, attrs)) = self.console.cell(from) {
+            let _ = self.console.fill_character(ch, 1, to);
+            let _ = self.console.fill_attribute(attrs, 1, to);
+        }
+    }
+
+    /// Blank `len` cells from `start`, using the current attributes.
+    fn erase(&mut self, start: Coord, len: i16) {
+        if len > 0 {
+            let attrs = self.console.attrs();
+            let _ = self.console.fill_character(' ', len as u32, start);
+            let _ = self.console.fill_attribute(attrs, len as u32, start);
+        }
+    }
+
+    /// Move the rows from `top` to `bottom` inclusive up by `n`, or down if `n` is negative, blanking the rows that come into view.
+    fn scroll_rows(&mut self, top: i16, bottom: i16, n: i16) {
+        let width = self.console.size().x;
+        let moved = min(bottom - top + 1, if n < 0 { -n } else { n });
+        if n > 0 {
+            for y in top..bottom - moved + 1 {
+                for x in 0..width {
+                    self.copy_cell(Coord::new(x, y + moved), Coord::new(x, y));
+                }
+            }
+            self.erase(Coord::new(0, bottom - moved + 1), moved * width);
+        } else if n < 0 {
+            for y in (top + moved..bottom + 1).rev() {
+                for x in 0..width {
+                    self.copy_cell(Coord::new(x, y - moved), Coord::new(x, y));
+                }
+            }
+            self.erase(Coord::new(0, top), moved * width);
+        }
+    }
+
+    /// Move down a row, scrolling if the cursor is at the bottom of the scroll region.
+    fn line_feed(&mut self) {
+        let cursor = self.console.cursor();
+        match self.cursor_region() {
+            Some((top, bottom)) if cursor.y == bottom => self.scroll_rows(top, bottom, 1),
+            _ => if cursor.y + 1 < self.console.size().y {
+                self.move_cursor(Coord::new(cursor.x, cursor.y + 1));
+            }
+        }
+        self.wrap_pending = false;
+    }
+
+    /// Move up a row, scrolling the region down if the cursor is already at the top.
+    fn reverse_index(&mut self) {
+        let cursor = self.console.cursor();
+        match self.cursor_region() {
+            Some((top, bottom)) if cursor.y == top => self.scroll_rows(top, bottom, -1),
+            _ => self.move_cursor(Coord::new(cursor.x, max(0, cursor.y - 1)))
+        }
+        self.wrap_pending = false;
+    }
+
+    /// Insert `n` blank lines at the cursor's row, pushing the rest of the scroll region down.
+    fn insert_lines(&mut self, n: i16) {
+        if let Some((_, bottom)) = self.cursor_region() {
+            let y = self.console.cursor().y;
+            self.scroll_rows(y, bottom, -n);
+            self.move_cursor(Coord::new(0, y));
+        }
+    }
+
+    /// Delete `n` lines at the cursor's row, pulling the rest of the scroll region up.
+    fn delete_lines(&mut self, n: i16) {
+        if let Some((_, bottom)) = self.cursor_region() {
+            let y = self.console.cursor().y;
+            self.scroll_rows(y, bottom, n);
+            self.move_cursor(Coord::new(0, y));
+        }
+    }
+
+    /// Insert `n` blanks at the cursor, pushing the rest of the row right.
+    fn insert_chars(&mut self, n: i16) {
+        let (cursor, width) = (self.console.cursor(), self.console.size().x);
+        let n = min(n, width - cursor.x);
+        for x in (cursor.x + n..width).rev() {
+            self.copy_cell(Coord::new(x - n, cursor.y), Coord::new(x, cursor.y));
+        }
+        self.erase(cursor, n);
+        self.wrap_pending = false;
+    }
+
+    /// Delete `n` characters at the cursor, pulling the rest of the row left.
+    fn delete_chars(&mut self, n: i16) {
+        let (cursor, width) = (self.console.cursor(), self.console.size().x);
+        let n = min(n, width - cursor.x);
+        for x in cursor.x..width - n {
+            self.copy_cell(Coord::new(x + n, cursor.y), Coord::new(x, cursor.y));
+        }
+        self.erase(Coord::new(width - n, cursor.y), n);
+        self.wrap_pending = false;
+    }
+
+    /// Switch to or from the alternate screen, which starts out blank.  The cursor stays where it is.
+    fn set_alternate_screen(&mut self, on: bool) {
+        match (on, self.main.take()) {
+            (true, None) => {
+                self.main = Some(self.console.clone());
+                let size = self.console.size();
+                self.erase(Coord::new(0, 0), size.x * size.y);
+            },
+            (true, main) => self.main = main,
+            (false, Some(mut main)) => {
+                // Everything but the contents carries on as it was.
+                let _ = main.set_cursor_position(self.console.cursor());
+                let _ = main.set_text_attribute(self.console.attrs());
+                if let Ok(mode) = self.console.mode() {
+                    let _ = main.set_mode(mode);
+                }
+                if let Ok(info) = self.console.cursor_info() {
+                    let _ = main.set_cursor_info(&info);
+                }
+                if let Ok(title) = self.console.title() {
+                    let _ = main.set_title(&title);
+                }
+                self.console = main;
+            },
+            (false, None) => ()
+        }
+    }
+
+    /// Write a run of text with no control characters in it, wrapping before each character that doesn't fit.
+    fn write_chars(&mut self, buf: &[u8]) -> io::Result<()> {
+        let width = self.console.size().x;
+        let mut start = 0;
+        for i in 0..buf.len() + 1 {
+            // Each character starts with a byte that isn't a UTF-8 continuation byte.
+            if i < buf.len() && buf[i] & 0xc0 == 0x80 {
+                continue;
+            }
+            try!(self.console.write_text(&buf[start..i]));
+            start = i;
+            if i == buf.len() {
+                break;
+            }
+            if self.wrap_pending {
+                let y = self.console.cursor().y;
+                self.move_cursor(Coord::new(0, y));
+                self.line_feed();
+            }
+            // The console stays in the last column; the next character wraps.
+            if self.wrap && self.console.cursor().x == width - 1 {
+                self.wrap_pending = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ConsoleBackend for TermScreen {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            let text = rest.iter().position(|&b| b < 0x20).unwrap_or(rest.len());
+            try!(self.write_chars(&rest[..text]));
+            match rest.get(text) {
+                Some(&b'\n') => self.line_feed(),
+                Some(&b'\x07') => (),
+                Some(&b) => {
+                    self.wrap_pending = false;
+                    try!(self.console.write_text(&[b]));
+                },
+                None => break
+            }
+            rest = &rest[text + 1..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.console.flush()
+    }
+
+    fn screen_buffer_info(&self) -> io::Result<ScreenBufferInfo> {
+        self.console.screen_buffer_info()
+    }
+
+    fn set_cursor_position(&mut self, pos: Coord) -> io::Result<()> {
+        self.wrap_pending = false;
+        self.console.set_cursor_position(pos)
+    }
+
+    fn set_text_attribute(&mut self, attrs: u16) -> io::Result<()> {
+        self.console.set_text_attribute(attrs)
+    }
+
+    fn fill_attribute(&mut self, attrs: u16, len: u32, start: Coord) -> io::Result<u32> {
+        self.console.fill_attribute(attrs, len, start)
+    }
+
+    fn fill_character(&mut self, ch: char, len: u32, start: Coord) -> io::Result<u32> {
+        self.console.fill_character(ch, len, start)
+    }
+
+    fn cursor_info(&self) -> io::Result<CursorInfo> {
+        self.console.cursor_info()
+    }
+
+    fn set_cursor_info(&mut self, info: &CursorInfo) -> io::Result<()> {
+        self.console.set_cursor_info(info)
+    }
+
+    fn mode(&self) -> io::Result<u32> {
+        let mode = try!(self.console.mode());
+        Ok(if self.wrap { mode | ENABLE_WRAP_AT_EOL_OUTPUT } else { mode })
+    }
+
+    fn set_mode(&mut self, mode: u32) -> io::Result<()> {
+        self.wrap = mode & ENABLE_WRAP_AT_EOL_OUTPUT != 0;
+        self.console.set_mode(mode & !ENABLE_WRAP_AT_EOL_OUTPUT)
+    }
+
+    fn set_window(&mut self, window: Rect) -> io::Result<()> {
+        self.console.set_window(window)
+    }
+
+    fn set_buffer_size(&mut self, size: Coord) -> io::Result<()> {
+        try!(self.console.set_buffer_size(size));
+        if let Some(ref mut main) = self.main {
+            let _ = main.set_window(self.console.window());
+            let _ = main.set_buffer_size(size);
+        }
+        let margins = self.margins;
+        self.set_scroll_region(margins);
+        self.wrap_pending = false;
+        Ok(())
+    }
+
+    fn largest_window(&self) -> io::Result<Coord> {
+        self.console.largest_window()
+    }
+
+    fn font_size(&self) -> io::Result<Coord> {
+        self.console.font_size()
+    }
+
+    fn title(&self) -> io::Result<String> {
+        self.console.title()
+    }
+
+    fn set_title(&mut self, title: &str) -> io::Result<()> {
+        self.console.set_title(title)
+    }
+}
+
+/**
+Fills in the parts of being a terminal that `ConsoleInterpreter` leaves out, since a Windows console can't do them, using what `TermScreen` can do instead.
+*/
+struct Terminal {
+    interp: CharsetTranslator<ConsoleInterpreter<File, TermScreen>>,
+}
+
+impl Terminal {
+    fn screen(&mut self) -> &mut TermScreen {
+        self.interp.get_mut().get_mut()
+    }
+
+    /// Handle a control sequence the parser doesn't know, returning whether it was one of ours.
+    fn csi_seq(&mut self, private: bool, params: &[u16], final_byte: u8) -> Result<bool, GenError> {
+        let n = params.first().cloned().unwrap_or(0).max(1).min(i16::MAX as u16) as i16;
+        let window = self.screen().console.window();
+        match (private, final_byte) {
+            (true, b'h') | (true, b'l') => {
+                let set = final_byte == b'h';
+                match params {
+                    [47] | [1047] => self.screen().set_alternate_screen(set),
+                    [1049] if set => {
+                        // Like DECSC, then switching to a cleared alternate screen.
+                        try!(self.interp.decsc_seq());
+                        self.screen().set_alternate_screen(false);
+                        self.screen().set_alternate_screen(true);
+                    },
+                    [1049] => {
+                        self.screen().set_alternate_screen(false);
+                        try!(self.interp.decrc_seq());
+                    },
+                    _ => return Ok(false)
+                }
+            },
+            (true, _) => return Ok(false),
+            (false, b'r') => {
+                let top = params.first().cloned().unwrap_or(0).max(1).min(i16::MAX as u16) as i16;
+                let bottom = match params.get(1).cloned().unwrap_or(0) {
+                    0 => window.height(),
+                    b => b.min(i16::MAX as u16) as i16,
+                };
+                let region = if top == 1 && bottom >= window.height() {
+                    None
+                } else {
+                    Some((window.top + top - 1, window.top + bottom - 1))
+                };
+                self.screen().set_scroll_region(region);
+                try!(self.interp.cup_seq(1, 1));
+            },
+            (false, b'L') => self.screen().insert_lines(n),
+            (false, b'M') => self.screen().delete_lines(n),
+            (false, b'@') => self.screen().insert_chars(n),
+            (false, b'P') => self.screen().delete_chars(n),
+            (false, b'X') => {
+                let screen = self.screen();
+                let cursor = screen.console.cursor();
+                let len = min(n, screen.console.size().x - cursor.x);
+                screen.erase(cursor, len);
+            },
+            (false, b'S') | (false, b'T') => {
+                let screen = self.screen();
+                let (top, bottom) = screen.region();
+                screen.scroll_rows(top, bottom, if final_byte == b'S' { n } else { -n });
+            },
+            (false, b'G') | (false, b'`') => {
+                let cursor = self.screen().console.cursor();
+                let row = (cursor.y - window.top + 1) as u16;
+                try!(self.interp.cup_seq(row, n as u16));
+            },
+            (false, b'd') => {
+                let cursor = self.screen().console.cursor();
+                try!(self.interp.cup_seq(n as u16, (cursor.x + 1) as u16));
+            },
+            _ => return Ok(false)
+        }
+        Ok(true)
+    }
+}
+
+impl AnsiInterpret for Terminal {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.interp.write_text(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.interp.flush()
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        self.screen().set_scroll_region(None);
+        self.screen().set_alternate_screen(false);
+        self.interp.ris_seq()
+    }
+
+    fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
+        if bytes == b"M" {
+            self.screen().reverse_index();
+            return Ok(());
+        }
+        if let Some((private, params, final_byte)) = parse_csi(bytes) {
+            if try!(self.csi_seq(private, &params, final_byte)) {
+                return Ok(());
+            }
+        }
+        self.interp.other_seq(bytes)
+    }
+
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> {
+        self.interp.sgr_attrs(attrs)
+    }
+
+    forward_seqs! { interp;
+        fn cuu_seq(&mut self, r: u16);
+        fn cud_seq(&mut self, r: u16);
+        fn cuf_seq(&mut self, c: u16);
+        fn cub_seq(&mut self, c: u16);
+        fn cup_seq(&mut self, r: u16, c: u16);
+        fn ed_seq(&mut self, n: EraseDisplay);
+        fn el_seq(&mut self, n: EraseLine);
+        fn xtpushsgr_seq(&mut self, mask: StyleMask);
+        fn xtpopsgr_seq(&mut self);
+        fn dsr_seq(&mut self);
+        fn scp_seq(&mut self);
+        fn rcp_seq(&mut self);
+        fn decsc_seq(&mut self);
+        fn decrc_seq(&mut self);
+        fn deckpam_seq(&mut self);
+        fn deckpnm_seq(&mut self);
+        fn decaln_seq(&mut self);
+        fn scs_seq(&mut self, slot: CharsetSlot, set: Charset);
+        fn ls_seq(&mut self, slot: CharsetSlot);
+        fn ss_seq(&mut self, slot: CharsetSlot);
+        fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool);
+        fn set_cursor_visible(&mut self, visible: bool);
+        fn set_line_wrap(&mut self, wrap: bool);
+        fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool);
+        fn xtwinops_seq(&mut self, op: WindowOp);
+        fn query_seq(&mut self, q: Query);
+        fn osc_txt_seq(&mut self, n: u16, txt: &str);
+        fn hvp_seq(&mut self, r: u16, c: u16);
+    }
+}
+
+/// Feed everything the child writes to the terminal, until there's nobody left on the other end.
+fn read_output(mut master: File, shared: &(Mutex<Screen>, Condvar)) {
+    let (ref lock, ref cvar) = *shared;
+    let mut buf = [0; 4096];
+    loop {
+        let bytes = match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(bytes) => bytes,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            // Linux reports the slave side closing as EIO.
+            Err(_) => break
+        };
+        let mut screen = lock.lock().unwrap();
+        if screen.term.write_all(&buf[..bytes]).is_err() {
+            break;
+        }
+        cvar.notify_all();
+    }
+    lock.lock().unwrap().done = true;
+    cvar.notify_all();
+}
+
+/// Open a new pseudo-terminal master, ready for the slave to be opened.
+fn open_master() -> io::Result<File> {
+    let fd = try!(cvt(unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) }));
+    let master = unsafe { File::from_raw_fd(fd) };
+    try!(cvt(unsafe { libc::grantpt(fd) }));
+    try!(cvt(unsafe { libc::unlockpt(fd) }));
+    Ok(master)
+}
+
+fn slave_name(master: &File) -> io::Result<String> {
+    let mut buf = [0 as libc::c_char; 128];
+    let r = unsafe { libc::ptsname_r(master.as_raw_fd(), buf.as_mut_ptr(), buf.len()) };
+    if r != 0 {
+        throw!(io::Error::from_raw_os_error(r));
+    }
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+#[test]
+fn test_term_screen() {
+    let mut screen = TermScreen::new(SimulatedConsole::new(5, 4, 4)).unwrap();
+    screen.write_text(b"ab\ncdefg").unwrap();
+    assert_eq!(screen.console.row_text(0), "ab");
+    assert_eq!(screen.console.row_text(1), "  cde");
+    assert_eq!(screen.console.row_text(2), "fg");
+
+    // The last column waits for another character before wrapping.
+    screen.write_text(b"\r12345").unwrap();
+    assert_eq!(screen.console.cursor(), Coord::new(4, 2));
+    screen.write_text(b"\r").unwrap();
+    assert_eq!(screen.console.row_text(3), "");
+
+    screen.set_scroll_region(Some((1, 2)));
+    screen.write_text(b"\nX").unwrap();
+    assert_eq!(screen.console.row_text(0), "ab");
+    assert_eq!(screen.console.row_text(1), "12345");
+    assert_eq!(screen.console.row_text(2), "X");
+    assert_eq!(screen.console.row_text(3), "");
+
+    screen.reverse_index();
+    screen.reverse_index();
+    assert_eq!(screen.console.row_text(1), "");
+    assert_eq!(screen.console.row_text(2), "12345");
+    screen.delete_lines(1);
+    assert_eq!(screen.console.row_text(1), "12345");
+    assert_eq!(screen.console.row_text(2), "");
+    screen.insert_lines(3);
+    assert_eq!(screen.console.row_text(1), "");
+    assert_eq!(screen.console.row_text(0), "ab");
+
+    screen.set_cursor_position(Coord::new(1, 0)).unwrap();
+    screen.insert_chars(2);
+    assert_eq!(screen.console.row_text(0), "a  b");
+    screen.delete_chars(1);
+    assert_eq!(screen.console.row_text(0), "a b");
+
+    screen.set_alternate_screen(true);
+    assert_eq!(screen.console.row_text(0), "");
+    screen.write_text(b"alt").unwrap();
+    screen.set_alternate_screen(false);
+    assert_eq!(screen.console.row_text(0), "a b");
+    assert_eq!(screen.console.cursor(), Coord::new(4, 0));
+}
@@ -3,28 +3,58 @@ This module is concerned with how to interpose an interpreter between Rust and t
 
 This is a *tremendous* pain in the ass.  Here's how it goes:
 */
-use std::io::{self, Read, Write};
+use std::io;
+use std::ptr;
 use std::sync::{Arc, Mutex};
-use std::thread;
-use self::mlw::*;
+use intercept::InterceptHandle;
 use util::SharedWrite;
+use super::SendHandle;
+use self::mlw::*;
 
-pub fn intercept_stdio() {
-    try_intercept_stdio().unwrap()
-}
+pub fn intercept_stdio() -> io::Result<InterceptHandle> {
+    use std::os::windows::io::{AsRawHandle, IntoRawHandle};
 
-fn try_intercept_stdio() -> io::Result<()> {
-    use std::os::windows::io::AsRawHandle;
+    // Declared first so that it's dropped last: by the time it joins the threads on a failure below, the write ends of their pipes are closed.
+    let mut handle = InterceptHandle::new();
 
-    // Get the current stdout handle.
+    // Get the current standard handles.
     let conin = try!(try!(get_std_handle(StdHandle::Input))
         .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no stdin handle available for this process")));
     let conout = try!(try!(get_std_handle(StdHandle::Output))
         .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no stdout handle available for this process")));
-    // let conerr = try!(try!(get_std_handle(StdHandle::Error))
-    //     .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no stderr handle available for this process")));
+    let conerr = try!(get_std_handle(StdHandle::Error));
+
+    // Remember them, so they can be put back later.
+    let original = [
+        SendHandle(conin.as_raw_handle()),
+        SendHandle(conout.as_raw_handle()),
+        SendHandle(conerr.as_ref().map(|h| h.as_raw_handle()).unwrap_or(ptr::null_mut())),
+    ];
+
+    // Put back exactly the handles that got installed, and close them so the threads see EOF once they've caught up.  This is set up before anything is redirected so that a failure part-way through undoes everything.
+    let installed: Arc<Mutex<Vec<(StdHandle, SendHandle)>>> = Arc::new(Mutex::new(vec![]));
+    handle.on_restore({
+        let installed = installed.clone();
+        move || {
+            // Keep going after a failure, or the threads reading the rest of the pipes would never finish.
+            let mut result = Ok(());
+            for (std_handle, installed) in installed.lock().unwrap().drain(..) {
+                let original = match std_handle {
+                    StdHandle::Input => &original[0],
+                    StdHandle::Output => &original[1],
+                    StdHandle::Error => &original[2],
+                };
+                let restored = set_std_handle(std_handle, original.0);
+                let closed = close_handle(installed.0);
+                if result.is_ok() {
+                    result = restored.and(closed);
+                }
+            }
+            result
+        }
+    });
 
-    // Create the pipe we'll use to capture stdout output.
+    // Create the pipes we'll use to capture input and output.
     let (irp, iwp) = try!(create_pipe());
     let (orp, owp) = try!(create_pipe());
     let (erp, ewp) = try!(create_pipe());
@@ -33,141 +63,27 @@ fn try_intercept_stdio() -> io::Result<()> {
 
     let iwp = SharedWrite::new(iwp);
 
-    let interp = super::ConsoleInterpreter::new(iwp.clone(), conout, console);
-    let interc = ::AnsiIntercept::new(interp);
-    let interc = Arc::new(Mutex::new(interc));
-
-    // Spin up the interpreter threads.
-    let _ = try!(thread::Builder::new()
-        .name(String::from("ansi_interpreter.stdin"))
-        .spawn({ move || {
-            let mut conin = conin;
-            let mut iwp = iwp;
-            let mut buf = [0; 4096];
-
-            loop {
-                let bytes = match conin.read(&mut buf) {
-                    Ok(0) => {
-                        // *Probably* EOF.
-                        return;
-                    },
-
-                    Ok(bytes) => bytes,
-
-                    Err(err) => {
-                        panic!("error while reading from stdin: {}", err);
-                    }
-                };
+    let interp = ::ConsoleInterpreter::new(iwp.clone(), super::Win32Console::new(conout, console));
+    let interp = ::CharsetTranslator::new(interp);
+    // stdout and stderr each get their own parser, so that a sequence split across writes to one isn't mangled by output to the other.
+    let interc = ::MultiSourceIntercept::new(interp);
 
-                // Send the bytes along to the Rust stdin.
-                let mut buf = &buf[..bytes];
-                while buf.len() > 0 {
-                    match iwp.write(buf) {
-                        Ok(0) => {
-                            // *Probably* cannot write any more.
-                            return;
-                        }
-
-                        Ok(b) => {
-                            buf = &buf[b..];
-                        },
-
-                        Err(err) => {
-                            panic!("error while writing to stdin pipe: {}", err);
-                        }
-                    }
-                }
-            }
-        } }));
-
-    let _ = try!(thread::Builder::new()
-        .name(String::from("ansi_interpreter.stdout"))
-        .spawn({ let interc = interc.clone(); move || {
-            let mut orp = orp;
-            let mut buf = [0; 4096];
-
-            loop {
-                let bytes = match orp.read(&mut buf) {
-                    Ok(0) => {
-                        // *Probably* EOF.
-                        return;
-                    },
-
-                    Ok(bytes) => bytes,
-
-                    Err(err) => {
-                        panic!("error while reading from stdout pipe: {}", err);
-                    }
-                };
+    // Spin up the interpreter threads.  Nothing can interrupt a console read, so the stdin thread is left to notice the pipe has gone the next time the user types something.
+    try!(handle.pump("ansi_interpreter.stdin", conin, iwp, false));
+    try!(handle.pump("ansi_interpreter.stdout", orp, interc.source(1), true));
+    try!(handle.pump("ansi_interpreter.stderr", erp, interc.source(2), true));
 
-                // Push those bytes through the interceptor.
-                let mut buf = &buf[..bytes];
-                while buf.len() > 0 {
-                    match interc.lock().unwrap().write(buf) {
-                        Ok(0) => {
-                            // *Probably* cannot write any more.
-                            return;
-                        }
-
-                        Ok(b) => {
-                            buf = &buf[b..];
-                        },
-
-                        Err(err) => {
-                            panic!("error while writing to stdout: {}", err);
-                        }
-                    }
-                }
-            }
-        } }));
-
-    let _ = try!(thread::Builder::new()
-        .name(String::from("ansi_interpreter.stderr"))
-        .spawn({ let interc = interc.clone(); move || {
-            let mut erp = erp;
-            let mut buf = [0; 4096];
-
-            loop {
-                let bytes = match erp.read(&mut buf) {
-                    Ok(0) => {
-                        // *Probably* EOF.
-                        return;
-                    },
-
-                    Ok(bytes) => bytes,
-
-                    Err(err) => {
-                        panic!("error while reading from stderr pipe: {}", err);
-                    }
-                };
+    // Anything the standard library is holding on to was meant for the originals.
+    try!(handle.flush());
 
-                // Push those bytes through the interceptor.
-                let mut buf = &buf[..bytes];
-                while buf.len() > 0 {
-                    match interc.lock().unwrap().write(buf) {
-                        Ok(0) => {
-                            // *Probably* cannot write any more.
-                            return;
-                        }
-
-                        Ok(b) => {
-                            buf = &buf[b..];
-                        },
-
-                        Err(err) => {
-                            panic!("error while writing to stderr: {}", err);
-                        }
-                    }
-                }
-            }
-        } }));
-
-    // Redirect the process handle.
-    try!(set_std_handle(StdHandle::Input, irp));
-    try!(set_std_handle(StdHandle::Output, owp));
-    try!(set_std_handle(StdHandle::Error, ewp));
+    // Redirect the process handles.  Until a pipe end is installed, it's ours to close; afterwards, the restore does it.
+    let installs = vec![(StdHandle::Input, irp), (StdHandle::Output, owp), (StdHandle::Error, ewp)];
+    for (std_handle, file) in installs {
+        try!(set_std_handle(std_handle, file.as_raw_handle()));
+        installed.lock().unwrap().push((std_handle, SendHandle(file.into_raw_handle())));
+    }
 
-    Ok(())
+    Ok(handle)
 }
 
 mod mlw {
@@ -175,11 +91,11 @@ mod mlw {
     extern crate winapi;
 
     use std::fs::File;
-    use std::io;
-    use std::mem::zeroed;
-    use std::os::windows::io::{FromRawHandle, IntoRawHandle};
+    use std::io::{self, Read, Write};
+    use std::mem::{zeroed, ManuallyDrop};
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
     use std::ptr;
-    use self::winapi::{DWORD, INVALID_HANDLE_VALUE};
+    use self::winapi::{DWORD, HANDLE, INVALID_HANDLE_VALUE};
 
     const DEFAULT_BUFFER_SIZE: DWORD = 0;
 
@@ -214,20 +130,55 @@ mod mlw {
         }
     }
 
-    pub fn get_std_handle(std_handle: StdHandle) -> io::Result<Option<File>> {
+    /**
+    One of the process's standard handles.  This doesn't close the handle when dropped, since it isn't ours to close.
+    */
+    pub struct StdFile(ManuallyDrop<File>);
+
+    impl AsRawHandle for StdFile {
+        fn as_raw_handle(&self) -> RawHandle {
+            self.0.as_raw_handle()
+        }
+    }
+
+    impl Read for StdFile {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Write for StdFile {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    pub fn get_std_handle(std_handle: StdHandle) -> io::Result<Option<StdFile>> {
         unsafe {
             match kernel32::GetStdHandle(std_handle.into_handle()) {
                 h if h == INVALID_HANDLE_VALUE => Err(io::Error::last_os_error()),
                 h if h.is_null() => Ok(None),
-                h => Ok(Some(File::from_raw_handle(h)))
+                h => Ok(Some(StdFile(ManuallyDrop::new(File::from_raw_handle(h)))))
+            }
+        }
+    }
+
+    pub fn set_std_handle(std_handle: StdHandle, handle: HANDLE) -> io::Result<()> {
+        unsafe {
+            match kernel32::SetStdHandle(std_handle.into_handle(), handle) {
+                0 => Err(io::Error::last_os_error()),
+                _ => Ok(())
             }
         }
     }
 
-    pub fn set_std_handle<H>(std_handle: StdHandle, handle: H) -> io::Result<()>
-    where H: IntoRawHandle {
+    pub fn close_handle(handle: HANDLE) -> io::Result<()> {
         unsafe {
-            match kernel32::SetStdHandle(std_handle.into_handle(), handle.into_raw_handle()) {
+            match kernel32::CloseHandle(handle) {
                 0 => Err(io::Error::last_os_error()),
                 _ => Ok(())
             }
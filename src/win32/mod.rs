@@ -1,13 +1,7 @@
-/*
-
-# Todo
-
-## Track the window
-
-Most terminals work by having a fixed-size window, where lines pushed off the top go into the scrollback buffer.  Windows has a giant buffer with a sliding window.
-
-It would produce more "expected" behaviour if the interpreter kept track of where the window was "up to", so that even if you scroll away, it can work out how to interpret window positions relative to the buffer.
+/*!
+Driving a real Windows console.
 
+Working out what to do with the console lives in the portable `console` module; this just provides the `ConsoleBackend` that actually does it, and the plumbing to put it between the process and its standard handles.
 */
 #![cfg(windows)]
 extern crate kernel32;
@@ -18,397 +12,199 @@ pub use self::intercept::intercept_stdio;
 
 mod intercept;
 
-use std::cmp::{max, min};
 use std::io::{self, Write};
+use std::mem;
 use self::winapi::{
-    DWORD, HANDLE, WORD,
-    CONSOLE_SCREEN_BUFFER_INFO, COORD,
+    DWORD, HANDLE,
+    CONSOLE_CURSOR_INFO, CONSOLE_FONT_INFO, CONSOLE_SCREEN_BUFFER_INFO, COORD, FALSE, SMALL_RECT,
 };
 use self::wio::wide::ToWide;
-use ansi::{EraseDisplay, EraseLine, AnsiInterpret};
-use conv::{ConvUtil, UnwrapOrSaturate};
-
-pub type GenError = Box<::std::error::Error + Send + Sync>;
-
-const COLOR_ALL: WORD = FOREGROUND_WHITE | FOREGROUND_INTENSITY | BACKGROUND_WHITE | BACKGROUND_INTENSITY;
-
-const FOREGROUND_SHIFT: usize = 0;
-const FOREGROUND_BLUE: WORD = winapi::FOREGROUND_BLUE as WORD;
-const FOREGROUND_GREEN: WORD = winapi::FOREGROUND_GREEN as WORD;
-const FOREGROUND_RED: WORD = winapi::FOREGROUND_RED as WORD;
-const FOREGROUND_INTENSITY: WORD = winapi::FOREGROUND_INTENSITY as WORD;
-const FOREGROUND_WHITE: WORD = FOREGROUND_RED | FOREGROUND_GREEN | FOREGROUND_BLUE;
-
-const BACKGROUND_SHIFT: usize = 4;
-const BACKGROUND_BLUE: WORD = winapi::BACKGROUND_BLUE as WORD;
-const BACKGROUND_GREEN: WORD = winapi::BACKGROUND_GREEN as WORD;
-const BACKGROUND_RED: WORD = winapi::BACKGROUND_RED as WORD;
-const BACKGROUND_INTENSITY: WORD = winapi::BACKGROUND_INTENSITY as WORD;
-const BACKGROUND_WHITE: WORD = BACKGROUND_RED | BACKGROUND_GREEN | BACKGROUND_BLUE;
-
-pub struct ConsoleInterpreter<WIn, WOut>
-where WIn: Write, WOut: Write {
-    stdin: WIn,
-    stdout: WOut,
+use console::{ConsoleBackend, Coord, CursorInfo, Rect, ScreenBufferInfo};
+
+/**
+A real Windows console screen buffer.
+
+Text goes out through `out`, which should be the console's output handle opened as a file; everything else goes through `console`.
+*/
+pub struct Win32Console<W>
+where W: Write {
+    out: W,
     console: SendHandle,
-    scp: COORD,
 }
 
-impl<WIn, WOut> ConsoleInterpreter<WIn, WOut>
-where WIn: Write, WOut: Write {
-    pub fn new(stdin: WIn, stdout: WOut, console: HANDLE) -> Self {
-        ConsoleInterpreter {
-            stdin: stdin,
-            stdout: stdout,
+impl<W> Win32Console<W>
+where W: Write {
+    pub fn new(out: W, console: HANDLE) -> Self {
+        Win32Console {
+            out: out,
             console: SendHandle(console),
-            scp: COORD {
-                X: 0,
-                Y: 0,
-            }
-        }
-    }
-
-    fn mut_text_attrs<F, R>(&self, f: F) -> Result<R, io::Error>
-    where F: FnOnce(&mut WORD) -> R {
-        unsafe {
-            let mut info = ::std::mem::zeroed();
-            if kernel32::GetConsoleScreenBufferInfo(self.console.0, &mut info) == 0 {
-                return Err(io::Error::last_os_error());
-            }
-            let mut attrs = info.wAttributes;
-            let r = f(&mut attrs);
-            if kernel32::SetConsoleTextAttribute(self.console.0, attrs) == 0 {
-                return Err(io::Error::last_os_error());
-            }
-            Ok(r)
         }
     }
 }
 
-impl<WIn, WOut> AnsiInterpret for ConsoleInterpreter<WIn, WOut>
-where WIn: Write, WOut: Write {
+impl<W> ConsoleBackend for Win32Console<W>
+where W: Write {
     fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.stdout.write(buf)
+        self.out.write(buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.stdout.flush()
+        self.out.flush()
     }
 
-    fn cuu_seq(&mut self, r: u16) -> Result<(), GenError> {
-        if r == 0 { return Ok(()); }
-
+    fn screen_buffer_info(&self) -> io::Result<ScreenBufferInfo> {
         let csbi = try!(get_console_screen_buffer_info(self.console.0));
-
-        let abs_y = csbi.dwCursorPosition.Y;
-        let abs_x = csbi.dwCursorPosition.X;
-
-        let abs_y = max(0, abs_y.saturating_sub(r.value_as::<i16>().unwrap_or_saturate()));
-
-        let abs_pos = COORD {
-            X: abs_x,
-            Y: abs_y,
-        };
-
-        try!(set_console_cursor_position(self.console.0, abs_pos));
-        Ok(())
+        Ok(ScreenBufferInfo {
+            size: from_coord(csbi.dwSize),
+            cursor: from_coord(csbi.dwCursorPosition),
+            attrs: csbi.wAttributes,
+            window: Rect::new(csbi.srWindow.Left, csbi.srWindow.Top, csbi.srWindow.Right, csbi.srWindow.Bottom),
+            max_window: from_coord(csbi.dwMaximumWindowSize),
+        })
     }
 
-    fn cud_seq(&mut self, r: u16) -> Result<(), GenError> {
-        if r == 0 { return Ok(()); }
-
-        let csbi = try!(get_console_screen_buffer_info(self.console.0));
-
-        let abs_y = csbi.dwCursorPosition.Y;
-        let abs_x = csbi.dwCursorPosition.X;
-
-        let abs_y = min(csbi.dwSize.Y - 1, abs_y.saturating_add(r.value_as::<i16>().unwrap_or_saturate()));
-
-        let abs_pos = COORD {
-            X: abs_x,
-            Y: abs_y,
-        };
-
-        try!(set_console_cursor_position(self.console.0, abs_pos));
+    fn set_cursor_position(&mut self, pos: Coord) -> io::Result<()> {
+        unsafe {
+            if kernel32::SetConsoleCursorPosition(self.console.0, to_coord(pos)) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
         Ok(())
     }
 
-    fn cuf_seq(&mut self, c: u16) -> Result<(), GenError> {
-        if c == 0 { return Ok(()); }
-
-        let csbi = try!(get_console_screen_buffer_info(self.console.0));
-
-        let abs_y = csbi.dwCursorPosition.Y;
-        let abs_x = csbi.dwCursorPosition.X;
-
-        let abs_x = min(csbi.dwSize.X - 1, abs_x.saturating_add(c.value_as::<i16>().unwrap_or_saturate()));
-
-        let abs_pos = COORD {
-            X: abs_x,
-            Y: abs_y,
-        };
-
-        try!(set_console_cursor_position(self.console.0, abs_pos));
+    fn set_text_attribute(&mut self, attrs: u16) -> io::Result<()> {
+        unsafe {
+            if kernel32::SetConsoleTextAttribute(self.console.0, attrs) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
         Ok(())
     }
 
-    fn cub_seq(&mut self, c: u16) -> Result<(), GenError> {
-        if c == 0 { return Ok(()); }
-
-        let csbi = try!(get_console_screen_buffer_info(self.console.0));
+    fn fill_attribute(&mut self, attrs: u16, len: u32, start: Coord) -> io::Result<u32> {
+        unsafe {
+            let mut written = 0;
+            if kernel32::FillConsoleOutputAttribute(self.console.0, attrs, len, to_coord(start), &mut written) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(written)
+        }
+    }
 
-        let abs_y = csbi.dwCursorPosition.Y;
-        let abs_x = csbi.dwCursorPosition.X;
+    fn fill_character(&mut self, ch: char, len: u32, start: Coord) -> io::Result<u32> {
+        // The console only stores one UTF-16 unit per cell.
+        let mut units = [0; 2];
+        let unit = ch.encode_utf16(&mut units)[0];
+        unsafe {
+            let mut written = 0;
+            if kernel32::FillConsoleOutputCharacterW(self.console.0, unit, len, to_coord(start), &mut written) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(written)
+        }
+    }
 
-        let abs_x = max(0, abs_x.saturating_sub(c.value_as::<i16>().unwrap_or_saturate()));
+    fn cursor_info(&self) -> io::Result<CursorInfo> {
+        let info = try!(get_console_cursor_info(self.console.0));
+        Ok(CursorInfo {
+            size: info.dwSize,
+            visible: info.bVisible != 0,
+        })
+    }
 
-        let abs_pos = COORD {
-            X: abs_x,
-            Y: abs_y,
+    fn set_cursor_info(&mut self, info: &CursorInfo) -> io::Result<()> {
+        let info = CONSOLE_CURSOR_INFO {
+            dwSize: info.size,
+            bVisible: if info.visible { winapi::TRUE } else { winapi::FALSE },
         };
-
-        try!(set_console_cursor_position(self.console.0, abs_pos));
-        Ok(())
+        set_console_cursor_info(self.console.0, &info)
     }
 
-    fn cup_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
-        let x = c.saturating_sub(1);
-        let y = r.saturating_sub(1);
-
-        let csbi = try!(get_console_screen_buffer_info(self.console.0));
-
-        let x = min(x, csbi.dwSize.X.value_as::<u16>().unwrap_or_saturate() - 1);
-        let y = min(y, csbi.dwSize.Y.value_as::<u16>().unwrap_or_saturate() - 1);
+    fn mode(&self) -> io::Result<u32> {
+        get_console_mode(self.console.0)
+    }
 
-        let abs_x = x + csbi.srWindow.Left.value_as::<u16>().unwrap_or_saturate();
-        let abs_y = y + csbi.srWindow.Top.value_as::<u16>().unwrap_or_saturate();
+    fn set_mode(&mut self, mode: u32) -> io::Result<()> {
+        set_console_mode(self.console.0, mode)
+    }
 
-        let abs_pos = COORD {
-            X: abs_x.value_as::<i16>().unwrap_or_saturate(),
-            Y: abs_y.value_as::<i16>().unwrap_or_saturate(),
+    fn set_window(&mut self, window: Rect) -> io::Result<()> {
+        let rect = SMALL_RECT {
+            Left: window.left,
+            Top: window.top,
+            Right: window.right,
+            Bottom: window.bottom,
         };
-
-        try!(set_console_cursor_position(self.console.0, abs_pos));
+        unsafe {
+            if kernel32::SetConsoleWindowInfo(self.console.0, winapi::TRUE, &rect) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
         Ok(())
     }
 
-    fn ed_seq(&mut self, n: EraseDisplay) -> Result<(), GenError> {
-        use ansi::EraseDisplay::*;
+    fn set_buffer_size(&mut self, size: Coord) -> io::Result<()> {
         unsafe {
-            let csbi = try!(get_console_screen_buffer_info(self.console.0));
-
-            let (start, len) = match n {
-                TopToCursor => {
-                    let start = COORD {
-                        X: 0,
-                        Y: csbi.srWindow.Top,
-                    };
-                    let lines = (csbi.dwCursorPosition.Y - start.Y) + 1;
-                    let lines = lines.value_as::<DWORD>().unwrap_or_saturate();
-                    let len = lines * csbi.dwSize.X.value_as::<DWORD>().unwrap_or_saturate();
-                    (start, len)
-                },
-                CursorToBottom => {
-                    let start = COORD {
-                        X: 0,
-                        Y: csbi.dwCursorPosition.Y,
-                    };
-                    let lines = (csbi.srWindow.Bottom - start.Y) + 1;
-                    let lines = lines.value_as::<DWORD>().unwrap_or_saturate();
-                    let len = lines * csbi.dwSize.X.value_as::<DWORD>().unwrap_or_saturate();
-                    (start, len)
-                },
-                All => {
-                    let start = COORD {
-                        X: 0,
-                        Y: csbi.srWindow.Top,
-                    };
-                    let lines = (csbi.srWindow.Bottom - start.Y) + 1;
-                    let lines = lines.value_as::<DWORD>().unwrap_or_saturate();
-                    let len = lines * csbi.dwSize.X.value_as::<DWORD>().unwrap_or_saturate();
-                    (start, len)
-                },
-            };
-
-            let mut dummy = 0;
-            if kernel32::FillConsoleOutputAttribute(self.console.0, csbi.wAttributes, len, start, &mut dummy) == 0 {
-                throw!(io::Error::last_os_error());
-            }
-            if kernel32::FillConsoleOutputCharacterW(self.console.0, 0x20, len, start, &mut dummy) == 0 {
-                throw!(io::Error::last_os_error());
+            if kernel32::SetConsoleScreenBufferSize(self.console.0, to_coord(size)) == 0 {
+                return Err(io::Error::last_os_error());
             }
-
-            Ok(())
         }
+        Ok(())
     }
 
-    fn el_seq(&mut self, n: EraseLine) -> Result<(), GenError> {
-        use ansi::EraseLine::*;
+    fn largest_window(&self) -> io::Result<Coord> {
         unsafe {
-            let csbi = try!(get_console_screen_buffer_info(self.console.0));
-
-            let (start, len) = match n {
-                StartToCursor => {
-                    let start = COORD {
-                        X: 0,
-                        Y: csbi.dwCursorPosition.Y,
-                    };
-                    let cols = csbi.dwCursorPosition.X + 1;
-                    let cols = cols.value_as::<DWORD>().unwrap_or_saturate();
-                    (start, cols)
-                },
-                CursorToEnd => {
-                    let start = COORD {
-                        X: csbi.dwCursorPosition.X,
-                        Y: csbi.dwCursorPosition.Y,
-                    };
-                    let cols = csbi.dwSize.X - csbi.dwCursorPosition.X;
-                    let cols = cols.value_as::<DWORD>().unwrap_or_saturate();
-                    (start, cols)
-                },
-                All => {
-                    let start = COORD {
-                        X: 0,
-                        Y: csbi.dwCursorPosition.Y,
-                    };
-                    let cols = csbi.dwSize.X;
-                    let cols = cols.value_as::<DWORD>().unwrap_or_saturate();
-                    (start, cols)
-                },
-            };
-
-            let mut dummy = 0;
-            if kernel32::FillConsoleOutputAttribute(self.console.0, csbi.wAttributes, len, start, &mut dummy) == 0 {
-                throw!(io::Error::last_os_error());
+            match kernel32::GetLargestConsoleWindowSize(self.console.0) {
+                COORD { X: 0, Y: 0 } => Err(io::Error::last_os_error()),
+                size => Ok(from_coord(size))
             }
-            if kernel32::FillConsoleOutputCharacterW(self.console.0, 0x20, len, start, &mut dummy) == 0 {
-                throw!(io::Error::last_os_error());
-            }
-
-            Ok(())
         }
     }
 
-    fn sgr_seq(&mut self, ns: &[u8]) -> Result<(), GenError> {
-        try!(self.flush());
-        for &n in ns {
-            match n {
-                0 => try!(self.mut_text_attrs(|attrs| {
-                    // Reset.
-                    *attrs = (*attrs & !COLOR_ALL) | FOREGROUND_WHITE;
-                })),
-                1 => try!(self.mut_text_attrs(|attrs| {
-                    // Bold.
-                    *attrs = *attrs | FOREGROUND_INTENSITY;
-                })),
-                22 => try!(self.mut_text_attrs(|attrs| {
-                    // Not-bold.
-                    *attrs = *attrs & !FOREGROUND_INTENSITY;
-                })),
-                n @ 30...37 => try!(self.mut_text_attrs(|attrs| {
-                    // Foreground.
-                    if let Some(c) = sgr_color_to_fg(n) {
-                        *attrs = (*attrs & !FOREGROUND_WHITE) | c;
-                    }
-                })),
-                39 => try!(self.mut_text_attrs(|attrs| {
-                    // Default-foreground.
-                    *attrs = (*attrs & !FOREGROUND_INTENSITY) | FOREGROUND_WHITE;
-                })),
-                _n @ 40...47 => try!(self.mut_text_attrs(|attrs| {
-                    // Background.
-                    if let Some(c) = sgr_color_to_bg(n) {
-                        *attrs = (*attrs & !BACKGROUND_WHITE) | c;
-                    }
-                })),
-                49 => try!(self.mut_text_attrs(|attrs| {
-                    // Default-background.
-                    *attrs = (*attrs & !BACKGROUND_INTENSITY) | BACKGROUND_WHITE;
-                })),
-                n @ 90...97 => try!(self.mut_text_attrs(|attrs| {
-                    // Bold-foreground.
-                    if let Some(c) = sgr_color_to_fg(n) {
-                        *attrs = (*attrs & !FOREGROUND_WHITE) | c;
-                    }
-                })),
-                _n @ 100...107 => try!(self.mut_text_attrs(|attrs| {
-                    // Bold-background.
-                    if let Some(c) = sgr_color_to_bg(n) {
-                        *attrs = (*attrs & !BACKGROUND_WHITE) | c;
-                    }
-                })),
-                _ => {
-                    // Do nothing.
-                }
+    fn font_size(&self) -> io::Result<Coord> {
+        unsafe {
+            let mut info = mem::zeroed::<CONSOLE_FONT_INFO>();
+            if kernel32::GetCurrentConsoleFont(self.console.0, FALSE, &mut info) == 0 {
+                return Err(io::Error::last_os_error());
             }
+            Ok(from_coord(info.dwFontSize))
         }
-        Ok(())
     }
 
-    fn dsr_seq(&mut self) -> Result<(), GenError> {
-        let csbi = try!(get_console_screen_buffer_info(self.console.0));
-
-        let abs_pos = csbi.dwCursorPosition;
-        let win = csbi.srWindow;
-
-        let rel_x = (abs_pos.X - win.Left).value_as::<u16>().unwrap_or_saturate() + 1;
-        let rel_y = (abs_pos.Y - win.Top).value_as::<u16>().unwrap_or_saturate() + 1;
-
-        try!(write!(self.stdin, "\x1b[{};{}R", rel_y, rel_x));
-        Ok(())
-    }
-
-    fn scp_seq(&mut self) -> Result<(), GenError> {
-        let info = try!(get_console_screen_buffer_info(self.console.0));
-        self.scp = info.dwCursorPosition;
-        Ok(())
+    fn title(&self) -> io::Result<String> {
+        get_console_title()
     }
 
-    fn rcp_seq(&mut self) -> Result<(), GenError> {
-        try!(set_console_cursor_position(self.console.0, self.scp));
-        Ok(())
-    }
-
-    fn osc_txt_seq(&mut self, n: u16, txt: &str) -> Result<(), GenError> {
+    fn set_title(&mut self, title: &str) -> io::Result<()> {
         unsafe {
-            match n {
-                0 | 2 => {
-                    let wtxt = txt.to_wide_null();
-                    if kernel32::SetConsoleTitleW(wtxt.as_ptr()) == 0 {
-                        throw!(io::Error::last_os_error())
-                    }
-                    Ok(())
-                },
-                _ => Ok(())
+            let wtxt = title.to_wide_null();
+            if kernel32::SetConsoleTitleW(wtxt.as_ptr()) == 0 {
+                return Err(io::Error::last_os_error());
             }
         }
+        Ok(())
     }
+}
 
-    fn hvp_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
-        self.cup_seq(r, c)
-    }
+fn from_coord(c: COORD) -> Coord {
+    Coord::new(c.X, c.Y)
+}
 
-    fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
-        let mut bs = String::new();
-        for b in bytes {
-            use std::fmt::Write;
-            write!(bs, "{:02x}", b).unwrap();
-        }
-        rethrow!(write!(self.stdout, "[UNK:{}]", bs))
-    }
+fn to_coord(c: Coord) -> COORD {
+    COORD { X: c.x, Y: c.y }
 }
 
 #[test]
 fn test_winapi_consts() {
-    use self::FOREGROUND_RED as FR;
-    use self::FOREGROUND_GREEN as FG;
-    use self::FOREGROUND_BLUE as FB;
-    use self::FOREGROUND_SHIFT as FS;
+    use console::FOREGROUND_RED as FR;
+    use console::FOREGROUND_GREEN as FG;
+    use console::FOREGROUND_BLUE as FB;
+    use console::FOREGROUND_SHIFT as FS;
 
-    use self::BACKGROUND_RED as BR;
-    use self::BACKGROUND_GREEN as BG;
-    use self::BACKGROUND_BLUE as BB;
-    use self::BACKGROUND_SHIFT as BS;
+    use console::BACKGROUND_RED as BR;
+    use console::BACKGROUND_GREEN as BG;
+    use console::BACKGROUND_BLUE as BB;
+    use console::BACKGROUND_SHIFT as BS;
 
     assert_eq!(1 << FS, FB);
     assert_eq!(2 << FS, FG);
@@ -417,60 +213,95 @@ fn test_winapi_consts() {
     assert_eq!(1 << BS, BB);
     assert_eq!(2 << BS, BG);
     assert_eq!(4 << BS, BR);
-}
 
-fn sgr_color_to_fg(n: u8) -> Option<WORD> {
-    use self::FOREGROUND_INTENSITY as FI;
-    use self::FOREGROUND_SHIFT as FS;
+    // The portable copies had better match the real thing.
+    use console::{FOREGROUND_INTENSITY, BACKGROUND_INTENSITY, COMMON_LVB_REVERSE_VIDEO, COMMON_LVB_UNDERSCORE};
+    use console::COMMON_LVB_TRAILING_BYTE;
+    use console::{ENABLE_PROCESSED_OUTPUT, ENABLE_WRAP_AT_EOL_OUTPUT};
+
+    assert_eq!(FR as DWORD, winapi::FOREGROUND_RED);
+    assert_eq!(FG as DWORD, winapi::FOREGROUND_GREEN);
+    assert_eq!(FB as DWORD, winapi::FOREGROUND_BLUE);
+    assert_eq!(FOREGROUND_INTENSITY as DWORD, winapi::FOREGROUND_INTENSITY);
+    assert_eq!(BR as DWORD, winapi::BACKGROUND_RED);
+    assert_eq!(BG as DWORD, winapi::BACKGROUND_GREEN);
+    assert_eq!(BB as DWORD, winapi::BACKGROUND_BLUE);
+    assert_eq!(BACKGROUND_INTENSITY as DWORD, winapi::BACKGROUND_INTENSITY);
+    assert_eq!(COMMON_LVB_REVERSE_VIDEO as DWORD, winapi::COMMON_LVB_REVERSE_VIDEO);
+    assert_eq!(COMMON_LVB_UNDERSCORE as DWORD, winapi::COMMON_LVB_UNDERSCORE);
+    assert_eq!(COMMON_LVB_TRAILING_BYTE as DWORD, winapi::COMMON_LVB_TRAILING_BYTE);
+    assert_eq!(ENABLE_PROCESSED_OUTPUT, winapi::ENABLE_PROCESSED_OUTPUT);
+    assert_eq!(ENABLE_WRAP_AT_EOL_OUTPUT, winapi::ENABLE_WRAP_AT_EOL_OUTPUT);
+}
 
-    fn split_bits(c: u8) -> WORD {
-        (((c & 1) << 2)
-            | (c & 2)
-            | ((c & 4) >> 2)) as WORD
+fn get_console_screen_buffer_info(console: HANDLE) -> io::Result<CONSOLE_SCREEN_BUFFER_INFO> {
+    unsafe {
+        let mut info = ::std::mem::zeroed();
+        if kernel32::GetConsoleScreenBufferInfo(console, &mut info) == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(info)
+        }
     }
-
-    Some(match n {
-        n @ 30...37 => split_bits(n - 30) << FS,
-        n @ 90...97 => (split_bits(n - 90) << FS) | FI,
-        39 => 0,
-
-        _ => return None
-    })
 }
 
-fn sgr_color_to_bg(n: u8) -> Option<WORD> {
-    use self::BACKGROUND_INTENSITY as BI;
-    use self::BACKGROUND_SHIFT as BS;
+fn get_console_title() -> io::Result<String> {
+    unsafe {
+        let mut buf = [0u16; 1024];
+        let len = kernel32::GetConsoleTitleW(buf.as_mut_ptr(), buf.len() as DWORD);
+        if len == 0 {
+            let err = io::Error::last_os_error();
+            // An empty title also returns zero, but leaves the error code alone.
+            match err.raw_os_error() {
+                Some(0) | None => Ok(String::new()),
+                Some(_) => Err(err)
+            }
+        } else {
+            Ok(String::from_utf16_lossy(&buf[..len as usize]))
+        }
+    }
+}
 
-    fn split_bits(c: u8) -> WORD {
-        (((c & 1) << 2)
-            | (c & 2)
-            | ((c & 4) >> 2)) as WORD
+fn get_console_cursor_info(console: HANDLE) -> io::Result<CONSOLE_CURSOR_INFO> {
+    unsafe {
+        let mut info = ::std::mem::zeroed();
+        if kernel32::GetConsoleCursorInfo(console, &mut info) == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(info)
+        }
     }
+}
 
-    Some(match n {
-        n @ 40...47 => split_bits(n - 40) << BS,
-        n @ 100...107 => (split_bits(n - 100) << BS) | BI,
-        49 => 0,
+fn set_console_cursor_info(console: HANDLE, info: &CONSOLE_CURSOR_INFO) -> io::Result<()> {
+    unsafe {
+        if kernel32::SetConsoleCursorInfo(console, info) == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
 
-        _ => return None
-    })
+/// Whether `handle` is a console.
+pub fn is_console(handle: ::std::os::windows::io::RawHandle) -> bool {
+    get_console_mode(handle as HANDLE).is_ok()
 }
 
-fn get_console_screen_buffer_info(console: HANDLE) -> io::Result<CONSOLE_SCREEN_BUFFER_INFO> {
+fn get_console_mode(console: HANDLE) -> io::Result<DWORD> {
     unsafe {
-        let mut info = ::std::mem::zeroed();
-        if kernel32::GetConsoleScreenBufferInfo(console, &mut info) == 0 {
+        let mut mode = 0;
+        if kernel32::GetConsoleMode(console, &mut mode) == 0 {
             Err(io::Error::last_os_error())
         } else {
-            Ok(info)
+            Ok(mode)
         }
     }
 }
 
-fn set_console_cursor_position(console: HANDLE, pos: COORD) -> io::Result<()> {
+fn set_console_mode(console: HANDLE, mode: DWORD) -> io::Result<()> {
     unsafe {
-        if kernel32::SetConsoleCursorPosition(console, pos) == 0 {
+        if kernel32::SetConsoleMode(console, mode) == 0 {
             Err(io::Error::last_os_error())
         } else {
             Ok(())
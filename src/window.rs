@@ -0,0 +1,192 @@
+/*!
+xterm window manipulation (XTWINOPS, `CSI Ps ; Ps ; Ps t`).
+*/
+use std::error::Error;
+use std::fmt;
+use conv::TryFrom;
+
+/**
+A decoded window operation.
+
+For the resize operations, `None` means "keep the current size" along that axis, and `Some(0)` means "use the size of the display".
+*/
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
+pub enum WindowOp {
+    Deiconify,
+    Iconify,
+    Move { x: u16, y: u16 },
+    ResizePixels { height: Option<u16>, width: Option<u16> },
+    Raise,
+    Lower,
+    Refresh,
+    ResizeChars { rows: Option<u16>, cols: Option<u16> },
+    RestoreMaximized,
+    Maximize,
+    ReportState,
+    ReportPosition,
+    ReportTextAreaPixels,
+    ReportCellSize,
+    ReportTextAreaSize,
+    ReportScreenSize,
+    ReportIconLabel,
+    ReportTitle,
+    /// DECSLPP: resize to this many lines.
+    ResizeLines(u16),
+}
+
+marker_error! {
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct UnknownWindowOp
+    impl {
+        desc {"unknown window operation"}
+    }
+}
+
+impl<'a> TryFrom<&'a [Option<u16>]> for WindowOp {
+    type Err = UnknownWindowOp;
+    fn try_from(ns: &'a [Option<u16>]) -> Result<WindowOp, Self::Err> {
+        use self::WindowOp::*;
+        let arg = |i: usize| ns.get(i).cloned().and_then(|n| n);
+        let op = match arg(0) {
+            Some(n) => n,
+            None => return Err(UnknownWindowOp)
+        };
+        match op {
+            1 => Ok(Deiconify),
+            2 => Ok(Iconify),
+            3 => Ok(Move { x: arg(1).unwrap_or(0), y: arg(2).unwrap_or(0) }),
+            4 => Ok(ResizePixels { height: arg(1), width: arg(2) }),
+            5 => Ok(Raise),
+            6 => Ok(Lower),
+            7 => Ok(Refresh),
+            8 => Ok(ResizeChars { rows: arg(1), cols: arg(2) }),
+            9 => match arg(1).unwrap_or(0) {
+                0 => Ok(RestoreMaximized),
+                1 => Ok(Maximize),
+                _ => Err(UnknownWindowOp)
+            },
+            11 => Ok(ReportState),
+            13 => Ok(ReportPosition),
+            14 => Ok(ReportTextAreaPixels),
+            16 => Ok(ReportCellSize),
+            18 => Ok(ReportTextAreaSize),
+            19 => Ok(ReportScreenSize),
+            20 => Ok(ReportIconLabel),
+            21 => Ok(ReportTitle),
+            n if n >= 24 => Ok(ResizeLines(n)),
+            _ => Err(UnknownWindowOp)
+        }
+    }
+}
+
+#[test]
+fn test_window_op_try_from() {
+    use conv::TryInto;
+    use self::WindowOp::*;
+    fn op(ns: &[Option<u16>]) -> Result<WindowOp, UnknownWindowOp> { ns.try_into() }
+
+    assert_eq!(op(&[Some(8), Some(24), Some(80)]), Ok(ResizeChars { rows: Some(24), cols: Some(80) }));
+    assert_eq!(op(&[Some(8), None, Some(80)]), Ok(ResizeChars { rows: None, cols: Some(80) }));
+    assert_eq!(op(&[Some(4)]), Ok(ResizePixels { height: None, width: None }));
+    assert_eq!(op(&[Some(9), Some(1)]), Ok(Maximize));
+    assert_eq!(op(&[Some(18)]), Ok(ReportTextAreaSize));
+    assert_eq!(op(&[Some(48)]), Ok(ResizeLines(48)));
+    assert_eq!(op(&[]), Err(UnknownWindowOp));
+    assert_eq!(op(&[None]), Err(UnknownWindowOp));
+    assert_eq!(op(&[Some(12)]), Err(UnknownWindowOp));
+}
+
+impl fmt::Display for WindowOp {
+    /**
+    Formats the operation as the parameters of an XTWINOPS sequence; `None` sizes are left empty.
+    */
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::WindowOp::*;
+
+        fn opt(n: Option<u16>) -> String {
+            n.map(|n| n.to_string()).unwrap_or_default()
+        }
+
+        match *self {
+            Deiconify => write!(fmt, "1"),
+            Iconify => write!(fmt, "2"),
+            Move { x, y } => write!(fmt, "3;{};{}", x, y),
+            ResizePixels { height, width } => write!(fmt, "4;{};{}", opt(height), opt(width)),
+            Raise => write!(fmt, "5"),
+            Lower => write!(fmt, "6"),
+            Refresh => write!(fmt, "7"),
+            ResizeChars { rows, cols } => write!(fmt, "8;{};{}", opt(rows), opt(cols)),
+            RestoreMaximized => write!(fmt, "9;0"),
+            Maximize => write!(fmt, "9;1"),
+            ReportState => write!(fmt, "11"),
+            ReportPosition => write!(fmt, "13"),
+            ReportTextAreaPixels => write!(fmt, "14"),
+            ReportCellSize => write!(fmt, "16"),
+            ReportTextAreaSize => write!(fmt, "18"),
+            ReportScreenSize => write!(fmt, "19"),
+            ReportIconLabel => write!(fmt, "20"),
+            ReportTitle => write!(fmt, "21"),
+            ResizeLines(n) => write!(fmt, "{}", n),
+        }
+    }
+}
+
+#[test]
+fn test_window_op_display() {
+    use conv::TryInto;
+    use self::WindowOp::*;
+
+    let ops = [
+        Deiconify, Iconify, Move { x: 10, y: 20 }, ResizePixels { height: Some(480), width: None },
+        Raise, Lower, Refresh, ResizeChars { rows: None, cols: Some(132) }, ResizeChars { rows: Some(0), cols: Some(0) },
+        RestoreMaximized, Maximize, ReportState, ReportPosition, ReportTextAreaPixels, ReportCellSize,
+        ReportTextAreaSize, ReportScreenSize, ReportIconLabel, ReportTitle, ResizeLines(48),
+    ];
+    for &op in &ops {
+        let ns: Vec<Option<u16>> = op.to_string().split(';').map(|n| n.parse().ok()).collect();
+        assert_eq!(ns[..].try_into(), Ok(op));
+    }
+    assert_eq!(ResizeChars { rows: None, cols: Some(132) }.to_string(), "8;;132");
+}
+
+/**
+An answer to one of the `WindowOp` reports.
+
+The `Display` implementation produces the exact bytes xterm would send back.
+*/
+#[derive(Clone, Eq, PartialEq, Debug, Hash)]
+pub enum WindowReport {
+    State { iconified: bool },
+    Position { x: u16, y: u16 },
+    TextAreaPixels { height: u16, width: u16 },
+    CellSize { height: u16, width: u16 },
+    TextAreaSize { rows: u16, cols: u16 },
+    ScreenSize { rows: u16, cols: u16 },
+    IconLabel(String),
+    Title(String),
+}
+
+impl fmt::Display for WindowReport {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use self::WindowReport::*;
+        match *self {
+            State { iconified } => write!(fmt, "\x1b[{}t", if iconified { 2 } else { 1 }),
+            Position { x, y } => write!(fmt, "\x1b[3;{};{}t", x, y),
+            TextAreaPixels { height, width } => write!(fmt, "\x1b[4;{};{}t", height, width),
+            CellSize { height, width } => write!(fmt, "\x1b[6;{};{}t", height, width),
+            TextAreaSize { rows, cols } => write!(fmt, "\x1b[8;{};{}t", rows, cols),
+            ScreenSize { rows, cols } => write!(fmt, "\x1b[9;{};{}t", rows, cols),
+            IconLabel(ref s) => write!(fmt, "\x1b]L{}\x1b\\", s),
+            Title(ref s) => write!(fmt, "\x1b]l{}\x1b\\", s),
+        }
+    }
+}
+
+#[test]
+fn test_window_report_display() {
+    use self::WindowReport::*;
+    assert_eq!(TextAreaSize { rows: 24, cols: 80 }.to_string(), "\x1b[8;24;80t");
+    assert_eq!(CellSize { height: 16, width: 8 }.to_string(), "\x1b[6;16;8t");
+    assert_eq!(State { iconified: false }.to_string(), "\x1b[1t");
+    assert_eq!(Title(String::from("hi")).to_string(), "\x1b]lhi\x1b\\");
+}
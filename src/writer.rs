@@ -0,0 +1,268 @@
+/*!
+Turning interpreted events back into escape sequences.
+*/
+use std::io::{self, Write};
+use ansisys::ScreenMode;
+use ansi::{AnsiInterpret, CursorShape, EraseDisplay, EraseLine, GenError};
+use charset::{Charset, CharsetSlot};
+use query::Query;
+use sgr::{self, SgrAttrs};
+use style::StyleMask;
+use window::WindowOp;
+
+/**
+An interpreter that writes every event back out as a standard escape sequence.
+
+On its own this is a long way round to copying bytes, but it gives the other interpreters something to pass events on to: wrapping it in a `ColorReducer`, for instance, gives output that's the same except for its colours.  Sequences the parser didn't understand are written out unchanged.
+*/
+pub struct AnsiWriter<W>
+where W: Write {
+    out: W,
+}
+
+impl<W> AnsiWriter<W>
+where W: Write {
+    pub fn new(out: W) -> Self {
+        AnsiWriter {
+            out: out,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.out
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.out
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W> AnsiInterpret for AnsiWriter<W>
+where W: Write {
+    fn write_text(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    fn cuu_seq(&mut self, r: u16) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[{}A", r))
+    }
+
+    fn cud_seq(&mut self, r: u16) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[{}B", r))
+    }
+
+    fn cuf_seq(&mut self, c: u16) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[{}C", c))
+    }
+
+    fn cub_seq(&mut self, c: u16) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[{}D", c))
+    }
+
+    fn cup_seq(&mut self, r: u16, c: u16) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[{};{}H", r, c))
+    }
+
+    fn ed_seq(&mut self, n: EraseDisplay) -> Result<(), GenError> {
+        let n = match n {
+            EraseDisplay::CursorToBottom => 0,
+            EraseDisplay::TopToCursor => 1,
+            EraseDisplay::All => 2,
+        };
+        rethrow!(write!(self.out, "\x1b[{}J", n))
+    }
+
+    fn el_seq(&mut self, n: EraseLine) -> Result<(), GenError> {
+        let n = match n {
+            EraseLine::CursorToEnd => 0,
+            EraseLine::StartToCursor => 1,
+            EraseLine::All => 2,
+        };
+        rethrow!(write!(self.out, "\x1b[{}K", n))
+    }
+
+    fn sgr_attrs(&mut self, attrs: SgrAttrs) -> Result<(), GenError> {
+        let attrs: Vec<String> = attrs
+            .filter(|&attr| sgr::is_reencodable(attr))
+            .map(|attr| attr.to_string())
+            .collect();
+        if attrs.is_empty() {
+            return Ok(());
+        }
+        rethrow!(write!(self.out, "\x1b[{}m", attrs.join(";")))
+    }
+
+    fn xtpushsgr_seq(&mut self, mask: StyleMask) -> Result<(), GenError> {
+        if mask == StyleMask::all() {
+            return rethrow!(write!(self.out, "\x1b[#{{"));
+        }
+        let ps: Vec<&str> = [
+            (mask.bold, "1"), (mask.faint, "2"), (mask.italic, "3"), (mask.underline, "4"),
+            (mask.blink, "5"), (mask.reverse, "7"), (mask.conceal, "8"), (mask.crossed_out, "9"),
+            (mask.fg, "30"), (mask.bg, "31"),
+        ].iter().filter(|&&(set, _)| set).map(|&(_, p)| p).collect();
+        rethrow!(write!(self.out, "\x1b[{}#{{", ps.join(";")))
+    }
+
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[#}}"))
+    }
+
+    fn dsr_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[6n"))
+    }
+
+    fn scp_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[s"))
+    }
+
+    fn rcp_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[u"))
+    }
+
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b7"))
+    }
+
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b8"))
+    }
+
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1bc"))
+    }
+
+    fn deckpam_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b="))
+    }
+
+    fn deckpnm_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b>"))
+    }
+
+    fn decaln_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b#8"))
+    }
+
+    fn scs_seq(&mut self, slot: CharsetSlot, set: Charset) -> Result<(), GenError> {
+        let inter = match slot {
+            CharsetSlot::G0 => '(',
+            CharsetSlot::G1 => ')',
+            CharsetSlot::G2 => '*',
+            CharsetSlot::G3 => '+',
+        };
+        rethrow!(write!(self.out, "\x1b{}{}", inter, set.final_byte() as char))
+    }
+
+    fn ls_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> {
+        let seq: &[u8] = match slot {
+            CharsetSlot::G0 => b"\x0f",
+            CharsetSlot::G1 => b"\x0e",
+            CharsetSlot::G2 => b"\x1bn",
+            CharsetSlot::G3 => b"\x1bo",
+        };
+        rethrow!(self.out.write_all(seq))
+    }
+
+    fn ss_seq(&mut self, slot: CharsetSlot) -> Result<(), GenError> {
+        match slot {
+            CharsetSlot::G2 => rethrow!(write!(self.out, "\x1bN")),
+            CharsetSlot::G3 => rethrow!(write!(self.out, "\x1bO")),
+            // There's no single shift for these.
+            CharsetSlot::G0 | CharsetSlot::G1 => Ok(())
+        }
+    }
+
+    fn set_cursor_style(&mut self, shape: CursorShape, blinking: bool) -> Result<(), GenError> {
+        let n = match shape {
+            CursorShape::Block => 1,
+            CursorShape::Underline => 3,
+            CursorShape::Bar => 5,
+        } + if blinking { 0 } else { 1 };
+        rethrow!(write!(self.out, "\x1b[{} q", n))
+    }
+
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[?25{}", if visible { 'h' } else { 'l' }))
+    }
+
+    fn set_line_wrap(&mut self, wrap: bool) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[?7{}", if wrap { 'h' } else { 'l' }))
+    }
+
+    fn screen_mode_seq(&mut self, mode: ScreenMode, set: bool) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[={}{}", mode as u8, if set { 'h' } else { 'l' }))
+    }
+
+    fn xtwinops_seq(&mut self, op: WindowOp) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b[{}t", op))
+    }
+
+    fn query_seq(&mut self, q: Query) -> Result<(), GenError> {
+        match q {
+            Query::CursorPosition => self.dsr_seq(),
+            Query::Status => rethrow!(write!(self.out, "\x1b[5n")),
+            Query::PrimaryDeviceAttributes => rethrow!(write!(self.out, "\x1b[c")),
+            Query::SecondaryDeviceAttributes => rethrow!(write!(self.out, "\x1b[>c")),
+            Query::TertiaryDeviceAttributes => rethrow!(write!(self.out, "\x1b[=c")),
+            Query::Mode { private, mode } => rethrow!(write!(self.out, "\x1b[{}{}$p", if private { "?" } else { "" }, mode)),
+            Query::Setting(ref pt) => rethrow!(write!(self.out, "\x1bP$q{}\x1b\\", pt)),
+            Query::Version => rethrow!(write!(self.out, "\x1b[>q")),
+            Query::Capabilities(ref names) => {
+                let names: Vec<String> = names.iter()
+                    .map(|name| name.bytes().map(|b| format!("{:02X}", b)).collect())
+                    .collect();
+                rethrow!(write!(self.out, "\x1bP+q{}\x1b\\", names.join(";")))
+            },
+        }
+    }
+
+    fn osc_txt_seq(&mut self, n: u16, txt: &str) -> Result<(), GenError> {
+        rethrow!(write!(self.out, "\x1b]{};{}\x1b\\", n, txt))
+    }
+
+    fn other_seq(&mut self, bytes: &[u8]) -> Result<(), GenError> {
+        try!(self.out.write_all(b"\x1b"));
+        rethrow!(self.out.write_all(bytes))
+    }
+}
+
+#[test]
+fn test_ansi_writer() {
+    use ansi::AnsiIntercept;
+
+    fn rewrite(input: &str) -> String {
+        let mut out = AnsiIntercept::new(AnsiWriter::new(vec![]));
+        out.write_all(input.as_bytes()).unwrap();
+        String::from_utf8(out.get_ref().get_ref().clone()).unwrap()
+    }
+
+    // Anything already in the standard form comes out the same.
+    let same = [
+        "plain text",
+        "\x1b[3A\x1b[1B\x1b[10C\x1b[2D\x1b[5;7H\x1b[0J\x1b[1J\x1b[2J\x1b[0K\x1b[2K",
+        "\x1b[0;1;38;5;208;48;2;1;2;3;4:3m\x1b[#{\x1b[1;30#{\x1b[#}",
+        "\x1b[6n\x1b[5n\x1b[c\x1b[>c\x1b[=c\x1b[?25$p\x1b[4$p\x1bP$qm\x1b\\\x1b[>q\x1bP+q636F6C6F7273;524742\x1b\\",
+        "\x1b[s\x1b[u\x1b7\x1b8\x1bc\x1b=\x1b>\x1b#8",
+        "\x1b(0q\x1b(B\x1b)A\x0eq\x0f\x1bn\x1bo\x1bNx\x1bOy",
+        "\x1b[2 q\x1b[5 q\x1b[?25l\x1b[?7h\x1b[=13h\x1b[8;24;80t\x1b[9;1t\x1b]2;Title\x1b\\",
+        "\x1b[?1049h\x1b[12X\x1b[3g",
+    ];
+    for &s in &same {
+        assert_eq!(rewrite(s), s);
+    }
+
+    // Everything else comes out in the standard form.
+    assert_eq!(rewrite("\x1b[A\x1b[H\x1b[J\x1b[m\x1b[;1m\x1b[3;4f\x1b[0 q\x1b]0;t\x07"),
+        "\x1b[1A\x1b[1;1H\x1b[0J\x1b[0m\x1b[0;1m\x1b[3;4H\x1b[1 q\x1b]0;t\x1b\\");
+    assert_eq!(rewrite("\x1b[38;5m"), "");
+    assert_eq!(rewrite("\x1b[?25;1049l"), "\x1b[?25l\x1b[?1049l");
+}
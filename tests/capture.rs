@@ -0,0 +1,77 @@
+#![cfg(unix)]
+extern crate ansi_interpreter as ai;
+
+use std::env;
+use std::io::{self, Write};
+use std::process::Command;
+use std::thread;
+use ai::{Color, Event, SgrAttr};
+
+#[test]
+fn test_capture_stdio() {
+    let captured = ai::capture_stdio(|| {
+        io::stdout().write_all(b"hello \x1b[32mworld\x1b[m\n").unwrap();
+        io::stderr().write_all(b"\x1b[2K\x1b[1;1Hwarning").unwrap();
+        // Child processes write straight to the same descriptors.
+        let status = Command::new("sh").arg("-c").arg("printf 'from \\033[1mchild\\033[0m'").status().unwrap();
+        assert!(status.success());
+    }).unwrap();
+
+    assert_eq!(captured.stdout, vec![
+        Event::Text(String::from("hello ")),
+        Event::Sgr(vec![SgrAttr::Foreground(Color::Indexed(2))]),
+        Event::Text(String::from("world")),
+        Event::Sgr(vec![SgrAttr::Reset]),
+        Event::Text(String::from("\nfrom ")),
+        Event::Sgr(vec![SgrAttr::Bold]),
+        Event::Text(String::from("child")),
+        Event::Sgr(vec![SgrAttr::Reset]),
+    ]);
+    assert_eq!(captured.stdout_text(), "hello world\nfrom child");
+    assert_eq!(captured.stderr, vec![
+        Event::El(ai::EraseLine::All),
+        Event::Cup(1, 1),
+        Event::Text(String::from("warning")),
+    ]);
+}
+
+/// Prints when run as a child of `test_capture_println`, and does nothing otherwise.
+#[test]
+fn println_child() {
+    if env::var_os("CAPTURE_PRINTLN_CHILD").is_some() {
+        println!("printed \x1b[4munderlined\x1b[24m");
+    }
+}
+
+#[test]
+fn test_capture_println() {
+    // `println!` only reaches the real standard output when the harness isn't capturing it, so run this binary again as a child.
+    let captured = ai::capture_stdio(|| {
+        let status = Command::new(env::current_exe().unwrap())
+            .arg("--exact").arg("println_child").arg("--nocapture")
+            .env("CAPTURE_PRINTLN_CHILD", "1")
+            .status().unwrap();
+        assert!(status.success());
+    }).unwrap();
+
+    // The harness's own chatter comes along too.
+    assert!(captured.stdout.windows(2).any(|events| events == &[
+        Event::Sgr(vec![SgrAttr::Underline(ai::UnderlineStyle::Single)]),
+        Event::Text(String::from("underlined")),
+    ][..]));
+    assert!(captured.stdout_text().contains("printed underlined\n"));
+}
+
+#[test]
+fn test_capture_threads() {
+    // Captures on other threads don't see each other's output.
+    let threads: Vec<_> = (0..4).map(|i| thread::spawn(move || {
+        ai::capture_stdio(|| {
+            write!(io::stdout(), "thread {}", i).unwrap();
+        }).unwrap()
+    })).collect();
+
+    for (i, thread) in threads.into_iter().enumerate() {
+        assert_eq!(thread.join().unwrap().stdout_text(), format!("thread {}", i));
+    }
+}
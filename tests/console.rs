@@ -0,0 +1,125 @@
+extern crate ansi_interpreter as ai;
+
+use std::io::Write;
+use ai::{ConsoleBackend, Coord, Rect};
+
+type Console = ai::AnsiIntercept<ai::ConsoleInterpreter<Vec<u8>, ai::SimulatedConsole>>;
+
+fn console(cols: i16, rows: i16, buffer_rows: i16) -> Console {
+    ai::AnsiIntercept::new(ai::ConsoleInterpreter::new(vec![], ai::SimulatedConsole::new(cols, rows, buffer_rows)))
+}
+
+fn sim(con: &Console) -> &ai::SimulatedConsole {
+    con.get_ref().get_ref()
+}
+
+#[test]
+fn test_console_cursor() {
+    let mut con = console(10, 4, 4);
+    write!(con, "one\r\ntwo\r\nthree\x1b[2;2HX\x1b[2BY\x1b[3CZ\x1b[20AW\x1b[4;9H!").unwrap();
+    assert_eq!(sim(&con).window_text(), vec!["one    W", "tXo", "three", "  Y   Z !"]);
+
+    // Erasing uses the current attributes.
+    write!(con, "\x1b[44m\x1b[1;3H\x1b[K\x1b[4;5H\x1b[1K\x1b[2;1H\x1b[2K").unwrap();
+    assert_eq!(sim(&con).window_text(), vec!["on", "", "three", "      Z !"]);
+    assert_eq!(sim(&con).cell(Coord::new(5, 0)), Some((' ', 0x17)));
+    assert_eq!(sim(&con).cell(Coord::new(1, 0)), Some(('n', 0x07)));
+    write!(con, "\x1b[3;1H\x1b[0J").unwrap();
+    assert_eq!(sim(&con).window_text(), vec!["on", "", "", ""]);
+    write!(con, "\x1b[2;3H\x1b[1J").unwrap();
+    assert_eq!(sim(&con).window_text(), vec!["", "", "", ""]);
+}
+
+#[test]
+fn test_console_attrs() {
+    let mut con = console(20, 2, 2);
+    write!(con, "a\x1b[1;31mb\x1b[7;42mc\x1b[s\x1b[0;93md\x1b[u\x1b[Ce\x1b[mf").unwrap();
+    let attrs: Vec<u16> = (0..6).map(|x| sim(&con).cell(Coord::new(x, 0)).unwrap().1).collect();
+    assert_eq!(attrs, vec![0x07, 0x0c, 0x402c, 0x0e, 0x402c, 0x07]);
+    assert_eq!(sim(&con).row_text(0), "abcdef");
+
+    // Revealing brings back the colour from before the text was concealed.
+    write!(con, "\r\n\x1b[31;8mg\x1b[28mh\x1b[m").unwrap();
+    let attrs: Vec<u16> = (0..2).map(|x| sim(&con).cell(Coord::new(x, 1)).unwrap().1).collect();
+    assert_eq!(attrs, vec![0x00, 0x04]);
+}
+
+#[test]
+fn test_console_queries() {
+    let mut replies = vec![];
+    {
+        let sim = ai::SimulatedConsole::new(20, 3, 10);
+        let mut con = ai::AnsiIntercept::new(ai::ConsoleInterpreter::new(&mut replies, sim));
+        write!(con, "\n\n\n\nab\x1b[6n\x1b[?25l\x1b[?25$p\x1b]2;Title\x07\x1b[21t\x1b[18t\x1b[16t").unwrap();
+        let sim = con.get_ref().get_ref();
+        assert_eq!(sim.cursor(), Coord::new(2, 4));
+        assert_eq!(sim.window(), Rect::new(0, 2, 19, 4));
+        assert!(!sim.cursor_info().unwrap().visible);
+        assert_eq!(sim.title().unwrap(), "Title");
+    }
+    assert_eq!(String::from_utf8(replies).unwrap(), "\x1b[3;3R\x1b[?25;2$y\x1b]lTitle\x1b\\\x1b[8;3;20t\x1b[6;16;8t");
+}
+
+#[test]
+fn test_console_resize() {
+    let mut s = ai::SimulatedConsole::new(20, 3, 5);
+    s.set_max_window(Coord::new(40, 25));
+    let mut con = ai::AnsiIntercept::new(ai::ConsoleInterpreter::new(vec![], s));
+    write!(con, "\x1b[8;6;30t").unwrap();
+    assert_eq!(sim(&con).size(), Coord::new(30, 6));
+    assert_eq!(sim(&con).window(), Rect::new(0, 0, 29, 5));
+    write!(con, "\x1b[8;2;10t").unwrap();
+    assert_eq!(sim(&con).size(), Coord::new(10, 6));
+    assert_eq!(sim(&con).window(), Rect::new(0, 0, 9, 1));
+    // Zero means as big as possible, which is limited by the buffer.
+    write!(con, "\x1b[8;0;0t").unwrap();
+    assert_eq!(sim(&con).window(), Rect::new(0, 0, 9, 5));
+    // Anything bigger than the screen is cut down to fit it.
+    write!(con, "\x1b[8;32767;32767t").unwrap();
+    assert_eq!(sim(&con).size(), Coord::new(40, 25));
+    assert_eq!(sim(&con).window(), Rect::new(0, 0, 39, 24));
+    write!(con, "\x1b[8;3;20t\x1b[60000t").unwrap();
+    assert_eq!(sim(&con).window(), Rect::new(0, 0, 19, 24));
+}
+
+#[test]
+fn test_console_viewport() {
+    let mut replies = vec![];
+    {
+        let mut con = ai::AnsiIntercept::new(ai::ConsoleInterpreter::new(&mut replies, ai::SimulatedConsole::new(10, 3, 10)));
+        write!(con, "1\n2\n3\n4\n5").unwrap();
+        assert_eq!(con.get_ref().get_ref().window(), Rect::new(0, 2, 9, 4));
+
+        // The user scrolls back to the top; none of this should notice.
+        con.get_mut().get_mut().set_window(Rect::new(0, 0, 9, 2)).unwrap();
+        write!(con, "\x1b[6n\x1b[2J\x1b[1;1HX\x1b[10A\x1b[10BW").unwrap();
+
+        // Output off the bottom moves the screen down.
+        write!(con, "\n\nZ\x1b[1;3HY\x1b[6n").unwrap();
+
+        let sim = con.get_ref().get_ref();
+        let rows: Vec<String> = (0..7).map(|y| sim.row_text(y)).collect();
+        assert_eq!(rows, vec!["1", "2", "X", "", " WY", "", "Z"]);
+    }
+    assert_eq!(String::from_utf8(replies).unwrap(), "\x1b[3;2R\x1b[1;4R");
+}
+
+#[test]
+fn test_console_render() {
+    let mut con = console(16, 3, 3);
+    write!(con, "plain \x1b[1;31mred\x1b[m\r\n\x1b[44m  \x1b[m  \x1b[7;4mx\x1b[m      y\r\n\x1b[93;101mloud\x1b[39mer").unwrap();
+    let cells: Vec<(char, u16)> = (0..3).flat_map(|y| (0..16).map(move |x| Coord::new(x, y)))
+        .map(|pos| sim(&con).cell(pos).unwrap())
+        .collect();
+    let rendered = ai::render_cells(&cells, 16, 0x07);
+    assert_eq!(String::from_utf8(rendered.clone()).unwrap(),
+        "\x1b[H\x1b[2J\x1b[1Hplain \x1b[91mred\x1b[2H\x1b[0;44m  \x1b[0m  \x1b[4;7mx\x1b[6C\x1b[0my\x1b[3H\x1b[93;101mloud\x1b[39mer\x1b[0m");
+
+    // Drawing it again gets the same picture.
+    let mut copy = console(16, 3, 3);
+    copy.write_all(&rendered).unwrap();
+    assert_eq!(sim(&copy).window_text(), sim(&con).window_text());
+    for (i, &cell) in cells.iter().enumerate() {
+        assert_eq!(sim(&copy).cell(Coord::new(i as i16 % 16, i as i16 / 16)), Some(cell));
+    }
+}
@@ -45,9 +45,20 @@ impl<W: Write> ai::AnsiInterpret for Dump<W> {
     fn el_seq(&mut self, n: ai::EraseLine) -> Result<(), GenError> {
         rethrow!(write!(self.0, "[EL:{}]", n as u8))
     }
-    fn sgr_seq(&mut self, ns: &[u8]) -> Result<(), GenError> {
-        let ns = ns.iter().join(",");
-        rethrow!(write!(self.0, "[SGR:{}]", ns))
+    fn sgr_attrs(&mut self, mut attrs: ai::SgrAttrs) -> Result<(), GenError> {
+        let attrs = attrs.join(",");
+        rethrow!(write!(self.0, "[SGR:{}]", attrs))
+    }
+    fn xtpushsgr_seq(&mut self, mask: ai::StyleMask) -> Result<(), GenError> {
+        if mask == ai::StyleMask::all() {
+            rethrow!(self.0.write_all(b"[XTPUSHSGR]"))
+        } else {
+            rethrow!(write!(self.0, "[XTPUSHSGR:{}{}]",
+                if mask.fg { "fg" } else { "" }, if mask.bg { "bg" } else { "" }))
+        }
+    }
+    fn xtpopsgr_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(self.0.write_all(b"[XTPOPSGR]"))
     }
     fn dsr_seq(&mut self) -> Result<(), GenError> {
         rethrow!(self.0.write_all(b"[DSR]"))
@@ -62,6 +73,59 @@ impl<W: Write> ai::AnsiInterpret for Dump<W> {
         rethrow!(write!(self.0, "[HVP:{},{}]", r, c))
     }
 
+    fn decsc_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(self.0.write_all(b"[DECSC]"))
+    }
+    fn decrc_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(self.0.write_all(b"[DECRC]"))
+    }
+    fn ris_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(self.0.write_all(b"[RIS]"))
+    }
+    fn deckpam_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(self.0.write_all(b"[DECKPAM]"))
+    }
+    fn deckpnm_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(self.0.write_all(b"[DECKPNM]"))
+    }
+    fn decaln_seq(&mut self) -> Result<(), GenError> {
+        rethrow!(self.0.write_all(b"[DECALN]"))
+    }
+
+    fn scs_seq(&mut self, slot: ai::CharsetSlot, set: ai::Charset) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[SCS:{:?},{:?}]", slot, set))
+    }
+    fn ls_seq(&mut self, slot: ai::CharsetSlot) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[LS:{:?}]", slot))
+    }
+    fn ss_seq(&mut self, slot: ai::CharsetSlot) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[SS:{:?}]", slot))
+    }
+
+    fn set_cursor_style(&mut self, shape: ai::CursorShape, blinking: bool) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[DECSCUSR:{:?},{}]", shape, blinking))
+    }
+    fn set_cursor_visible(&mut self, visible: bool) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[DECTCEM:{}]", visible))
+    }
+    fn set_line_wrap(&mut self, wrap: bool) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[DECAWM:{}]", wrap))
+    }
+    fn screen_mode_seq(&mut self, mode: ai::ScreenMode, set: bool) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[SCREENMODE:{:?},{}]", mode, set))
+    }
+
+    fn xtwinops_seq(&mut self, op: ai::WindowOp) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[XTWINOPS:{:?}]", op))
+    }
+
+    fn query_seq(&mut self, q: ai::Query) -> Result<(), GenError> {
+        match q {
+            ai::Query::CursorPosition => self.dsr_seq(),
+            q => rethrow!(write!(self.0, "[QUERY:{:?}]", q))
+        }
+    }
+
     fn osc_txt_seq(&mut self, n: u16, txt: &str) -> Result<(), GenError> {
         rethrow!(write!(self.0, "[OSC:{},{:?}]", n, txt))
     }
@@ -79,6 +143,12 @@ impl<W: Write> ai::AnsiInterpret for Dump<W> {
     }
 }
 
+impl<W: Write> ai::SourceInterpret for Dump<W> {
+    fn set_source(&mut self, source: usize) -> Result<(), GenError> {
+        rethrow!(write!(self.0, "[SRC:{}]", source))
+    }
+}
+
 #[test]
 fn test_decode() {
     println!("");
@@ -138,3 +208,296 @@ Terminal title: [OSC:2,\"Final Destination (terminal, geddit?)\"].
 "
     );
 }
+
+#[test]
+fn test_decode_esc() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        write!(intercept,
+"Save \x1b7, restore \x1b8, save \x1b[s, restore \x1b[u.
+Reset \x1bc and align \x1b#8.
+Keypad \x1b= and \x1b>.
+Unknown \x1bZ and \x1b#3.
+"
+        )
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+"Save [DECSC], restore [DECRC], save [SCP], restore [RCP].
+Reset [RIS] and align [DECALN].
+Keypad [DECKPAM] and [DECKPNM].
+Unknown [UNK:5a] and [UNK:2333].
+"
+    );
+}
+
+#[test]
+fn test_decode_malformed() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        intercept.write_all(b"Scrollback \x1b[3J, too many \x1b[1;2A, split \x1b[4").unwrap();
+        intercept.write_all(b"K and \x1b[Kafter.").unwrap();
+    }
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+        "Scrollback [UNK:5b334a], too many [UNK:5b313b3241], split [UNK:5b344b] and [EL:0]after.");
+}
+
+#[test]
+fn test_decode_charsets() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        write!(intercept,
+"G0 \x1b(0, G1 \x1b)B, G2 \x1b*A, G3 \x1b+0, unknown \x1b(Z.
+Shift \x0eout\x0f and in, single \x1bN and \x1bO, locking \x1bn and \x1bo.
+"
+        )
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+"G0 [SCS:G0,DecSpecialGraphics], G1 [SCS:G1,Ascii], G2 [SCS:G2,Uk], G3 [SCS:G3,DecSpecialGraphics], unknown [UNK:285a].
+Shift [LS:G1]out[LS:G0] and in, single [SS:G2] and [SS:G3], locking [LS:G2] and [LS:G3].
+"
+    );
+}
+
+#[test]
+fn test_translate_charsets() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(ai::CharsetTranslator::new(Dump(&mut s)));
+        write!(intercept,
+"\x1b(0lqqk\x1b(B lqqk
+\x1b)0x\x0ex\x0fx \x1b*0q\x1bNqq
+\x1b(A#1 \x1b(B#1
+"
+        )
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+"\u{250c}\u{2500}\u{2500}\u{2510} lqqk
+x\u{2502}x q\u{2500}q
+\u{a3}1 #1
+"
+    );
+}
+
+#[test]
+fn test_decode_cursor_style() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        write!(intercept,
+"Insert \x1b[5 q, normal \x1b[2 q, default \x1b[ q, bogus \x1b[9 q.
+Hide \x1b[?25l, show \x1b[?25h, alt screen \x1b[?1049h, both \x1b[?25;1049l, lots \x1b[?7;2004;25;1h.
+Private SGR \x1b[>4;1m.
+"
+        )
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+"Insert [DECSCUSR:Bar,true], normal [DECSCUSR:Block,false], default [DECSCUSR:Block,true], bogus [UNK:5b392071].
+Hide [DECTCEM:false], show [DECTCEM:true], alt screen [UNK:5b3f3130343968], both [DECTCEM:false][UNK:5b3f313034396c], lots [DECAWM:true][UNK:5b3f3230303468][DECTCEM:true][UNK:5b3f3168].
+Private SGR [UNK:5b3e343b316d].
+"
+    );
+}
+
+#[test]
+fn test_decode_window_ops() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        write!(intercept,
+"Size? \x1b[18t Cells? \x1b[16t Title? \x1b[21t
+Resize \x1b[8;24;80t, just width \x1b[8;;132t, lines \x1b[48t.
+Iconify \x1b[2t, raise \x1b[5t, maximize \x1b[9;1t, nonsense \x1b[12t.
+"
+        )
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+"Size? [XTWINOPS:ReportTextAreaSize] Cells? [XTWINOPS:ReportCellSize] Title? [XTWINOPS:ReportTitle]
+Resize [XTWINOPS:ResizeChars { rows: Some(24), cols: Some(80) }], just width [XTWINOPS:ResizeChars { rows: None, cols: Some(132) }], lines [XTWINOPS:ResizeLines(48)].
+Iconify [XTWINOPS:Iconify], raise [XTWINOPS:Raise], maximize [XTWINOPS:Maximize], nonsense [UNK:5b313274].
+"
+    );
+}
+
+#[test]
+fn test_decode_queries() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        write!(intercept,
+"DA \x1b[c \x1b[0c \x1b[>c \x1b[=c
+DSR \x1b[5n \x1b[6n
+DECRQM \x1b[?25$p \x1b[4$p
+DECRQSS \x1bP$qm\x1b\\ \x1bP$q q\x1b\\
+XTVERSION \x1b[>q XTGETTCAP \x1bP+q436F;544E\x1b\\
+"
+        )
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+"DA [QUERY:PrimaryDeviceAttributes] [QUERY:PrimaryDeviceAttributes] [QUERY:SecondaryDeviceAttributes] [QUERY:TertiaryDeviceAttributes]
+DSR [QUERY:Status] [DSR]
+DECRQM [QUERY:Mode { private: true, mode: 25 }] [QUERY:Mode { private: false, mode: 4 }]
+DECRQSS [QUERY:Setting(\"m\")] [QUERY:Setting(\" q\")]
+XTVERSION [QUERY:Version] XTGETTCAP [QUERY:Capabilities([\"Co\", \"TN\"])]
+"
+    );
+}
+
+#[test]
+fn test_decode_sgr() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        write!(intercept,
+"Plain \x1b[m, bold red \x1b[1;31m, bright \x1b[92;104m, defaults \x1b[39;49;59m.
+Indexed \x1b[38;5;208;48;5;17m, underline colour \x1b[58;5;1m, unknown \x1b[26m.
+Truecolor \x1b[38;2;255;128;0m, colon \x1b[48:2::1:2:3;1m, overflow \x1b[38;5;99999m.
+Squiggle \x1b[4:3;58:2::255:0:0m, double \x1b[21m or \x1b[4:2m, off \x1b[4:0;59m.
+"
+        )
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+"Plain [SGR:0], bold red [SGR:1,31], bright [SGR:92,104], defaults [SGR:39,49,59].
+Indexed [SGR:38;5;208,48;5;17], underline colour [SGR:58;5;1], unknown [SGR:26].
+Truecolor [SGR:38;2;255;128;0], colon [SGR:48;2;1;2;3,1], overflow [SGR:38].
+Squiggle [SGR:4:3,58;2;255;0;0], double [SGR:21] or [SGR:21], off [SGR:24,59].
+"
+    );
+}
+
+struct Runs<'a>(&'a mut Vec<(String, ai::Style)>);
+
+impl<'a> ai::StyledInterpret for Runs<'a> {
+    fn write_styled(&mut self, buf: &[u8], style: &ai::Style) -> io::Result<usize> {
+        self.0.push((String::from_utf8_lossy(buf).into_owned(), *style));
+        Ok(buf.len())
+    }
+}
+
+#[test]
+fn test_decode_sgr_stack() {
+    let mut s = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(Dump(&mut s));
+        write!(intercept, "\x1b[#{{ \x1b[30;31#{{ \x1b[#}} \x1b[30#p \x1b[#q")
+    }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+
+    assert_eq!(&*String::from_utf8(s).unwrap(),
+        "[XTPUSHSGR] [XTPUSHSGR:fgbg] [XTPOPSGR] [XTPUSHSGR:fg] [XTPOPSGR]");
+}
+
+#[test]
+fn test_style_tracker() {
+    let mut runs = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(ai::StyleTracker::new(Runs(&mut runs)));
+        write!(intercept, "plain \x1b[1;38;2;255;0;0mred\x1b[22;4:3m curly\x1b[A\x1b[0m done")
+    }.expect("could not write to interceptor");
+
+    let texts: Vec<_> = runs.iter().map(|&(ref t, _)| &**t).collect();
+    assert_eq!(texts, ["plain ", "red", " curly", " done"]);
+
+    let red = ai::Style { fg: ai::Color::Rgb(255, 0, 0), bold: true, ..ai::Style::default() };
+    assert_eq!(runs[0].1, ai::Style::default());
+    assert_eq!(runs[1].1, red);
+    assert_eq!(runs[2].1, ai::Style { bold: false, underline: ai::UnderlineStyle::Curly, ..red });
+    assert_eq!(runs[3].1, ai::Style::default());
+}
+
+#[test]
+fn test_style_tracker_save_restore() {
+    let mut runs = vec![];
+    {
+        let mut intercept = ai::AnsiIntercept::new(ai::StyleTracker::new(Runs(&mut runs)));
+        write!(intercept, "\x1b[1;31ma\x1b[30#{{\x1b[32;44mb\x1b[#}}c\x1b7\x1b[mD\x1b8e")
+    }.expect("could not write to interceptor");
+
+    let red = ai::Style { fg: ai::Color::Indexed(1), bold: true, ..ai::Style::default() };
+    let green = ai::Style { fg: ai::Color::Indexed(2), bg: ai::Color::Indexed(4), ..red };
+    let styles: Vec<_> = runs.iter().map(|&(_, style)| style).collect();
+    assert_eq!(styles, [red, green, ai::Style { fg: ai::Color::Indexed(1), ..green }, ai::Style::default(), ai::Style { fg: ai::Color::Indexed(1), ..green }]);
+}
+
+#[test]
+fn test_reduce_colors() {
+    fn reduce(depth: ai::ColorDepth) -> String {
+        let mut s = vec![];
+        {
+            let mut intercept = ai::AnsiIntercept::new(ai::ColorReducer::new(Dump(&mut s), depth));
+            write!(intercept, "\x1b[1;38;2;255;135;0;48;5;17mhi\x1b[4:3;58:2::255:0:0;91m\x1b[38;5;99999m\x1b[2A")
+        }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+        String::from_utf8(s).unwrap()
+    }
+
+    assert_eq!(reduce(ai::ColorDepth::TrueColor), "[SGR:1,38;2;255;135;0,48;5;17]hi[SGR:4:3,58;2;255;0;0,91][CUU:2]");
+    assert_eq!(reduce(ai::ColorDepth::Indexed256), "[SGR:1,38;5;208,48;5;17]hi[SGR:4:3,58;5;196,91][CUU:2]");
+    assert_eq!(reduce(ai::ColorDepth::Ansi16), "[SGR:1,91,44]hi[SGR:4:3,58;5;9,91][CUU:2]");
+    assert_eq!(reduce(ai::ColorDepth::Ansi8), "[SGR:1,37,44]hi[SGR:4:3,58;5;1,31][CUU:2]");
+    assert_eq!(reduce(ai::ColorDepth::Monochrome), "[SGR:1,39,49]hi[SGR:4:3,59,39][CUU:2]");
+}
+
+#[test]
+fn test_decode_ansi_sys() {
+    fn decode(ice_colors: Option<bool>, text: &str) -> String {
+        let mut s = vec![];
+        {
+            let mut intercept: Box<Write> = match ice_colors {
+                None => Box::new(ai::AnsiIntercept::new(Dump(&mut s))),
+                Some(ice) => Box::new(ai::AnsiIntercept::new(ai::AnsiSys::new(Dump(&mut s), ice))),
+            };
+            write!(intercept, "{}", text)
+        }.expect(&format!("could not write to interceptor; got {:?}", ::std::str::from_utf8(&s).unwrap_or("{invalid}")));
+        String::from_utf8(s).unwrap()
+    }
+
+    let modes = "\x1b[=3h \x1b[=h \x1b[=19h \x1b[=7l \x1b[=7h \x1b[=8h \x1b[?7l";
+    assert_eq!(decode(None, modes),
+        "[SCREENMODE:Text80x25,true] [SCREENMODE:Text40x25Mono,true] [SCREENMODE:Graphics320x200x256,true] [SCREENMODE:LineWrap,false] [SCREENMODE:LineWrap,true] [UNK:5b3d3868] [DECAWM:false]");
+    assert_eq!(decode(Some(false), modes),
+        "[SCREENMODE:Text80x25,true] [SCREENMODE:Text40x25Mono,true] [SCREENMODE:Graphics320x200x256,true] [DECAWM:false] [DECAWM:true] [UNK:5b3d3868] [DECAWM:false]");
+
+    let art = "\x1b[5;44ma\x1b[41mb\x1b[25mc\x1b[0;5md\x1b[1;31m";
+    assert_eq!(decode(Some(false), art), "[SGR:5,44]a[SGR:41]b[SGR:25]c[SGR:0,5]d[SGR:1,31]");
+    assert_eq!(decode(Some(true), art), "[SGR:100,104]a[SGR:101]b[SGR:41]c[SGR:0,100]d[SGR:1,31]");
+
+    // Blinking and the background come back with everything else.
+    let saved = "\x1b7\x1b[5;44m\x1b8\x1b[41ma\x1b[#{\x1b[5m\x1b[#}b\x1b[5m\x1b[31#{\x1b[25;42m\x1b[#}c";
+    assert_eq!(decode(Some(true), saved),
+        "[DECSC][SGR:100,104][DECRC][SGR:41]a[XTPUSHSGR][SGR:101][XTPOPSGR]b[SGR:101][XTPUSHSGR:bg][SGR:41,42][XTPOPSGR][SGR:41]c");
+}
+
+#[test]
+fn test_decode_multi_source() {
+    fn decode(multi: ai::MultiSourceIntercept<Dump<Vec<u8>>>) -> String {
+        {
+            let mut out = multi.source(1);
+            let mut err = multi.source(2);
+            out.write_all(b"a\x1b[3").unwrap();
+            err.write_all(b"b\x1b[1").unwrap();
+            out.write_all(b"1mc").unwrap();
+            err.write_all(b"Kd").unwrap();
+            err.write_all(b"e").unwrap();
+        }
+        String::from_utf8(multi.into_inner().ok().unwrap().0).unwrap()
+    }
+
+    assert_eq!(decode(ai::MultiSourceIntercept::new(Dump(vec![]))), "ab[SGR:31]c[EL:1]de");
+    assert_eq!(decode(ai::MultiSourceIntercept::tagged(Dump(vec![]))),
+        "[SRC:1]a[SRC:2]b[SRC:1][SGR:31]c[SRC:2][EL:1]de");
+
+    let multi = ai::MultiSourceIntercept::new(Dump(vec![]));
+    let source = multi.source(1);
+    let multi = multi.into_inner().err().unwrap();
+    drop(source);
+    assert!(multi.into_inner().is_ok());
+}
@@ -0,0 +1,87 @@
+#![cfg(unix)]
+extern crate ansi_interpreter as ai;
+extern crate libc;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::FromRawFd;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Which file a descriptor refers to, if it's open.
+fn identify(fd: i32) -> Option<(u64, u64)> {
+    let file = unsafe { File::from_raw_fd(fd) };
+    let id = file.metadata().ok().map(|m| (m.dev(), m.ino()));
+    mem::forget(file);
+    id
+}
+
+#[test]
+fn test_intercept_stdio() {
+    // Passing output through never needs standard input.
+    let (stdin, stdout) = (identify(0), identify(1));
+    let handle = ai::intercept_stdio().unwrap();
+    assert_eq!(identify(0), stdin);
+    assert!(identify(1) != stdout);
+    handle.join().unwrap();
+
+    // Standard input that never runs dry, like a terminal nobody is typing at.
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    let saved_stdin = unsafe { libc::dup(0) };
+    assert_eq!(unsafe { libc::dup2(fds[0], 0) }, 0);
+    unsafe { libc::close(fds[0]) };
+    let mut typing = unsafe { File::from_raw_fd(fds[1]) };
+    let stdin = identify(0);
+
+    let out = Shared(Arc::new(Mutex::new(vec![])));
+    let err = Shared(Arc::new(Mutex::new(vec![])));
+
+    let handle = {
+        let (out, err) = (out.clone(), err.clone());
+        ai::intercept_stdio_with(move |fd, _, _| {
+            let log = if fd == 1 { out.clone() } else { err.clone() };
+            ai::ColorReducer::new(ai::AnsiWriter::new(log), ai::ColorDepth::Ansi16)
+        }).unwrap()
+    };
+    io::stdout().write_all(b"out \x1b[38;5;196mred\x1b[m").unwrap();
+    io::stderr().write_all(b"err \x1b[38;2;0;0;255m\x1b").unwrap();
+    io::stderr().write_all(b"[1mblue").unwrap();
+    typing.write_all(b"typed").unwrap();
+    let mut typed = [0; 5];
+    {
+        let mut stdin = unsafe { File::from_raw_fd(0) };
+        stdin.read_exact(&mut typed).unwrap();
+        mem::forget(stdin);
+    }
+    assert_eq!(&typed, b"typed");
+    handle.join().unwrap();
+    assert_eq!(identify(0), stdin);
+
+    // Nothing is left reading standard input once the interception is over.
+    typing.write_all(b"later").unwrap();
+    {
+        let mut stdin = unsafe { File::from_raw_fd(0) };
+        stdin.read_exact(&mut typed).unwrap();
+        mem::forget(stdin);
+    }
+    assert_eq!(&typed, b"later");
+    assert_eq!(unsafe { libc::dup2(saved_stdin, 0) }, 0);
+    unsafe { libc::close(saved_stdin) };
+
+    assert_eq!(String::from_utf8(out.0.lock().unwrap().clone()).unwrap(), "out \x1b[91mred\x1b[0m");
+    assert_eq!(String::from_utf8(err.0.lock().unwrap().clone()).unwrap(), "err \x1b[94m\x1b[1mblue");
+}
@@ -0,0 +1,77 @@
+#![cfg(target_os = "linux")]
+extern crate ansi_interpreter as ai;
+
+use std::io;
+use std::process::Command;
+use std::time::{Duration, Instant};
+use ai::{Coord, Session};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn sh(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+#[test]
+fn test_pty_session() {
+    let mut session = Session::spawn(Command::new("cat"), 20, 4).unwrap();
+    session.send_keys("hello\r").unwrap();
+    // Once from the terminal echoing it, and once from cat.
+    session.wait_for_text("hello\nhello", TIMEOUT).unwrap();
+    assert_eq!(session.screen_text(), "hello\nhello\n\n");
+    assert_eq!(session.cursor(), Coord::new(0, 2));
+
+    let err = session.wait_for_text("goodbye", Duration::from_millis(50)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+    session.send_keys("\x04").unwrap();
+    assert!(session.wait().unwrap().success());
+    let err = session.wait_for_text("goodbye", TIMEOUT).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_pty_session_queries() {
+    // Read the replies back without echoing them, and show them with the escapes made visible.
+    let script = "stty -echo -icanon min 1; \
+        printf '\\033[2J\\033[3;5H\\033[6n'; dd bs=1 count=6 2>/dev/null | tr '\\033' E; \
+        printf '\\033[5;1H\\033[c'; dd bs=1 count=7 2>/dev/null | tr '\\033' E; \
+        printf '\\033(0lqk\\033(B\\033[?2004h'";
+    let mut session = Session::spawn(sh(script), 20, 6).unwrap();
+    session.wait_for_text("\u{250c}", TIMEOUT).unwrap();
+    assert!(session.wait().unwrap().success());
+    assert_eq!(session.screen_text(), "\n\n    E[3;5R\n\nE[?1;0c\u{250c}\u{2500}\u{2510}\n");
+}
+
+#[test]
+fn test_pty_session_full_screen() {
+    // Line feeds go out bare, so they shouldn't return to the first column.
+    let script = "stty -echo -onlcr; printf 'before\\r\\n\\033[?1049h\\033[H\\033[2J'; \
+        printf 'top\\033[6;1Hstatus\\033[2;5r\\033[2;1Ha\\nb\\nc\\nd\\ne'; \
+        printf '\\033[3;1H\\033[L\\033[1;1H\\033[2@\\033[6;1H\\033[3P\\033[1;18Hxyz'; \
+        read line; printf '\\033[?1049lafter'";
+    let mut session = Session::spawn(sh(script), 20, 6).unwrap();
+    session.wait_for_text("xyz", TIMEOUT).unwrap();
+    assert_eq!(session.screen_text(), "  top            xyz\n b\n\n  c\n   d\ntus");
+    // Writing the last column doesn't wrap until there's more to write.
+    assert_eq!(session.cursor(), Coord::new(19, 0));
+
+    session.send_keys("\r").unwrap();
+    assert!(session.wait().unwrap().success());
+    assert_eq!(session.screen_text(), "before\nafter\n\n\n\n");
+    assert_eq!(session.cursor(), Coord::new(5, 1));
+}
+
+#[test]
+fn test_pty_session_background() {
+    // Something left running in the background keeps the terminal open after the child has gone.
+    let start = Instant::now();
+    let mut session = Session::spawn(sh("sleep 100 & echo started"), 20, 4).unwrap();
+    session.wait_for_text("started", TIMEOUT).unwrap();
+    assert!(session.wait().unwrap().success());
+    assert_eq!(session.screen_text(), "started\n\n\n");
+    drop(session);
+    assert!(start.elapsed() < TIMEOUT);
+}
@@ -0,0 +1,41 @@
+extern crate ansi_interpreter as ai;
+
+use std::io::{ErrorKind, Write};
+use ai::{AnsiIntercept, Terminfo, TerminfoWriter};
+
+/// Load an entry kept with the tests, so the exact capabilities don't depend on what the system has installed.
+fn fixture(name: &str) -> Terminfo {
+    Terminfo::from_path(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/").to_owned() + name).unwrap()
+}
+
+#[test]
+fn test_terminfo_fixture() {
+    let xterm = fixture("xterm-256color");
+    assert_eq!(xterm.names()[0], "xterm-256color");
+    assert!(xterm.get_bool("am"));
+    assert_eq!(xterm.get_number("colors"), Some(256));
+    assert_eq!(xterm.expand("cup", &[2, 4]), Some(Ok(b"\x1b[3;5H".to_vec())));
+    assert_eq!(xterm.expand("setaf", &[208]), Some(Ok(b"\x1b[38;5;208m".to_vec())));
+    assert_eq!(xterm.get_string("smcup"), Some(&b"\x1b[?1049h\x1b[22;0;0t"[..]));
+    // An extended capability.
+    assert_eq!(xterm.expand("Ss", &[2]), Some(Ok(b"\x1b[2 q".to_vec())));
+
+    let mut out = AnsiIntercept::new(TerminfoWriter::new(vec![], xterm));
+    write!(out, "\x1b[2;3H\x1b[1;38;2;255;135;0mhot\x1b[m\x1b[K").unwrap();
+    assert_eq!(out.get_ref().get_ref(), b"\x1b[2;3H\x1b[1m\x1b[38;5;208mhot\x1b(B\x1b[m\x1b[K");
+}
+
+#[test]
+fn test_terminfo_system() {
+    // What the system has varies, so all that can be checked is that it's understood.
+    for name in &["xterm-256color", "xterm", "vt100", "linux", "screen", "tmux-256color"] {
+        match Terminfo::from_name(name) {
+            Ok(info) => assert!(!info.names().is_empty()),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => (),
+            Err(err) => panic!("{}: {}", name, err)
+        }
+    }
+
+    assert_eq!(Terminfo::from_name("no-such-terminal").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(Terminfo::from_name("../x").unwrap_err().kind(), ErrorKind::InvalidInput);
+}